@@ -1,22 +1,137 @@
 #![feature(never_type)]
 
 use leint::Le;
-use hoard_derive::Primitive;
+use hoard::zone::{Zone, OwnedPtr};
+use hoard_derive::{Primitive, Transfer, Export, FmtDeep};
 
-#[derive(Primitive)]
+#[derive(Primitive, Debug, PartialEq)]
 #[repr(C)]
 pub struct Outpoint {
     txid: [u8;32],
     n: Le<u32>,
 }
 
-#[derive(Primitive)]
+#[derive(Primitive, Debug, PartialEq)]
 #[repr(C)]
 pub struct Foo(u8,bool);
 
-#[cfg(tests)]
+#[derive(Transfer, Export, FmtDeep, Debug)]
+#[hoard(zone = Z)]
+pub struct Pair<Z: Zone> {
+    left: OwnedPtr<Le<u32>, Z>,
+    right: Option<OwnedPtr<u8, Z>>,
+    n: Le<u16>,
+}
+
+#[derive(Transfer, Export, FmtDeep, Debug)]
+#[hoard(zone = Z)]
+pub enum Node<Z: Zone> {
+    Empty,
+    Leaf(u8),
+    Branch {
+        left: OwnedPtr<u8, Z>,
+        right: OwnedPtr<u8, Z>,
+    },
+}
+
+/// The zone needn't be named `Z`.
+#[derive(Transfer, FmtDeep, Debug)]
+#[hoard(zone = P)]
+pub struct Wrapped<P: Zone>(OwnedPtr<u8, P>);
+
+#[cfg(test)]
 mod tests {
+    use super::*;
+
+    use hoard::pile::TryPileMut;
+    use hoard::zone::{Alloc, TryGet, Transfer, Export, DebugDeep, Missing};
+    use hoard::testing::{check_marshal, NeverDumper};
+
+    #[test]
+    fn conformance() {
+        check_marshal(&NeverDumper, |rng| {
+            let mut txid = [0; 32];
+            rng.fill_bytes(&mut txid);
+            Outpoint { txid, n: (rng.next_u64() as u32).into() }
+        });
+        check_marshal(&NeverDumper, |rng| Foo(rng.next_u64() as u8, rng.next_bool()));
+    }
+
+    #[test]
+    fn transfer() {
+        let src = TryPileMut::default();
+        let dst = TryPileMut::default();
+
+        let pair = Pair { left: src.alloc(Le::new(1u32)), right: Some(src.alloc(2u8)), n: Le::new(3) };
+        let pair: Pair<TryPileMut> = pair.transfer(&src, &dst).unwrap();
+        assert_eq!(**dst.try_get(&pair.left).unwrap(), 1);
+        assert_eq!(**dst.try_get(pair.right.as_ref().unwrap()).unwrap(), 2);
+        assert_eq!(pair.n, 3);
+
+        let node = Node::Branch { left: src.alloc(4u8), right: src.alloc(5u8) };
+        match node.transfer(&src, &dst).unwrap() {
+            Node::Branch { left, right } => {
+                assert_eq!(**dst.try_get(&left).unwrap(), 4);
+                assert_eq!(**dst.try_get(&right).unwrap(), 5);
+            },
+            node => panic!("{:?}", node),
+        }
+
+        match Node::<TryPileMut>::Leaf(6).transfer(&src, &Missing).unwrap() {
+            Node::Leaf(6) => {},
+            node => panic!("{:?}", node),
+        }
+
+        let wrapped: Wrapped<TryPileMut> = Wrapped(src.alloc(7u8)).transfer(&src, &dst).unwrap();
+        assert_eq!(**dst.try_get(&wrapped.0).unwrap(), 7);
+    }
+
+    #[test]
+    fn export() {
+        let pile = TryPileMut::default();
+
+        let pair = Pair { left: pile.alloc(Le::new(1u32)), right: None, n: Le::new(3) };
+        assert_eq!(pair.export(&pile).to_json(), r#"{"Pair":{"left":1,"right":null,"n":3}}"#);
+
+        let node = Node::Branch { left: pile.alloc(4u8), right: pile.alloc(5u8) };
+        assert_eq!(node.export(&pile).to_json(), r#"{"Node::Branch":{"left":4,"right":5}}"#);
+        assert_eq!(Node::<TryPileMut>::Empty.export(&pile).to_json(), r#"{"Node::Empty":{}}"#);
+    }
+
+    #[test]
+    fn fmt_deep() {
+        let pile = TryPileMut::default();
+
+        let pair = Pair { left: pile.alloc(Le::new(1u32)), right: None, n: Le::new(3) };
+        assert_eq!(format!("{:?}", DebugDeep::new(&pair, &pile)),
+                   "Pair { left: Le(1), right: None, n: Le(3) }");
+
+        let node = Node::Branch { left: pile.alloc(4u8), right: pile.alloc(5u8) };
+        assert_eq!(format!("{:?}", DebugDeep::new(&node, &pile)), "Branch { left: 4, right: 5 }");
+        assert_eq!(format!("{:?}", DebugDeep::new(&Node::Leaf(6), &pile)), "Leaf(6)");
+        assert_eq!(format!("{:?}", DebugDeep::new(&Node::Empty, &pile)), "Empty");
+
+        let wrapped = Wrapped(Missing.alloc(7u8));
+        assert_eq!(format!("{:?}", DebugDeep::new(&wrapped, &Missing)), "Wrapped(@() <missing>)");
+    }
+
+    #[cfg(feature = "serde")]
     #[test]
-    fn test() {
+    fn export_serde() {
+        use hoard::zone::Exported;
+
+        let pile = TryPileMut::default();
+
+        let pair = Pair { left: pile.alloc(Le::new(1u32)), right: Some(pile.alloc(2u8)), n: Le::new(3) };
+        assert_eq!(serde_json::to_string(&Exported::new(&pair, &pile)).unwrap(),
+                   r#"{"left":1,"right":2,"n":3}"#);
+
+        let node = Node::Branch { left: Missing.alloc(4u8), right: Missing.alloc(5u8) };
+        assert_eq!(serde_json::to_string(&Exported::new(&node, &Missing)).unwrap(),
+                   r#"{"Branch":{"left":"<missing>","right":"<missing>"}}"#);
+
+        // Serializing the exported Value is the same as serializing as we go.
+        assert_eq!(serde_json::to_string(&pair.export(&pile)).unwrap(),
+                   serde_json::to_string(&Exported::new(&pair, &pile)).unwrap());
     }
 }
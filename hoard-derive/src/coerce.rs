@@ -0,0 +1,326 @@
+//! `#[derive(TryCoerce)]`
+//!
+//! Implements `TryCoerce<Target>` for every `#[try_coerce(Target)]` attribute, by coercing field
+//! by field. The target must be the source type itself with different generic arguments, eg
+//! `#[try_coerce(for<'b> Foo<'b>)]` on `Foo<'a>`, and the type must be `#[repr(C)]` or
+//! `#[repr(transparent)]`. The fields of the target are then the fields of the source, in the same
+//! order, with the generic arguments substituted; requiring each field to implement `TryCoerce`
+//! to its substituted type guarantees that the layouts are the same.
+//!
+//! The error type defaults to `!`, and can be set with `#[try_coerce(Target, error = E)]`; every
+//! field's error must convert `Into<E>`.
+//!
+//! `#[try_coerce(from Source)]` goes the other way, implementing `TryCoerce<Self>` for `Source`.
+//! The type must be a `#[repr(transparent)]` wrapper, eg `struct OffsetMut<'p, 'v>(Offset<'p, 'v>)`,
+//! and `Source` must implement `TryCoerce` to the type of the wrapped field. Other fields must be
+//! `PhantomData`. Like any coercion, this assumes the wrapper has no invariants beyond those of its
+//! field.
+use proc_macro2::TokenStream;
+use quote::quote;
+use std::collections::HashMap;
+
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::visit_mut::{self, VisitMut};
+
+use super::hoard_crate;
+
+/// The argument of a `#[try_coerce(...)]` attribute.
+///
+/// ```text
+/// #[try_coerce(for<'a> Target<'a>)]
+/// #[try_coerce(Target, error = TargetError)]
+/// #[try_coerce(from for<'a> Source<'a>)]
+/// ```
+struct Target {
+    from: bool,
+    lifetimes: Option<syn::BoundLifetimes>,
+    ty: syn::TypePath,
+    error: Option<syn::Type>,
+}
+
+impl Parse for Target {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let from = input.peek(syn::Ident)
+                && (input.peek2(syn::Token![for]) || input.peek2(syn::Ident))
+                && input.fork().parse::<syn::Ident>().map_or(false, |ident| ident == "from");
+        if from {
+            input.parse::<syn::Ident>()?;
+        }
+
+        let lifetimes = input.parse()?;
+        let ty = input.parse()?;
+
+        let error = if input.peek(syn::Token![,]) {
+            input.parse::<syn::Token![,]>()?;
+            let ident: syn::Ident = input.parse()?;
+            if ident != "error" {
+                return Err(syn::Error::new(ident.span(), "expected `error = ...`"));
+            }
+            input.parse::<syn::Token![=]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+
+        Ok(Self { from, lifetimes, ty, error })
+    }
+}
+
+fn has_repr(attrs: &[syn::Attribute], reprs: &[&str]) -> bool {
+    attrs.iter()
+         .filter(|attr| attr.path.is_ident("repr"))
+         .filter_map(|attr| attr.parse_meta().ok())
+         .any(|meta| match meta {
+             syn::Meta::List(list) => list.nested.iter().any(|nested| match nested {
+                 syn::NestedMeta::Meta(syn::Meta::Path(path)) => {
+                     reprs.iter().any(|repr| path.is_ident(repr))
+                 },
+                 _ => false,
+             }),
+             _ => false,
+         })
+}
+
+/// Substitutes the generic parameters of the source type with the arguments of the target.
+struct Substitute {
+    lifetimes: HashMap<syn::Ident, syn::Lifetime>,
+    types: HashMap<syn::Ident, syn::Type>,
+}
+
+impl Substitute {
+    fn new(name: &syn::Ident, generics: &syn::Generics, target: &syn::TypePath) -> syn::Result<Self> {
+        if let Some(qself) = &target.qself {
+            return Err(syn::Error::new(qself.lt_token.span(), "qualified paths are not supported"));
+        }
+
+        let segment = match target.path.segments.iter().collect::<Vec<_>>().as_slice() {
+            [segment] if target.path.leading_colon.is_none() && segment.ident == *name => *segment,
+            _ => return Err(syn::Error::new(target.span(),
+                            format!("the target must be `{}` with different generic arguments", name))),
+        };
+
+        let mut lifetime_args = vec![];
+        let mut type_args = vec![];
+        match &segment.arguments {
+            syn::PathArguments::None => {},
+            syn::PathArguments::AngleBracketed(args) => {
+                for arg in args.args.iter() {
+                    match arg {
+                        syn::GenericArgument::Lifetime(lifetime) => lifetime_args.push(lifetime.clone()),
+                        syn::GenericArgument::Type(ty) => type_args.push(ty.clone()),
+                        arg => return Err(syn::Error::new(arg.span(), "expected a lifetime or type argument")),
+                    }
+                }
+            },
+            syn::PathArguments::Parenthesized(args) => {
+                return Err(syn::Error::new(args.span(), "expected angle-bracketed arguments"));
+            },
+        }
+
+        let mut lifetime_params = vec![];
+        let mut type_params = vec![];
+        for param in generics.params.iter() {
+            match param {
+                syn::GenericParam::Lifetime(def) => lifetime_params.push(def.lifetime.ident.clone()),
+                syn::GenericParam::Type(param) if param.ident == *name => {
+                    return Err(syn::Error::new(param.ident.span(), "generic parameter shadows the type's name"));
+                },
+                syn::GenericParam::Type(param) => type_params.push(param.ident.clone()),
+                syn::GenericParam::Const(param) => {
+                    return Err(syn::Error::new(param.ident.span(), "const generics are not supported"));
+                },
+            }
+        }
+
+        if lifetime_args.len() != lifetime_params.len() || type_args.len() != type_params.len() {
+            return Err(syn::Error::new(segment.span(),
+                       format!("expected {} lifetime and {} type arguments",
+                               lifetime_params.len(), type_params.len())));
+        }
+
+        Ok(Self {
+            lifetimes: lifetime_params.into_iter().zip(lifetime_args).collect(),
+            types: type_params.into_iter().zip(type_args).collect(),
+        })
+    }
+
+    fn field_ty(&mut self, ty: &syn::Type) -> syn::Type {
+        let mut ty = ty.clone();
+        self.visit_type_mut(&mut ty);
+        ty
+    }
+}
+
+impl VisitMut for Substitute {
+    fn visit_lifetime_mut(&mut self, lifetime: &mut syn::Lifetime) {
+        if let Some(arg) = self.lifetimes.get(&lifetime.ident) {
+            *lifetime = arg.clone();
+        }
+    }
+
+    fn visit_type_mut(&mut self, ty: &mut syn::Type) {
+        if let syn::Type::Path(path) = ty {
+            if path.qself.is_none() {
+                if let Some(arg) = path.path.get_ident().and_then(|ident| self.types.get(ident)) {
+                    *ty = arg.clone();
+                    return;
+                }
+            }
+        }
+        visit_mut::visit_type_mut(self, ty)
+    }
+}
+
+pub fn derive_try_coerce(s: synstructure::Structure) -> TokenStream {
+    match try_derive_try_coerce(s) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+fn try_derive_try_coerce(s: synstructure::Structure) -> syn::Result<TokenStream> {
+    let ast = s.ast();
+    let name = &ast.ident;
+
+    let fields = match &ast.data {
+        syn::Data::Struct(data) => &data.fields,
+        _ => return Err(syn::Error::new(ast.ident.span(), "TryCoerce can only be derived for structs")),
+    };
+
+    if !has_repr(&ast.attrs, &["C", "transparent"]) {
+        return Err(syn::Error::new(ast.ident.span(),
+                   "TryCoerce requires #[repr(C)] or #[repr(transparent)]"));
+    }
+
+    let members: Vec<syn::Member> = fields.iter().enumerate().map(|(i, field)| {
+        match &field.ident {
+            Some(ident) => syn::Member::Named(ident.clone()),
+            None => syn::Member::Unnamed(syn::Index { index: i as u32, span: field.span() }),
+        }
+    }).collect();
+
+    let mut targets = vec![];
+    for attr in ast.attrs.iter().filter(|attr| attr.path.is_ident("try_coerce")) {
+        targets.push(attr.parse_args::<Target>()?);
+    }
+    if targets.is_empty() {
+        return Err(syn::Error::new(ast.ident.span(), "expected at least one #[try_coerce(...)] attribute"));
+    }
+
+    let hoard = hoard_crate();
+    let (_, ty_generics, _) = ast.generics.split_for_impl();
+
+    let mut impls = vec![];
+    for target in targets {
+        if target.from {
+            impls.push(derive_from(ast, fields, target)?);
+            continue;
+        }
+
+        let ty = &target.ty;
+        let mut substitute = Substitute::new(name, &ast.generics, ty)?;
+        let error = target.error.map(|error| quote!(#error))
+                                .unwrap_or_else(|| quote!(!));
+
+        let mut generics = ast.generics.clone();
+        if let Some(lifetimes) = target.lifetimes {
+            for (i, lifetime) in lifetimes.lifetimes.into_iter().enumerate() {
+                generics.params.insert(i, syn::GenericParam::Lifetime(lifetime));
+            }
+        }
+        {
+            let where_clause = generics.make_where_clause();
+            for field in fields.iter() {
+                let src = &field.ty;
+                let dst = substitute.field_ty(src);
+                where_clause.predicates.push(syn::parse_quote!(#src: #hoard::coerce::TryCoerce<#dst>));
+                where_clause.predicates.push(syn::parse_quote!(
+                    <#src as #hoard::coerce::TryCoerce<#dst>>::Error: Into<#error>
+                ));
+            }
+        }
+        let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+        impls.push(quote! {
+            unsafe impl #impl_generics #hoard::coerce::TryCoerce<#ty> for #name #ty_generics #where_clause {
+                type Error = #error;
+
+                #[inline(always)]
+                #[allow(unreachable_code)]
+                fn try_coerce_ptr(this: &Self) -> Result<*const #ty, Self::Error> {
+                    #(
+                        if let Err(err) = #hoard::coerce::try_coerce_field::<_, _, #ty, #error, _>(
+                            &this.#members,
+                            |__dst| &__dst.#members
+                        ) {
+                            return Err(err);
+                        }
+                    )*
+                    Ok((this as *const Self).cast::<#ty>())
+                }
+            }
+        });
+    }
+
+    Ok(quote! { #(#impls)* })
+}
+
+fn is_phantom_data(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(path) => path.qself.is_none()
+                              && path.path.segments.last().map_or(false, |last| last.ident == "PhantomData"),
+        _ => false,
+    }
+}
+
+/// Implements `TryCoerce<Self>` for the source type of a `#[try_coerce(from Source)]` attribute.
+fn derive_from(ast: &syn::DeriveInput, fields: &syn::Fields, source: Target) -> syn::Result<TokenStream> {
+    let name = &ast.ident;
+    if !has_repr(&ast.attrs, &["transparent"]) {
+        return Err(syn::Error::new(name.span(), "#[try_coerce(from ...)] requires #[repr(transparent)]"));
+    }
+
+    let mut wrapped = fields.iter().filter(|field| !is_phantom_data(&field.ty));
+    let field_ty = match (wrapped.next(), wrapped.next()) {
+        (Some(field), None) => &field.ty,
+        _ => return Err(syn::Error::new(name.span(),
+                        "#[try_coerce(from ...)] requires exactly one field that isn't PhantomData")),
+    };
+
+    let hoard = hoard_crate();
+    let src = &source.ty;
+    let error = source.error.map(|error| quote!(#error))
+                            .unwrap_or_else(|| quote!(!));
+
+    let mut generics = ast.generics.clone();
+    if let Some(lifetimes) = source.lifetimes {
+        for (i, lifetime) in lifetimes.lifetimes.into_iter().enumerate() {
+            generics.params.insert(i, syn::GenericParam::Lifetime(lifetime));
+        }
+    }
+    {
+        let where_clause = generics.make_where_clause();
+        where_clause.predicates.push(syn::parse_quote!(#src: #hoard::coerce::TryCoerce<#field_ty>));
+        where_clause.predicates.push(syn::parse_quote!(
+            <#src as #hoard::coerce::TryCoerce<#field_ty>>::Error: Into<#error>
+        ));
+    }
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = ast.generics.split_for_impl();
+
+    Ok(quote! {
+        unsafe impl #impl_generics #hoard::coerce::TryCoerce<#name #ty_generics> for #src #where_clause {
+            type Error = #error;
+
+            #[inline(always)]
+            #[allow(unreachable_code)]
+            fn try_coerce_ptr(this: &Self) -> Result<*const #name #ty_generics, Self::Error> {
+                match <#src as #hoard::coerce::TryCoerce<#field_ty>>::try_coerce_ptr(this) {
+                    Ok(ptr) => Ok(ptr.cast::<#name #ty_generics>()),
+                    Err(err) => Err(err.into()),
+                }
+            }
+        }
+    })
+}
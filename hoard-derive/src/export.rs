@@ -0,0 +1,120 @@
+//! `#[derive(Export)]`
+//!
+//! Implements `Export<Z>` by exporting field by field, keeping the names of the type, its
+//! variants, and its fields. Tuple fields are named by their index. As with `Transfer`, a type
+//! parameter marked with `#[hoard(zone = Z)]` is taken to be the zone; otherwise the type can be
+//! exported from any zone its fields can be.
+use proc_macro2::{Ident, Span, TokenStream};
+use quote::quote;
+
+use super::{hoard_crate, members, member_name, mentions_any, zone_param};
+
+pub fn derive_export(s: synstructure::Structure) -> TokenStream {
+    match try_derive_export(s) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+fn try_derive_export(s: synstructure::Structure) -> syn::Result<TokenStream> {
+    let ast = s.ast();
+    let name = &ast.ident;
+    let name_str = name.to_string();
+    let hoard = hoard_crate();
+
+    let mut generics = ast.generics.clone();
+    let zone = match zone_param(ast)? {
+        Some(zone) => zone,
+        None => {
+            let zone = Ident::new("__Z", Span::call_site());
+            generics.params.push(syn::parse_quote!(#zone));
+            zone
+        },
+    };
+
+    let field_tys: Vec<&syn::Type> = match &ast.data {
+        syn::Data::Struct(data) => data.fields.iter().map(|field| &field.ty).collect(),
+        syn::Data::Enum(data) => data.variants.iter()
+                                     .flat_map(|variant| variant.fields.iter())
+                                     .map(|field| &field.ty)
+                                     .collect(),
+        syn::Data::Union(_) => return Err(syn::Error::new(name.span(), "Export can't be derived for unions")),
+    };
+    // Only fields that depend on the generics need bounds; the rest either implement Export for
+    // every zone or don't, and bounding eg arrays runs into const generics bugs.
+    let params: Vec<Ident> = generics.type_params().map(|param| param.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    where_clause.predicates.push(syn::parse_quote!(#zone: #hoard::zone::Zone));
+    for ty in field_tys.into_iter().filter(|ty| mentions_any(ty, &params)) {
+        where_clause.predicates.push(syn::parse_quote!(#ty: #hoard::zone::Export<#zone>));
+    }
+
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = ast.generics.split_for_impl();
+
+    let export_fields = |fields: &syn::Fields, values: &[TokenStream]| {
+        let names = members(fields).iter().map(member_name).collect::<Vec<_>>();
+        quote! {
+            #(
+                #hoard::zone::export::ExportFields::field(&mut __fields, __ctx, #names, #values)?;
+            )*
+            #hoard::zone::export::ExportFields::end(__fields)
+        }
+    };
+
+    let body = match &ast.data {
+        syn::Data::Struct(data) => {
+            let len = data.fields.len();
+            let values: Vec<TokenStream> = members(&data.fields).iter()
+                                                                .map(|member| quote!(&self.#member))
+                                                                .collect();
+            let fields = export_fields(&data.fields, &values);
+            quote! {
+                let mut __fields = #hoard::zone::export::Exporter::export_struct(__exporter, #name_str, #len)?;
+                #fields
+            }
+        },
+        syn::Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(index, variant)| {
+                let index = index as u32;
+                let len = variant.fields.len();
+                let variant_name = &variant.ident;
+                let variant_str = variant_name.to_string();
+                let members = members(&variant.fields);
+                let bindings: Vec<Ident> = (0 .. members.len())
+                    .map(|i| Ident::new(&format!("__field{}", i), Span::call_site()))
+                    .collect();
+                let values: Vec<TokenStream> = bindings.iter().map(|binding| quote!(#binding)).collect();
+                let fields = export_fields(&variant.fields, &values);
+                quote! {
+                    #name::#variant_name { #(#members: #bindings,)* } => {
+                        let mut __fields = #hoard::zone::export::Exporter::export_variant(
+                            __exporter, #name_str, #index, #variant_str, #len
+                        )?;
+                        #fields
+                    },
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        },
+        syn::Data::Union(_) => unreachable!(),
+    };
+
+    Ok(quote! {
+        impl #impl_generics #hoard::zone::Export<#zone> for #name #ty_generics #where_clause {
+            #[allow(unused_variables, unused_mut)]
+            fn export_to<__E: #hoard::zone::export::Exporter>(
+                &self,
+                __ctx: &#hoard::zone::export::ExportContext<#zone>,
+                __exporter: __E,
+            ) -> Result<__E::Ok, __E::Error>
+            {
+                #body
+            }
+        }
+    })
+}
@@ -0,0 +1,118 @@
+//! `#[derive(FmtDeep)]`
+//!
+//! Implements `FmtDeep<Z>` with the `Formatter::debug_*` builders, formatting the type as
+//! `#[derive(Debug)]` would, but with every field formatted deeply. A type parameter marked with
+//! `#[hoard(zone = Z)]` is taken to be the zone; otherwise the type can be formatted in any zone
+//! its fields can be.
+use proc_macro2::{Ident, Span, TokenStream};
+use quote::quote;
+
+use super::{hoard_crate, members, member_name, mentions_any, zone_param};
+
+pub fn derive_fmt_deep(s: synstructure::Structure) -> TokenStream {
+    match try_derive_fmt_deep(s) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+/// Formats `fields`, whose values are `values`, with the builder `#name` would use.
+fn fmt_fields(name: &str, fields: &syn::Fields, values: &[TokenStream]) -> TokenStream {
+    match fields {
+        syn::Fields::Named(_) => {
+            let names = members(fields).iter().map(member_name).collect::<Vec<_>>();
+            quote! {
+                f.debug_struct(#name)
+                 #( .field(#names, &__ctx.deep(#values)) )*
+                 .finish()
+            }
+        },
+        syn::Fields::Unnamed(_) => quote! {
+            f.debug_tuple(#name)
+             #( .field(&__ctx.deep(#values)) )*
+             .finish()
+        },
+        syn::Fields::Unit => quote! {
+            f.write_str(#name)
+        },
+    }
+}
+
+fn try_derive_fmt_deep(s: synstructure::Structure) -> syn::Result<TokenStream> {
+    let ast = s.ast();
+    let name = &ast.ident;
+    let name_str = name.to_string();
+    let hoard = hoard_crate();
+
+    let mut generics = ast.generics.clone();
+    let zone = match zone_param(ast)? {
+        Some(zone) => zone,
+        None => {
+            let zone = Ident::new("__Z", Span::call_site());
+            generics.params.push(syn::parse_quote!(#zone));
+            zone
+        },
+    };
+
+    let field_tys: Vec<&syn::Type> = match &ast.data {
+        syn::Data::Struct(data) => data.fields.iter().map(|field| &field.ty).collect(),
+        syn::Data::Enum(data) => data.variants.iter()
+                                     .flat_map(|variant| variant.fields.iter())
+                                     .map(|field| &field.ty)
+                                     .collect(),
+        syn::Data::Union(_) => return Err(syn::Error::new(name.span(), "FmtDeep can't be derived for unions")),
+    };
+    // As with Export, only fields that depend on the generics get bounds.
+    let params: Vec<Ident> = generics.type_params().map(|param| param.ident.clone()).collect();
+    let where_clause = generics.make_where_clause();
+    where_clause.predicates.push(syn::parse_quote!(#zone: #hoard::zone::Zone));
+    for ty in field_tys.into_iter().filter(|ty| mentions_any(ty, &params)) {
+        where_clause.predicates.push(syn::parse_quote!(#ty: #hoard::zone::FmtDeep<#zone>));
+    }
+
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = ast.generics.split_for_impl();
+
+    let body = match &ast.data {
+        syn::Data::Struct(data) => {
+            let values: Vec<TokenStream> = members(&data.fields).iter()
+                                                                .map(|member| quote!(&self.#member))
+                                                                .collect();
+            fmt_fields(&name_str, &data.fields, &values)
+        },
+        syn::Data::Enum(data) => {
+            let arms = data.variants.iter().map(|variant| {
+                let variant_name = &variant.ident;
+                let members = members(&variant.fields);
+                let bindings: Vec<Ident> = (0 .. members.len())
+                    .map(|i| Ident::new(&format!("__field{}", i), Span::call_site()))
+                    .collect();
+                let values: Vec<TokenStream> = bindings.iter().map(|binding| quote!(#binding)).collect();
+                let fmt = fmt_fields(&variant_name.to_string(), &variant.fields, &values);
+                quote! {
+                    #name::#variant_name { #(#members: #bindings,)* } => #fmt,
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        },
+        syn::Data::Union(_) => unreachable!(),
+    };
+
+    Ok(quote! {
+        impl #impl_generics #hoard::zone::FmtDeep<#zone> for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn fmt_deep(
+                &self,
+                __ctx: &#hoard::zone::DeepContext<#zone>,
+                f: &mut ::core::fmt::Formatter,
+            ) -> ::core::fmt::Result
+            {
+                #body
+            }
+        }
+    })
+}
@@ -1,7 +1,83 @@
-use quote::quote;
+use proc_macro2::{Ident, TokenTree};
+use quote::{quote, ToTokens};
 use syn;
+use syn::spanned::Spanned;
 use synstructure::decl_derive;
 
+mod coerce;
+use self::coerce::*;
+decl_derive!([TryCoerce, attributes(try_coerce)] => derive_try_coerce);
+
+mod transfer;
+use self::transfer::*;
+decl_derive!([Transfer, attributes(hoard)] => derive_transfer);
+
+mod export;
+use self::export::*;
+decl_derive!([Export, attributes(hoard)] => derive_export);
+
+mod fmtdeep;
+use self::fmtdeep::*;
+decl_derive!([FmtDeep, attributes(hoard)] => derive_fmt_deep);
+
+/// Path to the `hoard` crate.
+///
+/// Always `::hoard`; hoard itself has an `extern crate self as hoard` so derives work within it
+/// too.
+fn hoard_crate() -> proc_macro2::TokenStream {
+    quote!(::hoard)
+}
+
+/// Returns the members of `fields`, in order.
+fn members(fields: &syn::Fields) -> Vec<syn::Member> {
+    fields.iter().enumerate().map(|(i, field)| {
+        match &field.ident {
+            Some(ident) => syn::Member::Named(ident.clone()),
+            None => syn::Member::Unnamed(syn::Index { index: i as u32, span: field.span() }),
+        }
+    }).collect()
+}
+
+/// Returns the name of a member as written, without any `r#` prefix.
+fn member_name(member: &syn::Member) -> String {
+    match member {
+        syn::Member::Named(ident) => ident.to_string().trim_start_matches("r#").to_string(),
+        syn::Member::Unnamed(index) => index.index.to_string(),
+    }
+}
+
+/// Returns true if `tokens` mentions any of `idents`.
+fn mentions_any(tokens: impl ToTokens, idents: &[Ident]) -> bool {
+    tokens.into_token_stream().into_iter().any(|tree| match tree {
+        TokenTree::Ident(ident) => idents.contains(&ident),
+        TokenTree::Group(group) => mentions_any(group.stream(), idents),
+        _ => false,
+    })
+}
+
+/// Finds the type parameter named by a `#[hoard(zone = Z)]` attribute, if there is one.
+fn zone_param(ast: &syn::DeriveInput) -> syn::Result<Option<proc_macro2::Ident>> {
+    let mut zone = None;
+    for attr in ast.attrs.iter().filter(|attr| attr.path.is_ident("hoard")) {
+        let ident: proc_macro2::Ident = attr.parse_args_with(|input: syn::parse::ParseStream| {
+            let key: proc_macro2::Ident = input.parse()?;
+            if key != "zone" {
+                return Err(syn::Error::new(key.span(), "expected `zone = ...`"));
+            }
+            input.parse::<syn::Token![=]>()?;
+            input.parse()
+        })?;
+
+        if zone.is_some() {
+            return Err(syn::Error::new(ident.span(), "zone specified more than once"));
+        } else if !ast.generics.type_params().any(|param| param.ident == ident) {
+            return Err(syn::Error::new(ident.span(), "zone must be a type parameter"));
+        }
+        zone = Some(ident);
+    }
+    Ok(zone)
+}
+
 decl_derive!([Primitive, attributes(foo)] => derive_primitive);
 
 fn derive_primitive(s: synstructure::Structure) -> proc_macro2::TokenStream {
@@ -26,9 +102,13 @@ fn derive_primitive(s: synstructure::Structure) -> proc_macro2::TokenStream {
         },
     }
 
-    let fields_ty = fields_ty.iter().map(|field| &field.ty).map(|ty| quote! { #ty });
+    let fields_ty: Vec<_> = fields_ty.iter().map(|field| &field.ty).map(|ty| quote! { #ty }).collect();
     let validate_body = quote ! {
-        #( __blob.field::<#fields_ty,_>(|err| todo!())?; )*
+        #(
+            __blob.field::<#fields_ty,_>(|err| {
+                Error(Box::<<#fields_ty as ValidateBlob>::Error>::new(err))
+            })?;
+        )*
 
         unsafe { __blob.assume_valid() }
     };
@@ -37,6 +117,7 @@ fn derive_primitive(s: synstructure::Structure) -> proc_macro2::TokenStream {
         __dst = __dst.write_primitive(#bi)?;
     });
 
+    let name = s.ast().ident.to_string();
     let t = s.gen_impl(quote! {
         extern crate hoard;
 
@@ -47,7 +128,7 @@ fn derive_primitive(s: synstructure::Structure) -> proc_macro2::TokenStream {
 
         impl ::core::fmt::Display for Error {
             fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
-                todo!()
+                write!(f, "invalid {}: {}", #name, self.0)
             }
         }
 
@@ -57,6 +138,7 @@ fn derive_primitive(s: synstructure::Structure) -> proc_macro2::TokenStream {
         gen impl ::hoard::marshal::blob::ValidateBlob for @Self {
             type Error = Error;
 
+            #[allow(unreachable_code)]
             fn validate<'__a, __V: ::hoard::marshal::blob::PaddingValidator>(
                 mut __blob: BlobCursor<'__a, Self, __V>
             ) -> Result<::hoard::marshal::blob::ValidBlob<'__a, Self>,
@@ -0,0 +1,143 @@
+//! `#[derive(Transfer)]`
+//!
+//! Implements `Transfer<Z, Y>` by transferring field by field. A type parameter marked with
+//! `#[hoard(zone = Z)]` is taken to be the zone, and the transferred type is the same type with
+//! that parameter replaced by the destination zone; otherwise the transferred type is the type
+//! itself.
+use proc_macro2::{Group, Ident, Span, TokenStream, TokenTree};
+use quote::{quote, ToTokens};
+
+use super::{hoard_crate, members, zone_param};
+
+/// Replaces every occurrence of the identifier `from` with `to`.
+fn replace_ident(tokens: impl ToTokens, from: &Ident, to: &Ident) -> TokenStream {
+    tokens.into_token_stream().into_iter().map(|tree| match tree {
+        TokenTree::Ident(ident) if ident == *from => TokenTree::Ident(to.clone()),
+        TokenTree::Group(group) => {
+            let mut new = Group::new(group.delimiter(), replace_ident(group.stream(), from, to));
+            new.set_span(group.span());
+            TokenTree::Group(new)
+        },
+        tree => tree,
+    }).collect()
+}
+
+pub fn derive_transfer(s: synstructure::Structure) -> TokenStream {
+    match try_derive_transfer(s) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+fn try_derive_transfer(s: synstructure::Structure) -> syn::Result<TokenStream> {
+    let ast = s.ast();
+    let name = &ast.ident;
+    let hoard = hoard_crate();
+
+    let dst_zone = Ident::new("__Y", Span::call_site());
+    let src_zone = zone_param(ast)?;
+
+    let mut generics = ast.generics.clone();
+    let src_zone = match src_zone {
+        Some(src_zone) => {
+            // The destination zone has to meet the same bounds as the source zone.
+            let param = generics.type_params().find(|param| param.ident == src_zone).unwrap();
+            let bounds = param.bounds.clone();
+            generics.params.push(syn::parse_quote!(#dst_zone: #bounds));
+
+            let where_clause = generics.make_where_clause();
+            let predicates: Vec<syn::WherePredicate> = where_clause.predicates.iter()
+                .map(|predicate| syn::parse2(replace_ident(predicate, &src_zone, &dst_zone)))
+                .collect::<syn::Result<_>>()?;
+            where_clause.predicates.extend(predicates);
+            src_zone
+        },
+        None => {
+            let src_zone = Ident::new("__Z", Span::call_site());
+            generics.params.push(syn::parse_quote!(#src_zone));
+            generics.params.push(syn::parse_quote!(#dst_zone));
+            src_zone
+        },
+    };
+
+    let where_clause = generics.make_where_clause();
+    where_clause.predicates.push(syn::parse_quote!(#src_zone: #hoard::zone::TryGet));
+    where_clause.predicates.push(syn::parse_quote!(#dst_zone: #hoard::zone::Alloc));
+
+    let field_tys: Vec<&syn::Type> = match &ast.data {
+        syn::Data::Struct(data) => data.fields.iter().map(|field| &field.ty).collect(),
+        syn::Data::Enum(data) => data.variants.iter()
+                                     .flat_map(|variant| variant.fields.iter())
+                                     .map(|field| &field.ty)
+                                     .collect(),
+        syn::Data::Union(_) => return Err(syn::Error::new(name.span(), "Transfer can't be derived for unions")),
+    };
+    for ty in field_tys {
+        let transferred = replace_ident(ty, &src_zone, &dst_zone);
+        where_clause.predicates.push(syn::parse_quote! {
+            #ty: #hoard::zone::Transfer<#src_zone, #dst_zone, Transferred = #transferred>
+        });
+    }
+
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = ast.generics.split_for_impl();
+
+    let transferred = replace_ident(quote!(#name #ty_generics), &src_zone, &dst_zone);
+    let transferred_path = replace_ident(ty_generics.as_turbofish(), &src_zone, &dst_zone);
+
+    let transfer_fields = |path: TokenStream, fields: &syn::Fields, bindings: Option<&[Ident]>| {
+        let members = members(fields);
+        let values: Vec<TokenStream> = match bindings {
+            Some(bindings) => bindings.iter().map(|binding| quote!(#binding)).collect(),
+            None => members.iter().map(|member| quote!(&self.#member)).collect(),
+        };
+        quote! {
+            #path {
+                #(
+                    #members: #hoard::zone::Transfer::transfer_with(#values, __transferrer)?,
+                )*
+            }
+        }
+    };
+
+    let body = match &ast.data {
+        syn::Data::Struct(data) => {
+            let value = transfer_fields(quote!(#name #transferred_path), &data.fields, None);
+            quote! { Ok(#value) }
+        },
+        syn::Data::Enum(data) => {
+            let arms = data.variants.iter().map(|variant| {
+                let variant_name = &variant.ident;
+                let members = members(&variant.fields);
+                let bindings: Vec<Ident> = (0 .. members.len())
+                    .map(|i| Ident::new(&format!("__field{}", i), Span::call_site()))
+                    .collect();
+                let value = transfer_fields(quote!(#name #transferred_path :: #variant_name),
+                                            &variant.fields, Some(&bindings));
+                quote! {
+                    #name::#variant_name { #(#members: #bindings,)* } => #value,
+                }
+            });
+            quote! {
+                Ok(match self {
+                    #(#arms)*
+                })
+            }
+        },
+        syn::Data::Union(_) => unreachable!(),
+    };
+
+    Ok(quote! {
+        impl #impl_generics #hoard::zone::Transfer<#src_zone, #dst_zone> for #name #ty_generics #where_clause {
+            type Transferred = #transferred;
+
+            fn transfer_with(
+                &self,
+                __transferrer: &mut #hoard::zone::Transferrer<#src_zone, #dst_zone>
+            ) -> Result<Self::Transferred, <#src_zone as #hoard::zone::Zone>::Error>
+            {
+                #body
+            }
+        }
+    })
+}
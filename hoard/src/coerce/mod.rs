@@ -4,7 +4,13 @@ use static_assertions::assert_impl_all;
 
 use core::alloc::Layout;
 use core::any::type_name;
+use core::marker::PhantomData;
 use core::mem::ManuallyDrop;
+use core::num::{NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU128};
+
+use leint::{Le, Be};
+
+pub use hoard_derive::TryCoerce;
 
 mod array;
 pub use self::array::*;
@@ -105,7 +111,89 @@ macro_rules! unsafe_impl_coerce {
 unsafe_impl_coerce! {
     () => ();
     bool => {bool, u8};
+    Le<u16> => {Le<u16>};
+    Le<u32> => {Le<u32>};
+    Le<u64> => {Le<u64>};
+    Le<u128> => {Le<u128>};
+    Le<NonZeroU16> => {Le<NonZeroU16>};
+    Le<NonZeroU32> => {Le<NonZeroU32>};
+    Le<NonZeroU64> => {Le<NonZeroU64>};
+    Le<NonZeroU128> => {Le<NonZeroU128>};
+    Be<u16> => {Be<u16>};
+    Be<u32> => {Be<u32>};
+    Be<u64> => {Be<u64>};
+    Be<u128> => {Be<u128>};
+    Be<NonZeroU16> => {Be<NonZeroU16>};
+    Be<NonZeroU32> => {Be<NonZeroU32>};
+    Be<NonZeroU64> => {Be<NonZeroU64>};
+    Be<NonZeroU128> => {Be<NonZeroU128>};
+}
+
+unsafe impl<T: ?Sized, U: ?Sized> TryCoerce<PhantomData<U>> for PhantomData<T> {
+    type Error = !;
+}
+
+/// Checks a single field of a derived `TryCoerce` implementation.
+///
+/// `dst_field` is never called; it only selects the type of the corresponding field in `Dst`.
+#[doc(hidden)]
+#[inline(always)]
+pub fn try_coerce_field<T, U, Dst: ?Sized, E, F>(field: &T, dst_field: F) -> Result<(), E>
+    where T: TryCoerce<U>,
+          T::Error: Into<E>,
+          F: FnOnce(&Dst) -> &U,
+{
+    let _ = dst_field;
+    T::try_coerce_ptr(field).map(|_| ()).map_err(Into::into)
 }
 
 assert_impl_all!(!: TryCoerce<!>, Coerce<!>);
 assert_impl_all!(bool: Coerce<bool>, Coerce<u8>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, TryCoerce)]
+    #[repr(C)]
+    #[try_coerce(Flagged<bool>, error = TryCoerceBoolError)]
+    #[try_coerce(Flagged<u8>)]
+    struct Flagged<B> {
+        flag: B,
+        len: Le<u32>,
+    }
+
+    #[derive(Debug, PartialEq, TryCoerce)]
+    #[repr(transparent)]
+    #[try_coerce(for<'b> Wrapped<'b>)]
+    struct Wrapped<'a>(PhantomData<&'a ()>, Le<u64>);
+
+    #[derive(Debug, PartialEq, TryCoerce)]
+    #[repr(transparent)]
+    #[try_coerce(Tagged<T>)]
+    #[try_coerce(from u8, error = TryCoerceBoolError)]
+    struct Tagged<T>(bool, PhantomData<T>);
+
+    #[test]
+    fn derived_try_coerce() {
+        let n = Flagged::<u8> { flag: 1, len: 42.into() };
+        assert_eq!(TryCoerce::<Flagged<bool>>::try_coerce(n).unwrap(),
+                   Flagged { flag: true, len: 42.into() });
+
+        let n = Flagged::<u8> { flag: 2, len: 42.into() };
+        assert_eq!(TryCoerce::<Flagged<bool>>::try_coerce(n).unwrap_err(),
+                   TryCoerceBoolError);
+
+        let b = Flagged { flag: true, len: 42.into() };
+        assert_eq!(Coerce::<Flagged<u8>>::coerce(b),
+                   Flagged::<u8> { flag: 1, len: 42.into() });
+
+        fn shorten<'a>(w: Wrapped<'static>, _: &'a ()) -> Wrapped<'a> {
+            w.coerce()
+        }
+        assert_eq!(shorten(Wrapped(PhantomData, 7.into()), &()).1, 7);
+
+        assert_eq!(TryCoerce::<Tagged<()>>::try_coerce(1u8).unwrap(), Tagged(true, PhantomData));
+        assert_eq!(TryCoerce::<Tagged<()>>::try_coerce(2u8).unwrap_err(), TryCoerceBoolError);
+    }
+}
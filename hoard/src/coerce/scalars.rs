@@ -17,3 +17,9 @@ unsafe impl TryCoerce<bool> for u8 {
 #[derive(Error,Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Hash)]
 #[error("not a valid bool")]
 pub struct TryCoerceBoolError;
+
+impl From<!> for TryCoerceBoolError {
+    fn from(never: !) -> Self {
+        never
+    }
+}
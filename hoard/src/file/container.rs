@@ -0,0 +1,329 @@
+//! Several named piles in one file.
+//!
+//! A container file starts with a header and a directory of fixed-size entries, followed by the
+//! bytes of each pile:
+//!
+//! ```text
+//! magic: [u8; 12]     "\0Hoard Piles"
+//! version: Le<u16>
+//! count: Le<u16>
+//! entries: [Entry; count]
+//! pile bytes...
+//! ```
+//!
+//! Each `Entry` is a NUL-padded name, the version of the pile, and its start and length in the file.
+//! Piles are independent of each other: each is exposed as its own `Snapshot` range, and each
+//! version is written by `ContainerBuilder`, which rewrites the whole file.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str;
+use std::sync::Arc;
+
+use memmap::Mmap;
+
+use static_assertions::const_assert_eq;
+
+use singlelife::Unique;
+
+use crate::pile::{
+    TryPile,
+    snapshot::Snapshot,
+};
+
+const MAGIC: [u8; 12] = *b"\x00Hoard Piles";
+
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 64;
+
+/// Maximum length of a pile name, in bytes.
+pub const MAX_NAME_LEN: usize = 40;
+
+// Name, version, start, and length.
+const_assert_eq!(MAX_NAME_LEN + 3 * 8, ENTRY_SIZE);
+
+/// A pile within a container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    name: String,
+    version: u64,
+    start: usize,
+    len: usize,
+}
+
+impl Entry {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The version of the pile, incremented every time it's replaced.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// A file containing multiple piles.
+#[derive(Debug)]
+pub struct Container {
+    mapping: Arc<Mmap>,
+    entries: Vec<Entry>,
+}
+
+impl Container {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let fd = OpenOptions::new()
+                    .read(true)
+                    .open(path)?;
+
+        Self::open_fd(fd)
+    }
+
+    pub fn open_fd(fd: File) -> io::Result<Self> {
+        let mapping = unsafe { Mmap::map(&fd)? };
+        let entries = parse_entries(&mapping)?;
+
+        Ok(Self {
+            mapping: Arc::new(mapping),
+            entries,
+        })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Calls `f` with a `Snapshot` of the named pile.
+    ///
+    /// Like `pile()`, every call gets a snapshot with a brand new lifetime.
+    pub fn snapshot<R>(&self, name: &str, f: impl for<'p> FnOnce(Snapshot<'p, Arc<Mmap>>) -> R) -> Option<R> {
+        let entry = self.get(name)?;
+        Some(f(unsafe { self.entry_snapshot(entry) }))
+    }
+
+    /// Calls `f` with the named pile.
+    ///
+    /// Every call gets a pile with a brand new `'pile` lifetime, so offsets from one pile can't
+    /// be used with another.
+    pub fn pile<R>(&self, name: &str, f: impl FnOnce(TryPile) -> R) -> Option<R> {
+        let entry = self.get(name)?;
+        let snapshot: Snapshot<'static, _> = unsafe { self.entry_snapshot(entry) };
+
+        Some(Unique::new(&snapshot, |snapshot| {
+            // Safe because the snapshot, and thus the pile, is unique.
+            let snapshot: &Snapshot<_> = Unique::into_inner(snapshot);
+            f(unsafe { TryPile::from_mapping_unchecked(snapshot) })
+        }))
+    }
+
+    /// Creates a snapshot of a pile, with whatever brand the caller picks.
+    ///
+    /// Unsafe because the caller must ensure the brand isn't shared with any other snapshot.
+    unsafe fn entry_snapshot<'p>(&self, entry: &Entry) -> Snapshot<'p, Arc<Mmap>> {
+        Snapshot::new_unchecked_with_range(
+            self.mapping.clone(),
+            entry.start .. entry.start + entry.len
+        ).expect("entries to have been validated")
+    }
+}
+
+fn parse_entries(bytes: &[u8]) -> io::Result<Vec<Entry>> {
+    if bytes.len() < HEADER_SIZE || bytes[.. 12] != MAGIC {
+        return Err(invalid_data("not a container file"));
+    }
+
+    let version = u16::from_le_bytes(bytes[12 .. 14].try_into().unwrap());
+    if version != 0 {
+        return Err(invalid_data(format!("unsupported container version {}", version)));
+    }
+
+    let count = u16::from_le_bytes(bytes[14 .. 16].try_into().unwrap()) as usize;
+    let directory = bytes.get(HEADER_SIZE .. HEADER_SIZE + count * ENTRY_SIZE)
+                         .ok_or_else(|| invalid_data("truncated container directory"))?;
+
+    let mut entries: Vec<Entry> = Vec::with_capacity(count);
+    for raw in directory.chunks_exact(ENTRY_SIZE) {
+        let name = &raw[.. MAX_NAME_LEN];
+        let name_len = name.iter().position(|b| *b == 0).unwrap_or(MAX_NAME_LEN);
+        let name = str::from_utf8(&name[.. name_len])
+                       .map_err(|_| invalid_data("pile name not valid UTF-8"))?;
+
+        let field = |i: usize| u64::from_le_bytes(raw[i .. i + 8].try_into().unwrap());
+        let version = field(MAX_NAME_LEN);
+        let start = field(MAX_NAME_LEN + 8) as usize;
+        let len = field(MAX_NAME_LEN + 16) as usize;
+
+        if start.checked_add(len).map_or(true, |end| end > bytes.len()) {
+            return Err(invalid_data(format!("pile {:?} out of range", name)));
+        } else if entries.iter().any(|entry| entry.name == name) {
+            return Err(invalid_data(format!("duplicate pile {:?}", name)));
+        }
+
+        entries.push(Entry { name: name.to_owned(), version, start, len });
+    }
+    Ok(entries)
+}
+
+/// Builds a container file.
+#[derive(Debug, Default, Clone)]
+pub struct ContainerBuilder {
+    piles: BTreeMap<String, (u64, Vec<u8>)>,
+}
+
+impl ContainerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a builder with the same piles as an existing container.
+    pub fn from_container(container: &Container) -> Self {
+        let piles = container.entries.iter().map(|entry| {
+            let bytes = container.mapping[entry.start .. entry.start + entry.len].to_vec();
+            (entry.name.clone(), (entry.version, bytes))
+        }).collect();
+
+        Self { piles }
+    }
+
+    /// Inserts a pile, returning its new version.
+    ///
+    /// # Panics
+    ///
+    /// If the name is longer than `MAX_NAME_LEN`, or contains a NUL.
+    pub fn insert(&mut self, name: impl Into<String>, bytes: impl Into<Vec<u8>>) -> u64 {
+        let name = name.into();
+        assert!(name.len() <= MAX_NAME_LEN && !name.contains('\0'),
+                "invalid pile name {:?}", name);
+
+        let version = self.piles.get(&name).map_or(0, |(version, _)| version + 1);
+        self.piles.insert(name, (version, bytes.into()));
+        version
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.piles.remove(name).is_some()
+    }
+
+    pub fn write(&self, mut dst: impl Write) -> io::Result<()> {
+        let count: u16 = self.piles.len().try_into()
+                             .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many piles"))?;
+
+        dst.write_all(&MAGIC)?;
+        dst.write_all(&0u16.to_le_bytes())?;
+        dst.write_all(&count.to_le_bytes())?;
+
+        let mut start = HEADER_SIZE + self.piles.len() * ENTRY_SIZE;
+        for (name, (version, bytes)) in &self.piles {
+            let mut raw_name = [0u8; MAX_NAME_LEN];
+            raw_name[.. name.len()].copy_from_slice(name.as_bytes());
+
+            dst.write_all(&raw_name)?;
+            dst.write_all(&version.to_le_bytes())?;
+            dst.write_all(&(start as u64).to_le_bytes())?;
+            dst.write_all(&(bytes.len() as u64).to_le_bytes())?;
+            start += bytes.len();
+        }
+
+        for (_, bytes) in self.piles.values() {
+            dst.write_all(bytes)?;
+        }
+        dst.flush()
+    }
+
+    /// Writes the container to a file, atomically replacing it if it already exists.
+    pub fn write_to_path(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let fd = File::create(&tmp_path)?;
+        self.write(io::BufWriter::new(&fd))?;
+        fd.sync_all()?;
+
+        std::fs::rename(&tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+    use leint::Le;
+
+    #[test]
+    fn container_roundtrip() -> io::Result<()> {
+        let tmpdir = tempdir()?;
+        let path = tmpdir.path().join("container");
+
+        let mut builder = ContainerBuilder::new();
+        assert_eq!(builder.insert("foo", vec![1, 2, 3, 4]), 0);
+        assert_eq!(builder.insert("bar", vec![42]), 0);
+        builder.write_to_path(&path)?;
+
+        let container = Container::open(&path)?;
+        assert_eq!(container.entries().iter().map(Entry::name).collect::<Vec<_>>(),
+                   vec!["bar", "foo"]);
+
+        container.pile("foo", |pile| {
+            let tip = pile.try_get_tip::<Le<u32>>().unwrap();
+            assert_eq!(**tip, 0x04030201);
+        }).unwrap();
+        container.pile("bar", |pile| {
+            assert_eq!(**pile.try_get_tip::<u8>().unwrap(), 42);
+            assert!(pile.try_get_tip::<Le<u16>>().is_err());
+        }).unwrap();
+        assert!(container.pile("baz", |_| ()).is_none());
+
+        container.snapshot("foo", |snapshot| {
+            assert_eq!(&snapshot[..], &[1, 2, 3, 4]);
+        }).unwrap();
+        assert!(container.snapshot("baz", |_| ()).is_none());
+
+        // Replace one pile, leaving the other untouched.
+        let container = Container::open(&path)?;
+        let mut builder = ContainerBuilder::from_container(&container);
+        assert_eq!(builder.insert("foo", vec![5, 6]), 1);
+        drop(container);
+        builder.write_to_path(&path)?;
+
+        let container = Container::open(&path)?;
+        assert_eq!(container.get("foo").unwrap().version(), 1);
+        assert_eq!(container.get("foo").unwrap().len(), 2);
+        assert_eq!(container.get("bar").unwrap().version(), 0);
+
+        container.pile("bar", |pile| {
+            assert_eq!(**pile.try_get_tip::<u8>().unwrap(), 42);
+        }).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn container_invalid() {
+        assert!(parse_entries(b"not a container").is_err());
+
+        let mut buf = vec![];
+        let mut builder = ContainerBuilder::new();
+        builder.insert("foo", vec![1, 2, 3, 4]);
+        builder.write(&mut buf).unwrap();
+        assert_eq!(parse_entries(&buf).unwrap().len(), 1);
+
+        // Truncating the pile bytes leaves the entry out of range.
+        buf.truncate(buf.len() - 1);
+        assert_eq!(parse_entries(&buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
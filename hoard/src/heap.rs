@@ -21,7 +21,7 @@ use crate::{
 #[derive(Default,Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Hash)]
 pub struct Heap;
 
-#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Hash)]
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Hash,NonZero)]
 pub struct HeapPtr(NonNull<()>);
 
 unsafe impl Send for HeapPtr {}
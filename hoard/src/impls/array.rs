@@ -117,6 +117,30 @@ impl<'a, Y, T: Encode<'a, Y>, const N: usize> Encode<'a, Y> for [T; N] {
 
 impl<T: Primitive, const N: usize> Primitive for [T; N] {}
 
+impl<Z: TryGet, Y: Alloc, T, const N: usize> Transfer<Z, Y> for [T; N]
+where T: Transfer<Z, Y>,
+{
+    type Transferred = [T::Transferred; N];
+
+    fn transfer_with(&self, transferrer: &mut Transferrer<Z, Y>) -> Result<Self::Transferred, Z::Error> {
+        let mut r: [MaybeUninit<T::Transferred>; N] = unsafe { MaybeUninit::uninit().assume_init() };
+        let mut initializer = SliceInitializer::new(&mut r[..]);
+
+        for item in self.iter() {
+            initializer.push(item.transfer_with(transferrer)?)
+        }
+
+        initializer.done();
+
+        // Need a transmute_copy() as Rust doesn't seem to know the two arrays are the same size.
+        let r2 = unsafe { mem::transmute_copy(&r) };
+        assert_eq!(mem::size_of_val(&r), mem::size_of_val(&r2));
+        assert_eq!(mem::align_of_val(&r), mem::align_of_val(&r2));
+
+        Ok(r2)
+    }
+}
+
 /*
 assert_impl_all!([u8;10]: Load<!>);
 assert_impl_all!([[bool;10]; 10]: Load<!>);
@@ -8,6 +8,7 @@ use crate::marshal::blob::*;
 use crate::marshal::decode::*;
 use crate::marshal::encode::*;
 use crate::marshal::{PtrValidator, Dumper, Primitive};
+use crate::zone::{TryGet, Alloc, transfer::{Transfer, Transferrer}};
 
 pub mod never;
 pub mod scalar;
@@ -58,6 +58,14 @@ impl<'a, Z> Encode<'a, Z> for ! {
 }
 impl Primitive for ! {}
 
+impl<Z: TryGet, Y: Alloc> Transfer<Z, Y> for ! {
+    type Transferred = !;
+
+    fn transfer_with(&self, _: &mut Transferrer<Z, Y>) -> Result<!, Z::Error> {
+        match *self {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
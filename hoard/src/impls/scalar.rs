@@ -1,10 +1,11 @@
+use std::fmt;
 use std::mem;
 use std::num;
 use std::slice;
 
 use thiserror::Error;
 
-use leint::Le;
+use leint::{Le, Be};
 
 use super::*;
 
@@ -30,6 +31,7 @@ macro_rules! impl_all_valid {
             dst.write_bytes(src)?
                .finish()
         });
+        crate::impl_transfer_for_primitive!($t);
 
         impl Primitive for $t {}
     )+}
@@ -39,6 +41,9 @@ impl_all_valid! {
     (),
     u8, Le<u16>, Le<u32>, Le<u64>, Le<u128>,
     i8, Le<i16>, Le<i32>, Le<i64>, Le<i128>,
+        Be<u16>, Be<u32>, Be<u64>, Be<u128>,
+        Be<i16>, Be<i32>, Be<i64>, Be<i128>,
+    Le<f32>, Le<f64>,
 }
 
 #[non_exhaustive]
@@ -63,6 +68,146 @@ crate::impl_encode_for_primitive!(bool, |this, dst| {
     dst.write_bytes(&[if *this { 1 } else { 0 }][..])?
        .finish()
 });
+crate::impl_transfer_for_primitive!(bool);
+
+#[non_exhaustive]
+#[derive(Error, Debug)]
+#[error("invalid char blob")]
+pub struct ValidateCharError;
+
+/// The persistent form of a `char`: a `Le<u32>` holding a Unicode scalar value.
+///
+/// `char` itself is four-byte aligned, so it can't be loaded by reference, and doesn't implement
+/// `Persist`; convert to and from `PersistChar` instead.
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PersistChar(Le<u32>);
+
+impl PersistChar {
+    #[inline]
+    pub fn new(c: char) -> Self {
+        Self(Le::new(c as u32))
+    }
+
+    #[inline]
+    pub fn get(self) -> char {
+        // SAFETY: a PersistChar can only be created from a char, or by validation.
+        unsafe { std::char::from_u32_unchecked(self.0.get()) }
+    }
+}
+
+impl From<char> for PersistChar {
+    #[inline]
+    fn from(c: char) -> Self {
+        Self::new(c)
+    }
+}
+
+impl From<PersistChar> for char {
+    #[inline]
+    fn from(c: PersistChar) -> Self {
+        c.get()
+    }
+}
+
+impl fmt::Debug for PersistChar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.get(), f)
+    }
+}
+
+impl ValidateBlob for PersistChar {
+    type Error = ValidateCharError;
+    fn validate<'a, V>(blob: BlobCursor<'a, Self, V>) -> Result<ValidBlob<'a, Self>, BlobError<Self::Error, V::Error>>
+        where V: PaddingValidator
+    {
+        let n = u32::from_le_bytes([blob[0], blob[1], blob[2], blob[3]]);
+        match std::char::from_u32(n) {
+            Some(_) => unsafe { blob.assume_valid() },
+            None => Err(BlobError::Error(ValidateCharError)),
+        }
+    }
+}
+
+crate::impl_decode_for_primitive!(PersistChar);
+crate::impl_encode_for_primitive!(PersistChar, |this, dst| {
+    dst.write_bytes(&this.0.get().to_le_bytes())?
+       .finish()
+});
+crate::impl_transfer_for_primitive!(PersistChar);
+
+impl Primitive for PersistChar {}
+
+#[non_exhaustive]
+#[derive(Error, Debug)]
+#[error("non-canonical NaN")]
+pub struct ValidateCanonicalError;
+
+/// A float whose only NaN is the canonical NaN.
+///
+/// Every bit pattern is a valid `Le<f32>`, so equal NaNs can have different encodings; validating a
+/// `Canonical<Le<f32>>` rejects every NaN but the canonical one, so equal values always have the
+/// same encoding.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Canonical<T>(T);
+
+macro_rules! impl_canonical {
+    ($( $t:ident => $bits:ident, )+) => {$(
+        impl Canonical<Le<$t>> {
+            /// Creates a new `Canonical`, replacing any NaN with the canonical NaN.
+            #[inline]
+            pub fn new(n: $t) -> Self {
+                Self(Le::<$t>::new_canonical(n))
+            }
+
+            #[inline]
+            pub fn get(self) -> $t {
+                self.0.get()
+            }
+        }
+
+        impl From<Canonical<Le<$t>>> for Le<$t> {
+            #[inline]
+            fn from(n: Canonical<Le<$t>>) -> Self {
+                n.0
+            }
+        }
+
+        impl ValidateBlob for Canonical<Le<$t>> {
+            type Error = ValidateCanonicalError;
+            fn validate<'a, V>(blob: BlobCursor<'a, Self, V>)
+                -> Result<ValidBlob<'a, Self>, BlobError<Self::Error, V::Error>>
+                where V: PaddingValidator
+            {
+                blob.validate_bytes(|blob| {
+                    let mut bytes = [0; mem::size_of::<$t>()];
+                    bytes.copy_from_slice(&blob[..]);
+                    let n = Le::new($t::from_bits($bits::from_le_bytes(bytes)));
+                    if n.is_canonical() {
+                        Ok(unsafe { blob.assume_valid() })
+                    } else {
+                        Err(ValidateCanonicalError)
+                    }
+                })
+            }
+        }
+
+        crate::impl_decode_for_primitive!(Canonical<Le<$t>>);
+        crate::impl_encode_for_primitive!(Canonical<Le<$t>>, |this, dst| {
+            dst.write_bytes(&this.get().to_bits().to_le_bytes())?
+               .finish()
+        });
+        crate::impl_transfer_for_primitive!(Canonical<Le<$t>>);
+
+        impl Primitive for Canonical<Le<$t>> {}
+    )+}
+}
+
+impl_canonical! {
+    f32 => u32,
+    f64 => u64,
+}
 
 #[non_exhaustive]
 #[derive(Debug, Error)]
@@ -98,6 +243,7 @@ macro_rules! impl_nonzero {
             dst.write_bytes(src)?
                .finish()
         });
+        crate::impl_transfer_for_primitive!($t);
 
         impl Primitive for $t {}
     )+}
@@ -106,4 +252,6 @@ macro_rules! impl_nonzero {
 impl_nonzero! {
     num::NonZeroU8, Le<num::NonZeroU16>, Le<num::NonZeroU32>, Le<num::NonZeroU64>, Le<num::NonZeroU128>,
     num::NonZeroI8, Le<num::NonZeroI16>, Le<num::NonZeroI32>, Le<num::NonZeroI64>, Le<num::NonZeroI128>,
+    Be<num::NonZeroU16>, Be<num::NonZeroU32>, Be<num::NonZeroU64>, Be<num::NonZeroU128>,
+    Be<num::NonZeroI16>, Be<num::NonZeroI32>, Be<num::NonZeroI64>, Be<num::NonZeroI128>,
 }
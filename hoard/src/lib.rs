@@ -44,6 +44,9 @@ compile_error!("little endian required");
 
 use thiserror::Error;
 
+// Lets the derives' ::hoard paths work within hoard itself.
+extern crate self as hoard;
+
 // Utilities
 pub mod bytes;
 pub mod coerce;
@@ -56,10 +59,14 @@ pub mod zone;
 pub mod impls;
 
 pub mod pile;
+pub mod file;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 /// Prelude
 pub mod prelude {
-    pub use leint::Le;
+    pub use leint::{Le, Be};
 
     pub use crate::zone::{
         Alloc, Zone,
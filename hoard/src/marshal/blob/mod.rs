@@ -17,7 +17,7 @@ pub mod padding;
 pub use self::padding::PaddingValidator;
 
 mod writeblob;
-pub use self::writeblob::WriteBlob;
+pub use self::writeblob::{WriteBlob, IoWriteBlob};
 
 use crate::bytes::Bytes;
 
@@ -84,6 +84,10 @@ impl<'a, T: ?Sized> Blob<'a, T> {
     pub fn into_cursor_ignore_padding(self) -> BlobCursor<'a, T, padding::IgnorePadding> {
         BlobCursor::new(self, padding::IgnorePadding)
     }
+
+    pub fn into_cursor_with<V: PaddingValidator>(self, padding_validator: V) -> BlobCursor<'a, T, V> {
+        BlobCursor::new(self, padding_validator)
+    }
 }
 
 impl<'a, T> From<&'a Bytes<T>> for Blob<'a, T> {
@@ -1,5 +1,7 @@
 //! Padding validation
 
+use std::cell::Cell;
+
 pub unsafe trait PaddingValidator : Copy {
     type Error;
     fn validate_padding(&self, buf: &[u8]) -> Result<(), Self::Error>;
@@ -36,6 +38,26 @@ unsafe impl PaddingValidator for CheckPadding {
     }
 }
 
+/// Counts padding bytes, without checking them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CountPadding<'a>(&'a Cell<usize>);
+
+impl<'a> CountPadding<'a> {
+    pub fn new(count: &'a Cell<usize>) -> Self {
+        Self(count)
+    }
+}
+
+unsafe impl PaddingValidator for CountPadding<'_> {
+    type Error = !;
+
+    #[inline(always)]
+    fn validate_padding(&self, buf: &[u8]) -> Result<(), Self::Error> {
+        self.0.set(self.0.get() + buf.len());
+        Ok(())
+    }
+}
+
 unsafe impl<T: PaddingValidator> PaddingValidator for &'_ T {
     type Error = T::Error;
 
@@ -62,5 +84,11 @@ mod test {
         assert_eq!(check.validate_padding(&[]), Ok(()));
         assert_eq!(check.validate_padding(&[0,0,0]), Ok(()));
         assert_eq!(check.validate_padding(&[1,2,3]), Ok(()));
+
+        let count = Cell::new(0);
+        let check = CountPadding::new(&count);
+        assert_eq!(check.validate_padding(&[0,0,0]), Ok(()));
+        assert_eq!(check.validate_padding(&[1,2]), Ok(()));
+        assert_eq!(count.get(), 5);
     }
 }
@@ -1,5 +1,5 @@
 use std::convert::TryInto;
-use std::io::Cursor;
+use std::io::{self, Cursor};
 use std::mem::{self, MaybeUninit};
 use std::ptr;
 
@@ -147,6 +147,40 @@ impl<'a> WriteBlob for Vec<u8> {
     }
 }
 
+/// `WriteBlob` adapter for `io::Write`.
+///
+/// Writes the blob directly to the inner writer, returning the writer when finished.
+#[derive(Debug)]
+pub struct IoWriteBlob<W> {
+    inner: W,
+    remaining: usize,
+}
+
+impl<W: io::Write> IoWriteBlob<W> {
+    /// Creates a new `IoWriteBlob` for a blob of `len` bytes.
+    pub fn new(inner: W, len: usize) -> Self {
+        Self { inner, remaining: len }
+    }
+}
+
+impl<W: io::Write> WriteBlob for IoWriteBlob<W> {
+    type Ok = W;
+    type Error = io::Error;
+
+    #[inline]
+    fn write_bytes(mut self, src: &[u8]) -> Result<Self, Self::Error> {
+        self.remaining = self.remaining.checked_sub(src.len()).expect("overflow");
+        self.inner.write_all(src)?;
+        Ok(self)
+    }
+
+    #[inline]
+    fn finish(self) -> Result<Self::Ok, Self::Error> {
+        assert_eq!(self.remaining, 0, "Not all bytes written");
+        Ok(self.inner)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -184,4 +218,13 @@ mod tests {
         let dst = dst.finish().unwrap();
         assert!(ptr::eq(dst, &buf[..] as *const _ as *const [u8]));
     }
+
+    #[test]
+    fn test_io_write_blob() {
+        let dst = IoWriteBlob::new(vec![], 5);
+
+        let dst = dst.write_bytes(&[1]).unwrap();
+        let dst = dst.write_bytes(&[2,3,4,5]).unwrap();
+        assert_eq!(dst.finish().unwrap(), &[1,2,3,4,5]);
+    }
 }
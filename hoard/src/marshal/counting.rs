@@ -0,0 +1,96 @@
+//! Save statistics.
+
+use std::cell::Cell;
+
+use crate::pointee::Pointee;
+use crate::zone::{Zone, ValidPtr};
+
+use super::Dumper;
+
+/// Statistics of a save.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SaveStats {
+    /// Number of blobs written.
+    pub blobs_written: usize,
+
+    /// Total size of the blobs written.
+    pub bytes_written: usize,
+
+    /// Number of clean pointers that were reused, rather than saved again.
+    pub clean_reused: usize,
+}
+
+/// `Dumper` wrapper that counts what was saved.
+#[derive(Debug)]
+pub struct CountingDumper<D> {
+    inner: D,
+    stats: SaveStats,
+
+    // try_save_ptr() only gets &self
+    clean_reused: Cell<usize>,
+}
+
+impl<D> CountingDumper<D> {
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            stats: SaveStats::default(),
+            clean_reused: Cell::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> SaveStats {
+        SaveStats {
+            clean_reused: self.clean_reused.get(),
+            ..self.stats
+        }
+    }
+
+    /// Unwraps the dumper, returning the inner dumper and the statistics.
+    pub fn into_parts(self) -> (D, SaveStats) {
+        let stats = self.stats();
+        (self.inner, stats)
+    }
+}
+
+impl<Y, D: Dumper<Y>> Dumper<Y> for CountingDumper<D> {
+    type Error = D::Error;
+
+    type WriteBlob = D::WriteBlob;
+    type WriteBlobOk = D::WriteBlobOk;
+    type WriteBlobError = D::WriteBlobError;
+
+    type BlobPtr = D::BlobPtr;
+
+    #[inline]
+    fn try_save_ptr<'a, T: ?Sized + Pointee>(&self, ptr: &'a ValidPtr<T, Y>) -> Result<Y::PersistPtr, &'a T>
+        where Y: Zone
+    {
+        let r = self.inner.try_save_ptr(ptr);
+        if r.is_ok() {
+            self.clean_reused.set(self.clean_reused.get() + 1);
+        }
+        r
+    }
+
+    fn save_blob(
+        self,
+        size: usize,
+        f: impl FnOnce(Self::WriteBlob) -> Result<Self::WriteBlobOk, Self::WriteBlobError>
+    ) -> Result<(Self, Self::BlobPtr), Self::Error>
+    {
+        let Self { inner, mut stats, clean_reused } = self;
+        let (inner, ptr) = inner.save_blob(size, f)?;
+
+        stats.blobs_written += 1;
+        stats.bytes_written += size;
+        Ok((Self { inner, stats, clean_reused }, ptr))
+    }
+
+    #[inline(always)]
+    fn blob_ptr_to_zone_ptr(ptr: Self::BlobPtr) -> Y::PersistPtr
+        where Y: Zone
+    {
+        D::blob_ptr_to_zone_ptr(ptr)
+    }
+}
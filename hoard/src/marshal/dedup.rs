@@ -0,0 +1,188 @@
+//! Blob deduplication.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::pointee::Pointee;
+use crate::zone::{Zone, ValidPtr};
+
+use super::Dumper;
+use super::blob::WriteBlob;
+
+/// Index of previously saved blobs.
+///
+/// Blobs are indexed by their full contents, so the index uses memory proportional to the total
+/// size of the distinct blobs saved. An index can be reused across saves, but only with dumpers
+/// writing to the same pile: the blob pointers it holds are returned as-is.
+pub struct DedupIndex<P> {
+    blobs: HashMap<Vec<u8>, P>,
+}
+
+impl<P> Default for DedupIndex<P> {
+    fn default() -> Self {
+        Self { blobs: HashMap::new() }
+    }
+}
+
+impl<P> DedupIndex<P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of distinct blobs in the index.
+    pub fn len(&self) -> usize {
+        self.blobs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blobs.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.blobs.clear()
+    }
+
+    /// Looks up the pointer to a previously saved blob.
+    pub fn get(&self, blob: &[u8]) -> Option<&P> {
+        self.blobs.get(blob)
+    }
+}
+
+impl<P: fmt::Debug> fmt::Debug for DedupIndex<P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+         .entries(self.blobs.iter().map(|(blob, ptr)| (ptr, blob)))
+         .finish()
+    }
+}
+
+/// Deduplicating `Dumper` wrapper.
+///
+/// Each blob is encoded to a buffer first; if identical bytes have already been saved, the existing
+/// blob pointer is returned rather than saving the blob again.
+#[derive(Debug)]
+pub struct DedupDumper<D, P> {
+    inner: D,
+    index: DedupIndex<P>,
+}
+
+impl<D, P> DedupDumper<D, P> {
+    /// Wraps a dumper with a new, empty, index.
+    pub fn new(inner: D) -> Self {
+        Self::with_index(inner, DedupIndex::new())
+    }
+
+    /// Wraps a dumper, reusing an existing index.
+    pub fn with_index(inner: D, index: DedupIndex<P>) -> Self {
+        Self { inner, index }
+    }
+
+    pub fn index(&self) -> &DedupIndex<P> {
+        &self.index
+    }
+
+    /// Unwraps the dumper, returning the inner dumper and the index.
+    pub fn into_parts(self) -> (D, DedupIndex<P>) {
+        (self.inner, self.index)
+    }
+}
+
+impl<Y, D> Dumper<Y> for DedupDumper<D, D::BlobPtr>
+where D: Dumper<Y>,
+      D::BlobPtr: Clone,
+{
+    type Error = D::Error;
+
+    type WriteBlob = Vec<u8>;
+    type WriteBlobOk = Vec<u8>;
+    type WriteBlobError = !;
+
+    type BlobPtr = D::BlobPtr;
+
+    #[inline]
+    fn try_save_ptr<'a, T: ?Sized + Pointee>(&self, ptr: &'a ValidPtr<T, Y>) -> Result<Y::PersistPtr, &'a T>
+        where Y: Zone
+    {
+        self.inner.try_save_ptr(ptr)
+    }
+
+    fn save_blob(
+        self,
+        size: usize,
+        f: impl FnOnce(Self::WriteBlob) -> Result<Self::WriteBlobOk, Self::WriteBlobError>
+    ) -> Result<(Self, Self::BlobPtr), Self::Error>
+    {
+        let blob = match f(Vec::with_capacity(size)) {
+            Ok(blob) => blob,
+            Err(never) => never,
+        };
+        assert_eq!(blob.len(), size, "wrong blob size");
+
+        if let Some(ptr) = self.index.get(&blob) {
+            let ptr = ptr.clone();
+            Ok((self, ptr))
+        } else {
+            let Self { inner, mut index } = self;
+            let (inner, ptr) = inner.save_blob(size, |dst| {
+                dst.write_bytes(&blob)?
+                   .finish()
+            })?;
+            index.blobs.insert(blob, ptr.clone());
+            Ok((Self { inner, index }, ptr))
+        }
+    }
+
+    #[inline(always)]
+    fn blob_ptr_to_zone_ptr(ptr: Self::BlobPtr) -> Y::PersistPtr
+        where Y: Zone
+    {
+        D::blob_ptr_to_zone_ptr(ptr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::marshal::encode::Encode;
+    use crate::pile::{TryPile, TryPileMut, VecDumper};
+    use crate::zone::Alloc;
+
+    #[test]
+    fn dedup_blobs() {
+        let pile = TryPileMut::default();
+        let x = [pile.alloc(1u8), pile.alloc(2u8), pile.alloc(1u8)];
+
+        let mut buf = vec![];
+        let dumper = DedupDumper::new(VecDumper::new(pile, &mut buf));
+
+        let mut state = x.make_encode_state();
+        let dumper = x.encode_poll(&mut state, dumper).unwrap();
+        let (dumper, _) = dumper.encode_value(&x, &state).unwrap();
+
+        let (_, index) = dumper.into_parts();
+        assert_eq!(index.len(), 3);
+        assert_eq!(buf,
+                   &[1, 2,
+                     1, 0, 0, 0, 0, 0, 0, 0,
+                     3, 0, 0, 0, 0, 0, 0, 0,
+                     1, 0, 0, 0, 0, 0, 0, 0,
+                   ][..]);
+
+        // Blobs from the previous save are reused
+        TryPile::new(&buf, |pile| {
+            let pile = TryPileMut::from(pile);
+            let y = pile.alloc(2u8);
+
+            let mut buf2 = vec![];
+            let dumper = DedupDumper::with_index(VecDumper::new(pile, &mut buf2), index);
+
+            let mut state = y.make_encode_state();
+            let dumper = y.encode_poll(&mut state, dumper).unwrap();
+            let (_, ptr) = dumper.encode_value(&y, &state).unwrap();
+
+            assert_eq!(ptr.get(), buf.len());
+            assert_eq!(buf2, &[3, 0, 0, 0, 0, 0, 0, 0]);
+        });
+    }
+}
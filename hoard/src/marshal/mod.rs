@@ -11,6 +11,8 @@ pub mod load;
 use self::load::{PersistPointee, ValidatePointeeChildren};
 
 pub mod encode;
+pub mod dedup;
+pub mod counting;
 pub mod save;
 
 pub trait Primitive : decode::Decode<!> + for<'a> encode::Encode<'a, !, Encoded=Self> {
@@ -0,0 +1,482 @@
+//! Arena-backed mutable piles.
+//!
+//! `TryPileMut` heap-allocates every dirty value individually. `TryPileArena` instead bump
+//! allocates dirty values from an `Arena`, which is freed in bulk with `Arena::reset()` once the
+//! dirty values have been saved. Dirty pointers are still `OffsetMut`'s in their pointer form, so
+//! everything that works with `TryPileMut` - `VecDumper`, `WriteDumper`, copy-on-write via
+//! `try_get_mut()` - works with `TryPileArena` too.
+//!
+//! Values are still dropped individually when their `OwnedPtr` is dropped; only the memory is
+//! reclaimed in bulk.
+//!
+//! Every value is preceded by a pointer back to its arena, which is how `clone_ptr()` - which only
+//! gets the pointer - allocates the clone in the same arena.
+
+use std::alloc::Layout;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::fmt;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::ptr::{self, NonNull};
+
+use owned::Take;
+
+use crate::pointee::Pointee;
+use crate::zone::{*, refs::*};
+use crate::marshal::load::*;
+use crate::marshal::save::*;
+use crate::marshal::*;
+
+use super::*;
+
+/// Default size of the chunks an `Arena` allocates.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Bump allocator for dirty values.
+pub struct Arena {
+    chunks: RefCell<Vec<Box<[MaybeUninit<u8>]>>>,
+    next: Cell<usize>,
+    end: Cell<usize>,
+    chunk_size: usize,
+    allocated: Cell<usize>,
+}
+
+impl fmt::Debug for Arena {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Arena")
+            .field("chunks", &self.chunks.borrow().len())
+            .field("chunk_size", &self.chunk_size)
+            .field("allocated", &self.allocated.get())
+            .finish()
+    }
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self::with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Creates a new `Arena` that allocates memory `chunk_size` bytes at a time.
+    ///
+    /// Values larger than `chunk_size` get a chunk of their own.
+    pub fn with_chunk_size(chunk_size: usize) -> Self {
+        Self {
+            chunks: RefCell::new(vec![]),
+            next: Cell::new(0),
+            end: Cell::new(0),
+            chunk_size,
+            allocated: Cell::new(0),
+        }
+    }
+
+    /// Returns the number of bytes allocated for values since the last reset.
+    pub fn allocated(&self) -> usize {
+        self.allocated.get()
+    }
+
+    /// Frees every allocation in bulk.
+    ///
+    /// Taking `&mut self` guarantees that no `TryPileArena` zone - and thus no dirty pointer - is
+    /// still borrowing the arena. The first chunk is kept for reuse.
+    pub fn reset(&mut self) {
+        let chunks = self.chunks.get_mut();
+        chunks.truncate(1);
+
+        let (next, end) = chunks.first().map(|chunk| {
+            let start = chunk.as_ptr() as usize;
+            (start, start + chunk.len())
+        }).unwrap_or((0, 0));
+
+        self.next.set(next);
+        self.end.set(end);
+        self.allocated.set(0);
+    }
+
+    /// Allocates memory for a value, preceded by a pointer back to the arena.
+    fn alloc_value(&self, layout: Layout) -> NonNull<u16> {
+        // OffsetMut uses the least significant bit as a tag, so allocations must be at least
+        // 2-byte aligned.
+        let align = cmp::max(layout.align(), 2);
+
+        if layout.size() == 0 {
+            return unsafe { NonNull::new_unchecked(align as *mut u16) };
+        }
+
+        // The header ends up right before the value, and aligned, whatever the value's alignment.
+        let layout = Layout::from_size_align(layout.size(), align).unwrap();
+        let (with_header, offset) = Layout::new::<*const Arena>().extend(layout).unwrap();
+
+        let value = self.alloc_layout(with_header) + offset;
+        unsafe {
+            *((value - mem::size_of::<*const Arena>()) as *mut *const Arena) = self;
+        }
+
+        self.allocated.set(self.allocated.get() + layout.size());
+        unsafe { NonNull::new_unchecked(value as *mut u16) }
+    }
+
+    /// Returns the arena a dirty value was allocated from, or `None` if the value is zero-sized.
+    ///
+    /// # Safety
+    ///
+    /// `value` must have been allocated by `alloc_value()`.
+    unsafe fn owner<'a, T>(value: &T) -> Option<&'a Arena> {
+        if mem::size_of::<T>() == 0 {
+            None
+        } else {
+            let header = (value as *const T as usize) - mem::size_of::<*const Arena>();
+            Some(&**(header as *const *const Arena))
+        }
+    }
+
+    /// Bump allocates memory, returning its address.
+    fn alloc_layout(&self, layout: Layout) -> usize {
+        let align = layout.align();
+        let mut start = (self.next.get() + align - 1) & !(align - 1);
+        if self.next.get() == 0 || start + layout.size() > self.end.get() {
+            let chunk_len = cmp::max(self.chunk_size, layout.size() + align);
+            let chunk: Box<[MaybeUninit<u8>]> = (0 .. chunk_len).map(|_| MaybeUninit::uninit()).collect();
+
+            let chunk_start = chunk.as_ptr() as usize;
+            self.end.set(chunk_start + chunk.len());
+            self.chunks.borrow_mut().push(chunk);
+
+            start = (chunk_start + align - 1) & !(align - 1);
+        }
+
+        self.next.set(start + layout.size());
+        start
+    }
+}
+
+/// Mutable, unverified, pile whose dirty values are allocated from an `Arena`.
+#[derive(Debug, Clone, Copy)]
+pub struct TryPileArena<'a, 'p, 'v> {
+    pile: TryPileMut<'p, 'v>,
+    arena: &'a Arena,
+}
+
+impl<'p, 'v> TryPileMut<'p, 'v> {
+    /// Allocates dirty values from `arena` rather than the heap.
+    pub fn with_arena<'a>(self, arena: &'a Arena) -> TryPileArena<'a, 'p, 'v> {
+        TryPileArena { pile: self, arena }
+    }
+}
+
+impl<'a, 'p, 'v> TryPileArena<'a, 'p, 'v> {
+    pub fn arena(&self) -> &'a Arena {
+        self.arena
+    }
+
+    /// Like `TryPileMut::encode_dirty()`.
+    ///
+    /// Once the bytes have been written, dropping the dirty values lets the arena be reset.
+    pub fn encode_dirty<'b, T>(&self, value: &'b T) -> Vec<u8>
+        where T: Encode<'b, Self>
+    {
+        let mut dst = vec![];
+
+        let dumper = VecDumper::<Self>::new(*self, &mut dst);
+
+        let mut state = value.make_encode_state();
+        let dumper = value.encode_poll(&mut state, dumper).unwrap();
+
+        let (_dumper, _offset) = dumper.encode_value(value, &state).unwrap();
+        dst
+    }
+
+    /// Like `TryPileMut::save_dirty()`.
+    pub fn save_dirty<'b, T>(&self, value: &'b T, dst: &mut Vec<u8>) -> (T, Offset<'static, 'static>, SaveStats)
+        where T: Encode<'b, Self, Encoded = T>
+    {
+        match save_dirty_impl(value, VecDumper::<Self>::new(*self, dst)) {
+            Ok(r) => r,
+            Err(never) => never,
+        }
+    }
+}
+
+impl<'a, 'p, 'v> PileZone<'p, 'v> for TryPileArena<'a, 'p, 'v> {
+    #[inline(always)]
+    fn get_try_pile(&self) -> TryPile<'p, 'v> {
+        self.pile.get_try_pile()
+    }
+
+    #[inline(always)]
+    fn mapping(&self) -> &'p dyn Mapping {
+        self.pile.mapping()
+    }
+}
+
+impl<'a, 'p, 'v> PileZoneMut<'p, 'v> for TryPileArena<'a, 'p, 'v> {}
+
+impl<'a, 'p, 'v> Zone for TryPileArena<'a, 'p, 'v> {
+    type Ptr = OffsetMut<'p,'v>;
+    type Persist = TryPile<'static, 'static>;
+    type PersistPtr = Offset<'static, 'static>;
+
+    type Error = Error<'p,'v>;
+
+    #[inline(always)]
+    fn duplicate(&self) -> Self {
+        *self
+    }
+
+    fn clone_ptr<T: Clone>(ptr: &ValidPtr<T, Self>) -> OwnedPtr<T, Self> {
+        match Self::try_get_dirty(ptr) {
+            Ok(value) => unsafe {
+                // SAFETY: dirty values are allocated by alloc_value(), and the arena can't be
+                // moved or reset while the zone - and thus the pointer - borrows it.
+                let dst = match Arena::owner(value) {
+                    Some(arena) => arena.alloc_value(Layout::new::<T>()),
+                    None => NonNull::new_unchecked(cmp::max(mem::align_of::<T>(), 2) as *mut u16),
+                };
+                dst.cast::<T>().as_ptr().write(value.clone());
+
+                let fatptr = FatPtr {
+                    raw: OffsetMut::from_ptr(dst),
+                    metadata: (),
+                };
+                OwnedPtr::new_unchecked(ValidPtr::new_unchecked(fatptr))
+            },
+
+            // SAFETY: persisted values aren't owned by the pointer, so offsets can be duplicated.
+            Err(_) => unsafe { OwnedPtr::new_unchecked(ValidPtr::new_unchecked(**ptr)) },
+        }
+    }
+
+    fn try_get_dirty<T: ?Sized + Pointee>(ptr: &ValidPtr<T, Self>) -> Result<&T, FatPtr<T, Self::Persist>> {
+        match ptr.raw.kind() {
+            offsetmut::Kind::Ptr(nonnull) => unsafe {
+                Ok(&*T::make_fat_ptr(nonnull.cast().as_ptr(), ptr.metadata))
+            },
+            offsetmut::Kind::Offset(raw) => {
+                let raw = raw.cast();
+                Err(FatPtr { raw, metadata: ptr.metadata })
+            },
+        }
+    }
+
+    fn try_take_dirty_unsized<T: ?Sized + Pointee, R>(
+        owned: OwnedPtr<T, Self>,
+        f: impl FnOnce(Result<&mut ManuallyDrop<T>, FatPtr<T, Self::Persist>>) -> R,
+    ) -> R
+    {
+        let FatPtr { raw, metadata } = owned.into_inner().into_inner();
+
+        match raw.kind() {
+            // The memory belongs to the arena, so unlike OffsetMut there's nothing to deallocate.
+            offsetmut::Kind::Ptr(nonnull) => unsafe {
+                let v: &mut T = &mut *T::make_fat_ptr_mut(nonnull.cast().as_ptr(), metadata);
+                f(Ok(&mut *(v as *mut T as *mut ManuallyDrop<T>)))
+            },
+            offsetmut::Kind::Offset(offset) => f(Err(FatPtr { raw: offset.cast(), metadata })),
+        }
+    }
+}
+
+impl<'a, 'p, 'v> Alloc for TryPileArena<'a, 'p, 'v> {
+    fn alloc<T: ?Sized + Pointee>(&self, src: impl Take<T>) -> OwnedPtr<T, Self> {
+        src.take_unsized(|src| unsafe {
+            let metadata = T::metadata(src);
+            let layout = Layout::for_value::<T>(src);
+
+            let dst = self.arena.alloc_value(layout);
+            ptr::copy_nonoverlapping(src as *const _ as *const u8, dst.as_ptr() as *mut u8,
+                                     layout.size());
+
+            let fatptr = FatPtr {
+                raw: OffsetMut::from_ptr(dst),
+                metadata,
+            };
+            OwnedPtr::new_unchecked(ValidPtr::new_unchecked(fatptr))
+        })
+    }
+}
+
+impl<'a, 'p, 'v> TryGet for TryPileArena<'a, 'p, 'v> {
+    fn try_get<'b, T>(&self, ptr: &'b ValidPtr<T, Self>) -> Result<Ref<'b, T, Self>, Self::Error>
+        where T: ?Sized + PersistPointee
+    {
+        match Self::try_get_dirty(ptr) {
+            Ok(r) => Ok(Ref {
+                this: r,
+                zone: *self,
+            }),
+            Err(ptr) => {
+                let r_persist = try_get_impl(self, &ptr)?;
+                Ok(Ref {
+                    this: unsafe { T::assume_valid_ref(r_persist) },
+                    zone: *self,
+                })
+            },
+        }
+    }
+
+    fn try_take<T: ?Sized + Load<Self>>(&self, ptr: OwnedPtr<T, Self>)
+        -> Result<Own<T::Owned, Self>, Self::Error>
+    {
+        Self::try_take_dirty_unsized(ptr, |result| {
+            match result {
+                Ok(dirty) => {
+                    Ok(Own {
+                        this: unsafe { T::into_owned_unchecked(dirty) },
+                        zone: *self,
+                    })
+                },
+                Err(ptr) => {
+                    let r_persist = try_get_impl(self, &ptr)?;
+                    Ok(Own {
+                        this: unsafe { T::assume_valid(r_persist) },
+                        zone: *self,
+                    })
+                },
+            }
+        })
+    }
+}
+
+impl<'a, 'p, 'v> TryGetMut for TryPileArena<'a, 'p, 'v> {
+    fn try_get_mut<'b, T: ?Sized + Load<Self>>(&self, ptr: &'b mut ValidPtr<T, Self>)
+        -> Result<RefMut<'b, T, Self>, Self::Error>
+    {
+        try_get_mut_impl(self, ptr)
+    }
+}
+
+impl<'a, 'p, 'v> SavePtr<Self> for TryPileArena<'a, 'p, 'v> {
+    fn try_save_ptr<'b, T: ?Sized + Pointee, D>(ptr: &'b ValidPtr<T, Self>, dumper: &D)
+        -> Result<Offset<'static, 'static>, &'b T>
+        where D: Dumper<Self>
+    {
+        dumper.try_save_ptr(ptr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    #[test]
+    fn arena_alloc() {
+        let mut arena = Arena::with_chunk_size(16);
+
+        {
+            let pile = TryPileMut::default().with_arena(&arena);
+
+            let x = [[pile.alloc(1u8), pile.alloc(2u8), pile.alloc(3u8)],
+                     [pile.alloc(4u8), pile.alloc(5u8), pile.alloc(6u8)]];
+            let x = pile.alloc(x);
+
+            assert_eq!(pile.encode_dirty(&x),
+                       TryPileMut::default().encode_dirty(&{
+                           let pile = TryPileMut::default();
+                           pile.alloc([[pile.alloc(1u8), pile.alloc(2u8), pile.alloc(3u8)],
+                                       [pile.alloc(4u8), pile.alloc(5u8), pile.alloc(6u8)]])
+                       }));
+
+            // A value bigger than the chunk size gets a chunk of its own.
+            let big = pile.alloc([42u8; 100]);
+            assert_eq!(&pile.try_get(&big).unwrap()[..], &[42u8; 100][..]);
+
+            for ptr in pile.try_get(&x).unwrap().iter().flatten() {
+                assert_eq!(ptr.raw.get_ptr().unwrap().as_ptr() as usize & 1, 0);
+            }
+        }
+        assert_eq!(arena.allocated(), 6 + 6 * 8 + 100);
+
+        arena.reset();
+        assert_eq!(arena.allocated(), 0);
+        assert_eq!(arena.chunks.borrow().len(), 1);
+    }
+
+    #[test]
+    fn arena_save_dirty() {
+        let arena = Arena::new();
+        let pile = TryPileMut::default().with_arena(&arena);
+        let x = [pile.alloc(1u8), pile.alloc(2u8)];
+
+        let mut buf = vec![];
+        let (x, offset, stats) = pile.save_dirty(&x, &mut buf);
+        assert_eq!(offset.get(), 2);
+        assert_eq!(stats, SaveStats { blobs_written: 3, bytes_written: 18, clean_reused: 0 });
+        assert!(x.iter().all(|ptr| !ptr.is_dirty()));
+        assert_eq!(buf, &[1, 2,
+                          1, 0, 0, 0, 0, 0, 0, 0,
+                          3, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Saving again only writes x itself.
+        let (_, offset, stats) = pile.save_dirty(&x, &mut buf);
+        assert_eq!(offset.get(), 18);
+        assert_eq!(stats, SaveStats { blobs_written: 1, bytes_written: 16, clean_reused: 2 });
+        assert_eq!(&buf[18 ..], &buf[2 .. 18]);
+    }
+
+    #[test]
+    fn arena_drops_values() {
+        let arena = Arena::new();
+        let pile = TryPileMut::default().with_arena(&arena);
+
+        let rc = Rc::new(());
+        let ptr = pile.alloc(Rc::clone(&rc));
+        assert_eq!(Rc::strong_count(&rc), 2);
+
+        drop(ptr);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn arena_clone_ptr() {
+        let arena = Arena::new();
+        let pile = TryPileMut::default().with_arena(&arena);
+
+        let rc = Rc::new(());
+        let x = pile.alloc(Rc::clone(&rc));
+        let y = x.clone();
+        assert_ne!(x.raw, y.raw);
+        assert_eq!(Rc::strong_count(&rc), 3);
+        assert_eq!(arena.allocated(), 2 * mem::size_of::<Rc<()>>());
+
+        let big = pile.alloc([7u64; 8]);
+        assert_eq!(TryPileArena::try_get_dirty(&big.clone()).unwrap(), &[7u64; 8]);
+
+        let unit = pile.alloc(());
+        let _ = unit.clone();
+
+        drop((x, y));
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn arena_copy_on_write() {
+        let arena = Arena::new();
+        let pile = TryPileMut::default();
+
+        let buf = pile.encode_dirty(&pile.alloc(42u8));
+        TryPile::new(&buf, |pile| {
+            let pile = TryPileMut::from(pile).with_arena(&arena);
+
+            let mut ptr = pile.alloc(0u8);
+            *pile.try_get_mut(&mut ptr).unwrap().this = 1;
+            assert_eq!(arena.allocated(), 1);
+
+            let mut clean: OwnedPtr<u8, TryPileArena> = unsafe {
+                OwnedPtr::new_unchecked(ValidPtr::new_unchecked(FatPtr {
+                    raw: Offset::new(0).unwrap().into(),
+                    metadata: (),
+                }))
+            };
+            *pile.try_get_mut(&mut clean).unwrap().this += 1;
+            assert_eq!(**pile.try_get(&clean).unwrap(), 43);
+            assert_eq!(arena.allocated(), 2);
+        })
+    }
+}
@@ -0,0 +1,553 @@
+//! Compact piles, with 32-bit offsets.
+//!
+//! `TryPile32` and `TryPileMut32` work exactly like `TryPile` and `TryPileMut`, except that
+//! pointers are persisted as 32-bit `Offset32`'s, halving the size of every pointer. The price is
+//! that a compact pile can be at most `Offset32::MAX` bytes long; `VecDumper32` returns an
+//! `Offset32RangeError` rather than write anything past that.
+//!
+//! `TryPileMut32` keeps its dirty values in the table named by its `D` parameter; see
+//! `offset32::Dirty`.
+
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop};
+
+use owned::Take;
+use thiserror::Error;
+
+use crate::coerce::{Coerce, TryCoerce};
+use crate::pointee::Pointee;
+use crate::zone::{*, refs::*};
+use crate::marshal::decode::*;
+use crate::marshal::encode::*;
+use crate::marshal::load::*;
+use crate::marshal::save::*;
+use crate::marshal::counting::SaveStats;
+use crate::marshal::*;
+
+use super::{TryPile, PileZone, Mapping, try_get_impl, save_dirty_impl, SavedBytes};
+use super::error::Error;
+
+pub mod offset32;
+use self::offset32::{Offset32, OffsetMut32, Kind, Dirty};
+
+/// Fallible, unverified, compact pile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TryPile32<'p, 'v>(TryPile<'p, 'v>);
+
+/// Mutable, unverified, compact pile, with dirty values kept in the table `D`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TryPileMut32<'p, 'v, D>(TryPile32<'p, 'v>, PhantomData<D>);
+
+impl<'p, 'v> From<TryPile<'p, 'v>> for TryPile32<'p,'v> {
+    #[inline(always)]
+    fn from(trypile: TryPile<'p,'v>) -> Self {
+        Self(trypile)
+    }
+}
+
+impl<'p, 'v, D> From<TryPile32<'p, 'v>> for TryPileMut32<'p,'v,D> {
+    #[inline(always)]
+    fn from(trypile: TryPile32<'p,'v>) -> Self {
+        Self(trypile, PhantomData)
+    }
+}
+
+impl<'p, D> Default for TryPileMut32<'p, 'static, D> {
+    fn default() -> Self {
+        TryPileMut32(TryPile32(TryPile::empty()), PhantomData)
+    }
+}
+
+impl<'p, 'v> PileZone<'p, 'v> for TryPile32<'p, 'v> {
+    #[inline(always)]
+    fn get_try_pile(&self) -> TryPile<'p, 'v> {
+        self.0
+    }
+
+    #[inline(always)]
+    fn mapping(&self) -> &'p dyn Mapping {
+        self.0.mapping
+    }
+}
+
+impl<'p, 'v, D: Dirty> PileZone<'p, 'v> for TryPileMut32<'p, 'v, D> {
+    #[inline(always)]
+    fn get_try_pile(&self) -> TryPile<'p, 'v> {
+        (self.0).0
+    }
+
+    #[inline(always)]
+    fn mapping(&self) -> &'p dyn Mapping {
+        (self.0).0.mapping
+    }
+}
+
+impl TryPile32<'_, '_> {
+    /// Creates a new `TryPile32` from a slice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use hoard::pile::compact::TryPile32;
+    /// # use leint::Le;
+    /// TryPile32::new([0x12, 0x34, 0x56, 0x78], |pile| {
+    ///     let tip = pile.try_get_tip::<Le<u32>>().unwrap();
+    ///     assert_eq!(**tip, 0x78563412);
+    /// })
+    /// ```
+    #[inline]
+    pub fn new<R>(slice: impl AsRef<[u8]>, f: impl FnOnce(TryPile32) -> R) -> R {
+        TryPile::new(slice, |pile| f(TryPile32(pile)))
+    }
+}
+
+impl<'p,'v> TryPile32<'p, 'v> {
+    /// Tries to get the tip of a `TryPile32`.
+    pub fn try_get_tip<T: Decode<Self>>(&self) -> Result<Ref<'p, T, Self>, Error<'p,'v>> {
+        let offset = self.len().saturating_sub(mem::size_of::<T>());
+
+        // An out of range offset is clamped, and then caught by the bounds check.
+        let ptr = FatPtr::<T,_> {
+            raw: Offset32::new(offset).unwrap_or(Offset32::new(Offset32::MAX).unwrap()),
+            metadata: ()
+        };
+        let r = try_get_impl(self, &ptr)?;
+        Ok(Ref {
+            this: unsafe { T::assume_valid_ref(r) },
+            zone: *self,
+        })
+    }
+}
+
+impl<'p,'v> Zone for TryPile32<'p,'v> {
+    type Ptr = Offset32<'p,'v>;
+    type Persist = TryPile32<'static, 'static>;
+    type PersistPtr = Offset32<'static, 'static>;
+
+    type Error = Error<'p,'v>;
+
+    #[inline(always)]
+    fn duplicate(&self) -> Self {
+        *self
+    }
+
+    fn clone_ptr<T>(ptr: &ValidPtr<T, Self>) -> OwnedPtr<T, Self> {
+        unsafe { OwnedPtr::new_unchecked(ValidPtr::new_unchecked(**ptr)) }
+    }
+
+    fn try_get_dirty<T: ?Sized + Pointee>(ptr: &ValidPtr<T, Self>) -> Result<&T, FatPtr<T, Self::Persist>> {
+        Err(FatPtr {
+            raw: ptr.raw.cast(),
+            metadata: ptr.metadata,
+        })
+    }
+
+    fn try_take_dirty_unsized<T: ?Sized + Pointee, R>(
+        owned: OwnedPtr<T, Self>,
+        f: impl FnOnce(Result<&mut ManuallyDrop<T>, FatPtr<T, Self::Persist>>) -> R,
+    ) -> R
+    {
+        let fat = owned.into_inner().into_inner();
+        f(Err(FatPtr {
+            raw: fat.raw.cast(),
+            metadata: fat.metadata,
+        }))
+    }
+}
+
+impl<'p, 'v> TryGet for TryPile32<'p, 'v> {
+    fn try_get<'a, T>(&self, ptr: &'a ValidPtr<T, Self>) -> Result<Ref<'a, T, Self>, Self::Error>
+        where T: ?Sized + PersistPointee
+    {
+        let ptr: FatPtr<T, Self> = **ptr;
+        let ptr: FatPtr<T, TryPile32<'static, 'static>> = ptr.coerce();
+        let r_persist = try_get_impl(self, &ptr)?;
+        Ok(Ref {
+            this: unsafe { T::assume_valid_ref(r_persist) },
+            zone: *self,
+        })
+    }
+
+    fn try_take<T: ?Sized + Load<Self>>(&self, ptr: OwnedPtr<T, Self>)
+        -> Result<Own<T::Owned, Self>, Self::Error>
+    {
+        let ptr: FatPtr<T, Self> = **ptr;
+        let ptr: FatPtr<T, TryPile32<'static, 'static>> = ptr.coerce();
+        let r_persist = try_get_impl(self, &ptr)?;
+
+        Ok(Own {
+            this: unsafe { T::assume_valid(r_persist) },
+            zone: *self,
+        })
+    }
+}
+
+impl<'p,'v, D: Dirty> Zone for TryPileMut32<'p,'v,D> {
+    type Ptr = OffsetMut32<'p,'v,D>;
+    type Persist = TryPile32<'static, 'static>;
+    type PersistPtr = Offset32<'static, 'static>;
+
+    type Error = Error<'p,'v>;
+
+    fn alloc<T: ?Sized + Pointee>(src: impl Take<T>) -> OwnedPtr<T, Self> {
+        OffsetMut32::alloc(src)
+    }
+
+    #[inline(always)]
+    fn duplicate(&self) -> Self {
+        *self
+    }
+
+    fn clone_ptr<T: Clone>(ptr: &ValidPtr<T, Self>) -> OwnedPtr<T, Self> {
+        match Self::try_get_dirty(ptr) {
+            Ok(value) => OffsetMut32::alloc(value.clone()),
+
+            // SAFETY: persisted values aren't owned by the pointer, so offsets can be duplicated.
+            Err(_) => unsafe { OwnedPtr::new_unchecked(ValidPtr::new_unchecked(**ptr)) },
+        }
+    }
+
+    fn try_get_dirty<T: ?Sized + Pointee>(ptr: &ValidPtr<T, Self>) -> Result<&T, FatPtr<T, Self::Persist>> {
+        match ptr.raw.kind() {
+            Kind::Dirty(_) => unsafe {
+                let nonnull = ptr.raw.get_ptr().unwrap();
+                Ok(&*T::make_fat_ptr(nonnull.cast().as_ptr(), ptr.metadata))
+            },
+            Kind::Offset(raw) => {
+                let raw = raw.cast();
+                Err(FatPtr { raw, metadata: ptr.metadata })
+            },
+        }
+    }
+
+    fn try_take_dirty_unsized<T: ?Sized + Pointee, R>(
+        owned: OwnedPtr<T, Self>,
+        f: impl FnOnce(Result<&mut ManuallyDrop<T>, FatPtr<T, Self::Persist>>) -> R,
+    ) -> R
+    {
+        let metadata = owned.metadata;
+        OffsetMut32::try_take_dirty_unsized(owned, |r|
+            f(match r {
+                Ok(t_ref) => Ok(t_ref),
+                Err(offset) => Err(FatPtr { raw: offset.cast(), metadata }),
+            })
+        )
+    }
+}
+
+impl<'p,'v, D: Dirty> Alloc for TryPileMut32<'p,'v,D> {
+    fn alloc<T: ?Sized + Pointee>(&self, src: impl Take<T>) -> OwnedPtr<T, Self> {
+        OffsetMut32::alloc(src)
+    }
+}
+
+impl<'p, 'v, D: Dirty> TryGet for TryPileMut32<'p, 'v, D> {
+    fn try_get<'a, T>(&self, ptr: &'a ValidPtr<T, Self>) -> Result<Ref<'a, T, Self>, Self::Error>
+        where T: ?Sized + PersistPointee
+    {
+        match Self::try_get_dirty(ptr) {
+            Ok(r) => Ok(Ref {
+                this: r,
+                zone: *self,
+            }),
+            Err(ptr) => {
+                let r_persist = try_get_impl(self, &ptr)?;
+                Ok(Ref {
+                    this: unsafe { T::assume_valid_ref(r_persist) },
+                    zone: *self,
+                })
+            },
+        }
+    }
+
+    fn try_take<T: ?Sized + Load<Self>>(&self, ptr: OwnedPtr<T, Self>)
+        -> Result<Own<T::Owned, Self>, Self::Error>
+    {
+        let metadata: T::Metadata = ptr.metadata;
+        OffsetMut32::try_take_dirty_unsized(ptr, |result| {
+            match result {
+                Ok(dirty) => {
+                    Ok(Own {
+                        this: unsafe { T::into_owned_unchecked(dirty) },
+                        zone: *self,
+                    })
+                },
+                Err(offset) => {
+                    let ptr = FatPtr::<T, TryPile32> {
+                        raw: offset.coerce(),
+                        metadata,
+                    };
+                    let r_persist = try_get_impl(self, &ptr)?;
+                    Ok(Own {
+                        this: unsafe { T::assume_valid(r_persist) },
+                        zone: *self,
+                    })
+                },
+            }
+        })
+    }
+}
+
+impl<'p, 'v, D: Dirty> TryGetMut for TryPileMut32<'p, 'v, D> {
+    fn try_get_mut<'a, T: ?Sized + Load<Self>>(&self, ptr: &'a mut ValidPtr<T, Self>)
+        -> Result<RefMut<'a, T, Self>, Self::Error>
+    {
+        if let Ok(persist_ptr) = TryCoerce::<FatPtr<T, TryPile32>>::try_coerce(**ptr) {
+            let r = try_get_impl(self, &persist_ptr)?;
+            let owned = unsafe { T::assume_valid(r) };
+
+            let new_ptr: FatPtr<T, Self> = self.alloc(owned).into_inner().into_inner();
+
+            // SAFETY: new_ptr is freshly allocated, so it's valid as long as the metadata is
+            // unchanged.
+            assert_eq!(ptr.metadata, new_ptr.metadata);
+            let old_raw = unsafe { mem::replace(ptr.raw_mut(), new_ptr.raw) };
+
+            // Make sure we're not leaking memory.
+            assert!(old_raw.get_offset().is_some());
+        }
+
+        let nonnull = ptr.raw.get_ptr().expect("pointer should be dirty");
+        Ok(RefMut {
+            // SAFETY: We do in fact have mutable access here.
+            this: unsafe { &mut *T::make_fat_ptr_mut(nonnull.cast().as_ptr(), ptr.metadata) },
+            zone: *self,
+        })
+    }
+}
+
+impl<'p,'v, S: Dirty> SavePtr<Self> for TryPileMut32<'p, 'v, S> {
+    fn try_save_ptr<'a, T: ?Sized + Pointee, D>(ptr: &'a ValidPtr<T, Self>, dumper: &D)
+        -> Result<Offset32<'static, 'static>, &'a T>
+        where D: Dumper<Self>
+    {
+        dumper.try_save_ptr(ptr)
+    }
+}
+
+/// Returned when a compact pile would grow past `Offset32::MAX`.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("compact pile would grow to {end} bytes, past the {} byte limit", Offset32::MAX)]
+pub struct Offset32RangeError {
+    pub end: usize,
+}
+
+/// `Dumper` for compact piles, appending blobs to a `Vec`.
+#[derive(Debug)]
+pub struct VecDumper32<'a, 'p, 'v, Z> {
+    marker: PhantomData<TryPile<'p, 'v>>,
+    pile: Z,
+    buf: &'a mut Vec<u8>,
+    offset: usize,
+}
+
+impl<'a,'p,'v,Z> VecDumper32<'a, 'p, 'v, Z>
+where Z: PileZone<'p, 'v>
+{
+    pub fn new(pile: Z, buf: &'a mut Vec<u8>) -> Self {
+        Self {
+            marker: PhantomData,
+            offset: pile.len() + buf.len(),
+            pile, buf,
+        }
+    }
+}
+
+impl<Z> SavedBytes for VecDumper32<'_, '_, '_, Z> {
+    fn saved_bytes(&self) -> &[u8] {
+        self.buf
+    }
+}
+
+impl<'a,'p,'v, Z> Dumper<Z> for VecDumper32<'a,'p,'v, Z>
+where Z: PileZone<'p, 'v> + Zone<PersistPtr = Offset32<'static, 'static>>
+{
+    type Error = Offset32RangeError;
+    type BlobPtr = Offset32<'static, 'static>;
+
+    type WriteBlob = Vec<u8>;
+    type WriteBlobOk = Vec<u8>;
+    type WriteBlobError = !;
+
+    fn try_save_ptr<'ptr, T: ?Sized + Pointee>(
+        &self,
+        ptr: &'ptr ValidPtr<T, Z>
+    ) -> Result<Offset32<'static, 'static>, &'ptr T>
+    {
+        match Z::try_get_dirty(ptr) {
+            Ok(r) => Err(r),
+            Err(ptr) => Ok(ptr.raw),
+        }
+    }
+
+    fn save_blob(
+        self,
+        size: usize,
+        f: impl FnOnce(Self::WriteBlob) -> Result<Self::WriteBlobOk, Self::WriteBlobError>
+    ) -> Result<(Self, Offset32<'static, 'static>), Offset32RangeError>
+    {
+        let end = self.offset + size;
+        if end > Offset32::MAX {
+            return Err(Offset32RangeError { end });
+        }
+
+        // Vec<u8> appends, so the blob can be written in place.
+        let start = self.buf.len();
+        *self.buf = match f(mem::take(self.buf)) {
+            Ok(buf) => buf,
+            Err(never) => never,
+        };
+        assert_eq!(self.buf.len() - start, size, "wrong blob size");
+
+        let offset = Offset32::new(self.offset).unwrap();
+        Ok((Self { offset: end, ..self }, offset))
+    }
+
+    #[inline(always)]
+    fn blob_ptr_to_zone_ptr(ptr: Self::BlobPtr) -> Z::PersistPtr {
+        ptr
+    }
+}
+
+impl<'p, 'v, D: Dirty> TryPileMut32<'p,'v,D> {
+    /// Encodes the dirty parts of `value`, returning the bytes to append to the pile.
+    pub fn encode_dirty<'a, T>(&self, value: &'a T) -> Result<Vec<u8>, Offset32RangeError>
+        where T: Encode<'a, Self>
+    {
+        let mut dst = vec![];
+
+        let dumper = VecDumper32::new(*self, &mut dst);
+
+        let mut state = value.make_encode_state();
+        let dumper = value.encode_poll(&mut state, dumper)?;
+
+        let (_dumper, _offset) = dumper.encode_value(value, &state)?;
+        Ok(dst)
+    }
+
+    /// Like `TryPileMut::save_dirty()`.
+    ///
+    /// On error, `dst` is left unchanged.
+    pub fn save_dirty<'a, T>(&self, value: &'a T, dst: &mut Vec<u8>)
+        -> Result<(T, Offset32<'static, 'static>, SaveStats), Offset32RangeError>
+        where T: Encode<'a, Self, Encoded = T>
+    {
+        let orig_len = dst.len();
+        match save_dirty_impl(value, VecDumper32::new(*self, dst)) {
+            Ok(r) => Ok(r),
+            Err(err) => {
+                dst.truncate(orig_len);
+                Err(err)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::dirty_table!(Table);
+
+    #[test]
+    fn trypilemut32_alloc() {
+        let pile = TryPileMut32::<Table>::default();
+        let x = [pile.alloc(1u8), pile.alloc(2u8), pile.alloc(3u8)];
+        assert_eq!(pile.encode_dirty(&x).unwrap(),
+                   &[1, 2, 3,
+                     1, 0, 0, 0,
+                     3, 0, 0, 0,
+                     5, 0, 0, 0,
+                    ]);
+
+        let x = pile.alloc(x);
+        assert_eq!(pile.encode_dirty(&x).unwrap(),
+                   &[1, 2, 3,
+                     1, 0, 0, 0,
+                     3, 0, 0, 0,
+                     5, 0, 0, 0,
+                     7, 0, 0, 0,
+                    ]);
+    }
+
+    #[test]
+    fn trypilemut32_roundtrip() {
+        let pile = TryPileMut32::<Table>::default();
+        let x = [pile.alloc(1u8), pile.alloc(2u8), pile.alloc(3u8)];
+        let buf = pile.encode_dirty(&x).unwrap();
+
+        TryPile32::new(&buf, |pile| {
+            let tip = pile.try_get_tip::<[OwnedPtr<u8, TryPile32>; 3]>().unwrap();
+
+            for (ptr, expected) in tip.iter().zip(1u8 ..) {
+                assert_eq!(**pile.try_get(ptr).unwrap(), expected);
+            }
+
+            // Append a new root that mutates one value, and shares the rest.
+            let pile = TryPileMut32::<Table>::from(pile);
+            let share = |ptr: &OwnedPtr<u8, TryPile32>| -> OwnedPtr<u8, TryPileMut32<Table>> {
+                unsafe { OwnedPtr::new_unchecked(ValidPtr::new_unchecked((***ptr).coerce())) }
+            };
+            let mut root = [share(&tip[0]), share(&tip[1]), share(&tip[2])];
+            *pile.try_get_mut(&mut root[1]).unwrap().this = 42;
+            assert_eq!(**pile.try_get(&root[1]).unwrap(), 42);
+
+            let new_bytes = pile.encode_dirty(&root).unwrap();
+            assert_eq!(new_bytes,
+                       &[42,
+                          1, 0, 0, 0,
+                         31, 0, 0, 0,
+                          5, 0, 0, 0,
+                        ]);
+        })
+    }
+
+    #[test]
+    fn trypilemut32_save_dirty() {
+        let pile = TryPileMut32::<Table>::default();
+        let x = [pile.alloc(1u8), pile.alloc(2u8), pile.alloc(3u8)];
+        let encoded = pile.encode_dirty(&x).unwrap();
+
+        let mut buf = vec![];
+        let (x, offset, stats) = pile.save_dirty(&x, &mut buf).unwrap();
+        assert_eq!(offset.get(), 3);
+        assert_eq!(stats, SaveStats { blobs_written: 4, bytes_written: 15, clean_reused: 0 });
+        assert_eq!(buf, encoded);
+        assert!(x.iter().all(|ptr| !ptr.is_dirty()));
+
+        // Saving again only writes x itself.
+        let (_, offset, stats) = pile.save_dirty(&x, &mut buf).unwrap();
+        assert_eq!(offset.get(), 15);
+        assert_eq!(stats, SaveStats { blobs_written: 1, bytes_written: 12, clean_reused: 3 });
+        assert_eq!(&buf[15 ..], &encoded[3 ..]);
+    }
+
+    #[test]
+    fn trypilemut32_clone_ptr() {
+        let pile = TryPileMut32::<Table>::default();
+        let x = pile.alloc(42u8);
+        let y = x.clone();
+        assert_ne!(x.raw, y.raw);
+        drop(x);
+        assert_eq!(**pile.try_get(&y).unwrap(), 42);
+    }
+
+    #[test]
+    fn vecdumper32_range_error() {
+        let pile = TryPileMut32::<Table>::default();
+        let x = pile.alloc(42u8);
+
+        let mut buf = vec![];
+        let dumper = VecDumper32 {
+            marker: PhantomData,
+            pile,
+            buf: &mut buf,
+            offset: Offset32::MAX,
+        };
+
+        let mut state = x.make_encode_state();
+        let err = x.encode_poll(&mut state, dumper).unwrap_err();
+        assert_eq!(err, Offset32RangeError { end: Offset32::MAX + 1 });
+        assert!(buf.is_empty());
+    }
+}
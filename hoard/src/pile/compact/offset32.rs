@@ -0,0 +1,533 @@
+//! 32-bit pile offsets.
+//!
+//! `Offset32` is the compact counterpart of `Offset`: a little-endian `u32`, with the
+//! least-significant bit always set. That leaves 31 bits for the offset itself, so compact piles
+//! are limited to 2GiB.
+//!
+//! `OffsetMut32` extends `Offset32` with copy-on-write semantics. A 64-bit heap pointer doesn't fit
+//! in 32 bits, so unlike `OffsetMut` a dirty `OffsetMut32` doesn't point to heap memory directly.
+//! Instead it's an index into a `DirtyTable` of allocations, again using the least-significant bit
+//! to tell the two apart.
+//!
+//! Dirty values are looked up from the pointer alone, so the table can't be a field of the zone.
+//! Instead every `OffsetMut32` names its table with a type implementing `Dirty`, declared with
+//! `dirty_table!`; piles with different tables share nothing.
+//!
+//! This is a real limitation, not a choice: the table is process-wide, per `Dirty` type, and every
+//! access goes through its `Mutex`. Neither obvious alternative works in this crate:
+//!
+//! * Storing the table in the `TryPileMut32` instance: `Zone` gets at dirty values without a zone
+//!   instance. `OwnedPtr`'s `Drop` calls `Zone::try_take_dirty_unsized()`, and dumpers and
+//!   `ValidPtr::is_dirty()` call `Zone::try_get_dirty()`. None of them has a pile to find the
+//!   table through.
+//!
+//! * Encoding a real pointer, like `OffsetMut` does: loading coerces an `Offset32` blob to an
+//!   `OffsetMut32` in place, and `save_dirty()` reads its own encoding back as the value. Both need
+//!   `OffsetMut32` to be exactly 32 bits, which can't hold a heap pointer.
+//!
+//! Lifting the limitation needs zone-aware versions of those `Zone` methods.
+
+use std::alloc::Layout;
+use std::convert::TryInto;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::num::NonZeroU32;
+use std::ptr::NonNull;
+use std::hash::Hash;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use thiserror::Error;
+use leint::Le;
+use nonzero::NonZero;
+
+use owned::Take;
+
+use crate::coerce::TryCoerce;
+use crate::marshal::*;
+use crate::marshal::blob::*;
+use crate::marshal::decode::*;
+use crate::marshal::encode::*;
+use crate::pointee::Pointee;
+use crate::zone::*;
+
+use super::super::Pile;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, TryCoerce, NonZero)]
+#[repr(transparent)]
+#[try_coerce(for<'p, 'v> Offset32<'p, 'v>)]
+pub struct Offset32<'pile, 'version> {
+    marker: PhantomData<(
+        fn(&Pile<'pile, 'version>) -> &'pile (),
+        &'version (),
+    )>,
+    #[nonzero]
+    raw: Le<NonZeroU32>,
+}
+
+impl fmt::Debug for Offset32<'_,'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        assert!(self.raw.get().get() & 1 == 1);
+        <usize as fmt::Debug>::fmt(&self.get(), f)
+    }
+}
+
+impl<'p,'v> Offset32<'p,'v> {
+    pub const MAX: usize = (1 << 31) - 1;
+
+    /// Creates a new `Offset32`, returning `None` if the offset is out of range.
+    #[inline(always)]
+    pub fn new(offset: usize) -> Option<Self> {
+        if offset <= Self::MAX {
+            Some(Self {
+                marker: PhantomData,
+                raw: NonZeroU32::new(((offset as u32) << 1) | 1).unwrap().into(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Converts the `Offset32` into an offset with a different lifetime.
+    #[inline(always)]
+    pub fn cast<'p2,'v2>(&self) -> Offset32<'p2, 'v2> {
+        Offset32 {
+            marker: PhantomData,
+            raw: self.raw,
+        }
+    }
+
+    #[inline(always)]
+    pub fn get(&self) -> usize {
+        (self.raw.get().get() >> 1) as usize
+    }
+}
+
+impl From<Offset32<'_, '_>> for usize {
+    #[inline(always)]
+    fn from(offset: Offset32<'_,'_>) -> usize {
+        offset.get()
+    }
+}
+
+impl fmt::Pointer for Offset32<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:x}", self.get())
+    }
+}
+
+#[derive(Error,Debug, PartialEq, Eq)]
+#[error("invalid Offset32: {0}")]
+pub struct ValidateOffset32Error(u32);
+
+impl ValidateBlob for Offset32<'static, 'static> {
+    type Error = ValidateOffset32Error;
+
+    #[inline(always)]
+    fn validate<'a, V: PaddingValidator>(blob: BlobCursor<'a, Self, V>)
+        -> Result<ValidBlob<'a, Self>, BlobError<Self::Error, V::Error>>
+    {
+        blob.validate_bytes(|blob| {
+            let raw = u32::from_le_bytes(blob[..].try_into().unwrap());
+
+            if raw & 1 == 0 {
+                Err(ValidateOffset32Error(raw))
+            } else {
+                unsafe { Ok(blob.assume_valid()) }
+            }
+        })
+    }
+}
+
+unsafe impl Persist for Offset32<'_, '_> {
+    type Persist = Offset32<'static, 'static>;
+    type Error = ValidateOffset32Error;
+}
+
+unsafe impl<'a, Z> ValidateChildren<'a, Z> for Offset32<'_, '_> {
+    type State = ();
+
+    #[inline(always)]
+    fn validate_children(_: &Offset32<'static, 'static>) -> () {}
+
+    #[inline(always)]
+    fn poll<V: PtrValidator<Z>>(_: &Self::Persist, _: &mut (), _: &V) -> Result<(), V::Error> {
+        Ok(())
+    }
+}
+impl<Z> Decode<Z> for Offset32<'_,'_> {}
+
+impl<'p,'v, Z> Encoded<Z> for Offset32<'p, 'v> {
+    type Encoded = Self;
+}
+
+impl<Z> Encode<'_, Z> for Offset32<'_, '_> {
+    type State = ();
+
+    #[inline(always)]
+    fn make_encode_state(&self) -> () {}
+
+    #[inline(always)]
+    fn encode_poll<D: Dumper<Z>>(&self, _: &mut (), dumper: D) -> Result<D, D::Error> {
+        Ok(dumper)
+    }
+
+    #[inline(always)]
+    fn encode_blob<W: WriteBlob>(&self, _: &(), dst: W) -> Result<W::Ok, W::Error> {
+        dst.write_primitive(&self.raw)?
+           .finish()
+    }
+}
+impl Primitive for Offset32<'_, '_> {}
+
+/// Copy-on-write 32-bit pile offset, with dirty values kept in the table `D`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, TryCoerce)]
+#[repr(transparent)]
+#[try_coerce(from for<'p2, 'v2> Offset32<'p2, 'v2>)]
+pub struct OffsetMut32<'p,'v, D>(Offset32<'p,'v>, PhantomData<D>);
+
+unsafe impl<D> NonZero for OffsetMut32<'_, '_, D> {}
+
+unsafe impl<'p, 'v, D: Dirty> TryCoerce<OffsetMut32<'p, 'v, D>> for OffsetMut32<'_, '_, D> {
+    type Error = !;
+}
+
+unsafe impl<'p, 'v, D: Dirty> TryCoerce<Offset32<'p, 'v>> for OffsetMut32<'_, '_, D> {
+    type Error = TryCoerceOffsetMut32Error;
+
+    #[inline(always)]
+    fn try_coerce_ptr(this: &Self) -> Result<*const Offset32<'p,'v>, Self::Error> {
+        match this.kind() {
+            Kind::Offset(_) => Ok(this as *const _ as *const _),
+            Kind::Dirty(slot) => Err(TryCoerceOffsetMut32Error { slot }),
+        }
+    }
+}
+
+/// Returned if an `OffsetMut32` can't be coerced to an `Offset32` due to being dirty.
+#[derive(Error, Debug, PartialEq, Eq, Hash)]
+#[error("OffsetMut32 is dirty: slot {slot}")]
+pub struct TryCoerceOffsetMut32Error {
+    pub slot: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Kind<'p,'v> {
+    Offset(Offset32<'p,'v>),
+    Dirty(u32),
+}
+
+impl<'p, 'v, D> From<Offset32<'p,'v>> for OffsetMut32<'p,'v,D> {
+    #[inline]
+    fn from(offset: Offset32<'p,'v>) -> Self {
+        Self(offset, PhantomData)
+    }
+}
+
+unsafe impl<D: Dirty> Persist for OffsetMut32<'_, '_, D> {
+    type Persist = Offset32<'static, 'static>;
+    type Error = ValidateOffset32Error;
+}
+
+unsafe impl<'a, Z, D: Dirty> ValidateChildren<'a, Z> for OffsetMut32<'_, '_, D> {
+    type State = ();
+
+    #[inline(always)]
+    fn validate_children(_: &Offset32<'static, 'static>) -> () {}
+
+    #[inline(always)]
+    fn poll<V: PtrValidator<Z>>(_: &Self::Persist, _: &mut (), _: &V) -> Result<(), V::Error> {
+        Ok(())
+    }
+}
+impl<Z, D: Dirty> Decode<Z> for OffsetMut32<'_,'_,D> {}
+
+impl<'p,'v, D: Dirty> OffsetMut32<'p,'v,D> {
+    pub fn alloc<T: ?Sized + Pointee, Z>(src: impl Take<T>) -> OwnedPtr<T, Z>
+        where Z: Zone<Ptr=Self>
+    {
+        src.take_unsized(|src| unsafe {
+            let metadata = T::metadata(src);
+            let layout = Layout::for_value(src);
+
+            let ptr = if layout.size() > 0 {
+                let dst = NonNull::new(std::alloc::alloc(layout))
+                                  .unwrap_or_else(|| std::alloc::handle_alloc_error(layout));
+
+                core::ptr::copy_nonoverlapping(src as *const _ as *const u8, dst.as_ptr(),
+                                               layout.size());
+                dst
+            } else {
+                NonNull::new_unchecked(layout.align() as *mut u8)
+            };
+
+            let fatptr = FatPtr {
+                raw: Self::from_slot(D::table().lock().insert(Slot { ptr, layout })),
+                metadata,
+            };
+            OwnedPtr::new_unchecked(ValidPtr::new_unchecked(fatptr))
+        })
+    }
+
+    pub fn try_take_dirty_unsized<T: ?Sized + Pointee, Z, R>(
+        owned: OwnedPtr<T, Z>,
+        f: impl FnOnce(Result<&mut ManuallyDrop<T>, Offset32<'p,'v>>) -> R
+    ) -> R
+    where Z: Zone<Ptr=Self>
+    {
+        let FatPtr { raw, metadata } = owned.into_inner().into_inner();
+
+        match raw.kind() {
+            Kind::Dirty(slot) => unsafe {
+                let Slot { ptr, layout } = D::table().lock().remove(slot);
+
+                let v: &mut T = &mut *T::make_fat_ptr_mut(ptr.cast().as_ptr(), metadata);
+                let v: &mut ManuallyDrop<T> = &mut *(v as *mut _ as *mut _);
+
+                struct DeallocOnDrop(Slot);
+
+                impl Drop for DeallocOnDrop {
+                    #[inline(always)]
+                    fn drop(&mut self) {
+                        if self.0.layout.size() > 0 {
+                            unsafe { std::alloc::dealloc(self.0.ptr.as_ptr(), self.0.layout) }
+                        }
+                    }
+                }
+                let dealloc_on_drop = DeallocOnDrop(Slot { ptr, layout });
+
+                let r = f(Ok(v));
+
+                drop(dealloc_on_drop);
+
+                r
+            },
+            Kind::Offset(offset) => f(Err(offset)),
+        }
+    }
+
+    #[inline]
+    fn from_slot(slot: u32) -> Self {
+        let raw = (slot + 1) << 1;
+        Self(Offset32 {
+            marker: PhantomData,
+            raw: NonZeroU32::new(raw).unwrap().into(),
+        }, PhantomData)
+    }
+
+    #[inline]
+    pub fn kind(&self) -> Kind<'p,'v> {
+        let raw = self.0.raw.get().get();
+        if raw & 1 == 1 {
+            Kind::Offset(self.0)
+        } else {
+            Kind::Dirty((raw >> 1) - 1)
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_offset(&self) -> Option<Offset32<'p,'v>> {
+        match self.kind() {
+            Kind::Offset(offset) => Some(offset),
+            Kind::Dirty(_) => None,
+        }
+    }
+
+    /// Returns a pointer to the dirty value, if any.
+    ///
+    /// The pointer remains valid until the `OwnedPtr` it came from is taken or dropped.
+    #[inline]
+    pub fn get_ptr(&self) -> Option<NonNull<u8>> {
+        match self.kind() {
+            Kind::Dirty(slot) => Some(D::table().lock().get(slot)),
+            Kind::Offset(_) => None,
+        }
+    }
+}
+
+impl<D: Dirty> fmt::Debug for OffsetMut32<'_,'_,D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.kind(), f)
+    }
+}
+
+impl<D: Dirty> fmt::Pointer for OffsetMut32<'_,'_,D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A dirty allocation.
+struct Slot {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+// The table only hands out pointers; access to the values themselves is controlled by the
+// OwnedPtr's that own them.
+unsafe impl Send for Slot {}
+
+/// Names the `DirtyTable` that dirty `OffsetMut32`'s are indexes into.
+///
+/// Implement with `dirty_table!`.
+///
+/// # Safety
+///
+/// `table()` must always return the same table: a slot index looked up in the wrong table would
+/// point to some other value entirely.
+pub unsafe trait Dirty : 'static + Copy + Eq + Ord + Hash + fmt::Debug + Default + Send + Sync {
+    fn table() -> &'static DirtyTable;
+}
+
+/// Declares a `Dirty` type with a table of its own.
+///
+/// ```
+/// # use hoard::dirty_table;
+/// # use hoard::pile::compact::TryPileMut32;
+/// dirty_table!(pub MyPile);
+///
+/// let pile = TryPileMut32::<MyPile>::default();
+/// ```
+#[macro_export]
+macro_rules! dirty_table {
+    ($vis:vis $name:ident) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+        $vis struct $name;
+
+        unsafe impl $crate::pile::compact::offset32::Dirty for $name {
+            #[inline]
+            fn table() -> &'static $crate::pile::compact::offset32::DirtyTable {
+                static TABLE: $crate::pile::compact::offset32::DirtyTable
+                    = $crate::pile::compact::offset32::DirtyTable::new();
+                &TABLE
+            }
+        }
+    }
+}
+
+/// Table of dirty allocations.
+///
+/// Created empty in a `static` by `dirty_table!`; the table itself is allocated on first use.
+/// Shared by every pile using the same `Dirty` type, in every thread; see the module docs for why
+/// it can't belong to a pile.
+pub struct DirtyTable {
+    inner: AtomicPtr<Mutex<Slots>>,
+}
+
+impl fmt::Debug for DirtyTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let slots = self.lock();
+        f.debug_struct("DirtyTable")
+            .field("used", &(slots.slots.len() - slots.free.len()))
+            .field("free", &slots.free.len())
+            .finish()
+    }
+}
+
+impl DirtyTable {
+    pub const fn new() -> Self {
+        Self { inner: AtomicPtr::new(std::ptr::null_mut()) }
+    }
+
+    fn lock(&self) -> MutexGuard<Slots> {
+        let mut inner = self.inner.load(Ordering::Acquire);
+        if inner.is_null() {
+            let new = Box::into_raw(Box::new(Mutex::default()));
+            inner = match self.inner.compare_and_swap(std::ptr::null_mut(), new, Ordering::AcqRel) {
+                old if old.is_null() => new,
+                old => {
+                    // SAFETY: another thread won the race, so new was never shared.
+                    drop(unsafe { Box::from_raw(new) });
+                    old
+                },
+            };
+        }
+
+        // SAFETY: once set, inner is never changed or freed.
+        let inner = unsafe { &*inner };
+        inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for DirtyTable {
+    fn drop(&mut self) {
+        let inner = *self.inner.get_mut();
+        if !inner.is_null() {
+            // SAFETY: we have unique access, and inner came from Box::into_raw()
+            drop(unsafe { Box::from_raw(inner) });
+        }
+    }
+}
+
+/// The slots of a `DirtyTable`.
+#[derive(Default)]
+struct Slots {
+    slots: Vec<Option<Slot>>,
+    free: Vec<u32>,
+}
+
+/// Largest slot index that still fits in an `OffsetMut32`.
+const MAX_SLOT: u32 = (1 << 31) - 2;
+
+impl Slots {
+    fn insert(&mut self, slot: Slot) -> u32 {
+        match self.free.pop() {
+            Some(idx) => {
+                self.slots[idx as usize] = Some(slot);
+                idx
+            },
+            None => {
+                let idx = self.slots.len() as u32;
+                assert!(idx <= MAX_SLOT, "too many dirty OffsetMut32 allocations");
+                self.slots.push(Some(slot));
+                idx
+            }
+        }
+    }
+
+    fn get(&self, idx: u32) -> NonNull<u8> {
+        self.slots[idx as usize].as_ref().expect("dirty slot in use").ptr
+    }
+
+    fn remove(&mut self, idx: u32) -> Slot {
+        let slot = self.slots[idx as usize].take().expect("dirty slot in use");
+        self.free.push(idx);
+        slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset32_range() {
+        assert_eq!(Offset32::new(0).unwrap().get(), 0);
+        assert_eq!(Offset32::new(Offset32::MAX).unwrap().get(), Offset32::MAX);
+        assert!(Offset32::new(Offset32::MAX + 1).is_none());
+    }
+
+    dirty_table!(Table);
+
+    #[test]
+    fn offsetmut32_kind() {
+        let offset = OffsetMut32::<Table>::from(Offset32::new(42).unwrap());
+        assert_eq!(offset.kind(), Kind::Offset(Offset32::new(42).unwrap()));
+
+        assert_eq!(OffsetMut32::<Table>::from_slot(0).kind(), Kind::Dirty(0));
+        assert_eq!(OffsetMut32::<Table>::from_slot(MAX_SLOT).kind(), Kind::Dirty(MAX_SLOT));
+    }
+
+    #[test]
+    fn dirty_tables_are_separate() {
+        dirty_table!(Other);
+
+        let a = Table::table().lock().insert(Slot { ptr: NonNull::dangling(), layout: Layout::new::<()>() });
+        let b = Other::table().lock().insert(Slot { ptr: NonNull::dangling(), layout: Layout::new::<()>() });
+        assert_eq!(b, 0);
+
+        Table::table().lock().remove(a);
+        Other::table().lock().remove(b);
+    }
+}
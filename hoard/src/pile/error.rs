@@ -1,24 +1,32 @@
 use core::any::Any;
+use core::cmp;
 use core::fmt;
 use core::marker::PhantomData;
 use core::ptr::NonNull;
 
-use thiserror::Error;
+use std::any::type_name;
+use std::error::Error as StdError;
+use std::io;
 
 use super::*;
 
 use crate::pointee::{Metadata, MetadataKind};
 use crate::marshal::load::PersistPointee;
 
-#[derive(Debug, Error)]
-#[error("pile dereference failed: {0:?}")]
+/// Returned when a pile dereference fails.
+///
+/// The `Display` implementation describes the type being loaded, where it was loaded from, and
+/// includes a hex excerpt of the offending bytes.
+#[derive(Debug)]
 pub struct Error<'p, 'v>(Box<Inner<'p,'v>>);
 
 #[derive(Debug)]
 struct Inner<'p, 'v> {
     zone: TryPile<'p, 'v>,
-    offset: Offset<'static, 'static>,
+    offset: usize,
     metadata: MetadataKind,
+    type_name: &'static str,
+    size: Option<usize>,
 
     kind: ErrorKind,
 }
@@ -30,6 +38,9 @@ pub enum ErrorKind {
     Value(Box<dyn std::error::Error + 'static + Send + Sync>),
 }
 
+/// Maximum number of bytes shown in the hex excerpt.
+const EXCERPT_LEN: usize = 32;
+
 impl<'p,'v> Error<'p, 'v> {
     #[cold]
     pub fn new<Z, T: ?Sized + Pointee>(zone: &Z, ptr: &FatPtr<T, Z::Persist>, kind: ErrorKind) -> Self
@@ -37,11 +48,166 @@ impl<'p,'v> Error<'p, 'v> {
     {
         Self(Box::new(Inner {
             zone: zone.get_try_pile(),
-            offset: ptr.raw,
+            offset: ptr.raw.into(),
             metadata: ptr.metadata.kind(),
+            type_name: type_name::<T>(),
+            size: T::try_layout(ptr.metadata).ok().map(|layout| layout.size()),
             kind,
         }))
     }
+
+    /// The offset the value was being loaded from.
+    pub fn offset(&self) -> usize {
+        self.0.offset
+    }
+
+    /// The name of the type being loaded.
+    pub fn type_name(&self) -> &'static str {
+        self.0.type_name
+    }
+
+    /// The expected size of the value, if the metadata was valid.
+    pub fn size(&self) -> Option<usize> {
+        self.0.size
+    }
+
+    pub fn metadata(&self) -> &MetadataKind {
+        &self.0.metadata
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0.kind
+    }
+
+    /// The bytes the value was being loaded from, truncated to the end of the pile.
+    pub fn bytes(&self) -> &'p [u8] {
+        let len = self.0.zone.len();
+        let start = cmp::min(self.offset(), len);
+        let end = cmp::min(start.saturating_add(self.size().unwrap_or(0)), len);
+        self.0.zone.mapping().get(start .. end).unwrap_or(&[])
+    }
+}
+
+impl fmt::Display for Error<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to load {} at offset {}", self.type_name(), self.offset())?;
+
+        if !self.metadata().is_sized() {
+            write!(f, " with {}", self.metadata())?;
+        }
+
+        match self.size() {
+            Some(1) => write!(f, " (1 byte): ")?,
+            Some(size) => write!(f, " ({} bytes): ", size)?,
+            None => write!(f, ": ")?,
+        }
+
+        match self.kind() {
+            ErrorKind::Offset => write!(f, "out of range of {} byte pile", self.0.zone.len())?,
+            ErrorKind::Metadata(err) => write!(f, "invalid metadata: {}", err)?,
+            ErrorKind::Value(err) => write!(f, "invalid value: {}", err)?,
+        }
+
+        let bytes = self.bytes();
+        if !bytes.is_empty() {
+            write!(f, "; bytes:")?;
+            for b in bytes.iter().take(EXCERPT_LEN) {
+                write!(f, " {:02x}", b)?;
+            }
+            if bytes.len() > EXCERPT_LEN {
+                write!(f, " ...")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StdError for Error<'_, '_> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self.kind() {
+            ErrorKind::Offset => None,
+            ErrorKind::Metadata(err) | ErrorKind::Value(err) => Some(&**err),
+        }
+    }
+}
+
+/// An `Error` detached from its pile, so it can be sent between threads.
+#[derive(Debug)]
+struct DetachedError {
+    msg: String,
+    source: Option<Box<dyn StdError + 'static + Send + Sync>>,
+}
+
+impl fmt::Display for DetachedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl StdError for DetachedError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match &self.source {
+            Some(err) => Some(&**err),
+            None => None,
+        }
+    }
+}
+
+impl From<Error<'_, '_>> for io::Error {
+    /// Converts to an `io::Error` of kind `InvalidData`.
+    ///
+    /// The message is the `Display` output, and `source()` is preserved.
+    fn from(err: Error<'_, '_>) -> io::Error {
+        let msg = err.to_string();
+        let source = match err.0.kind {
+            ErrorKind::Offset => None,
+            ErrorKind::Metadata(err) | ErrorKind::Value(err) => Some(err),
+        };
+        io::Error::new(io::ErrorKind::InvalidData, DetachedError { msg, source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use leint::Le;
+
+    #[test]
+    fn display_offset() {
+        TryPile::new(&[1, 2, 3], |pile| {
+            let err = pile.try_get_tip::<Le<u32>>().unwrap_err();
+            assert_eq!(err.offset(), 0);
+            assert_eq!(err.to_string(),
+                       "failed to load leint::Le<u32> at offset 0 (4 bytes): out of range of 3 byte pile; bytes: 01 02 03");
+            assert!(err.source().is_none());
+        })
+    }
+
+    #[test]
+    fn display_value() {
+        TryPile::new(&[0, 2], |pile| {
+            let err = pile.try_get_tip::<bool>().unwrap_err();
+            assert_eq!(err.to_string(),
+                       "failed to load bool at offset 1 (1 byte): invalid value: invalid bool blob; bytes: 02");
+            assert_eq!(err.source().unwrap().to_string(), "invalid bool blob");
+
+            let err = io::Error::from(err);
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(err.to_string(),
+                       "failed to load bool at offset 1 (1 byte): invalid value: invalid bool blob; bytes: 02");
+            assert_eq!(err.get_ref().unwrap().source().unwrap().to_string(), "invalid bool blob");
+        })
+    }
+
+    #[test]
+    fn display_excerpt_truncated() {
+        TryPile::new(&[0xff; 40][..], |pile| {
+            let err = pile.try_get_tip::<[bool; 40]>().err().unwrap();
+            assert!(err.to_string().ends_with(
+                "; bytes: ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ff ..."));
+        })
+    }
 }
 
 /*
@@ -0,0 +1,408 @@
+//! Compressed, "frozen", pile prefixes.
+//!
+//! Older data in a pile is rarely read, yet usually makes up most of it. `freeze()` rewrites a
+//! prefix of a pile - typically everything up to some old commit - into a frozen file of
+//! compressed chunks, with a table translating pile offsets to compressed chunks:
+//!
+//! ```text
+//! magic: [u8; 8]      "\0Frozen\0"
+//! version: Le<u32>
+//! chunk_size: Le<u32>
+//! len: Le<u64>        uncompressed length
+//! count: Le<u64>      number of chunks
+//! ends: [Le<u64>; count]
+//! compressed chunks...
+//! ```
+//!
+//! Chunk `i` holds the bytes at offsets `i * chunk_size ..` of the pile, and ends at `ends[i]`,
+//! relative to the start of the compressed chunks.
+//!
+//! `FrozenMapping` combines a frozen prefix with the uncompressed tail of the pile. Offsets are
+//! unchanged by freezing, so existing `Offset`s keep resolving: reads from the frozen prefix are
+//! decompressed on demand into a `PageCache`, while reads from the tail go directly to its own -
+//! usually memory-mapped - `Mapping`. Piles read through a `FrozenView`, which pins the chunks it
+//! hands out references to, until `unpin()`ed.
+//!
+//! Hoard files are frozen with `Hoard::freeze()`, which freezes everything after the file header
+//! up to the last commit. Commit digests and `Mark`s are frozen along with the rest, so a
+//! `FrozenView` of a hoard reads them back unchanged; see `file::verify_frozen()`. Nothing here
+//! decides when to freeze, or runs `freeze()` for you.
+
+use std::cmp;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use super::mapping::{Mapping, SliceMapping};
+use super::pagecache::{PageCache, Pinned};
+
+pub mod rle;
+pub use self::rle::Rle;
+
+const MAGIC: [u8; 8] = *b"\x00Frozen\x00";
+const HEADER_SIZE: usize = 32;
+
+/// Default size of an uncompressed chunk.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Default cache capacity, in bytes.
+const DEFAULT_CAPACITY: usize = 16 * 1024 * 1024;
+
+/// The compressed data was invalid.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("invalid compressed data")]
+pub struct DecompressError;
+
+/// A compression scheme.
+///
+/// The codec isn't recorded in the frozen file; it must be the same when reading as writing.
+pub trait Codec : fmt::Debug {
+    /// Compresses `src`, appending the result to `dst`.
+    fn compress(&self, src: &[u8], dst: &mut Vec<u8>);
+
+    /// Decompresses `src`, which must decompress to exactly `dst.len()` bytes.
+    fn decompress(&self, src: &[u8], dst: &mut [u8]) -> Result<(), DecompressError>;
+}
+
+/// Freezes `bytes`, writing the frozen file to `dst`.
+///
+/// Synchronous, and compresses the whole prefix in memory.
+pub fn freeze(bytes: &[u8], chunk_size: usize, codec: &impl Codec, mut dst: impl Write) -> io::Result<()> {
+    assert!(chunk_size > 0 && chunk_size <= u32::max_value() as usize);
+
+    let mut ends = Vec::with_capacity(bytes.len() / chunk_size + 1);
+    let mut compressed = vec![];
+    for chunk in bytes.chunks(chunk_size) {
+        codec.compress(chunk, &mut compressed);
+        ends.push(compressed.len() as u64);
+    }
+
+    dst.write_all(&MAGIC)?;
+    dst.write_all(&0u32.to_le_bytes())?;
+    dst.write_all(&(chunk_size as u32).to_le_bytes())?;
+    dst.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    dst.write_all(&(ends.len() as u64).to_le_bytes())?;
+    for end in ends {
+        dst.write_all(&end.to_le_bytes())?;
+    }
+    dst.write_all(&compressed)?;
+    dst.flush()
+}
+
+/// A frozen prefix, followed by an uncompressed tail.
+///
+/// Decompressed chunks are shared by every `FrozenView` of the mapping, through a `PageCache`.
+pub struct FrozenMapping<F, T, C = Rle> {
+    frozen: F,
+    tail: T,
+    codec: C,
+
+    chunk_size: usize,
+    frozen_len: usize,
+    ends: Vec<usize>,
+    data_start: usize,
+
+    cache: PageCache,
+}
+
+impl<F, T: fmt::Debug, C: fmt::Debug> fmt::Debug for FrozenMapping<F, T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FrozenMapping")
+            .field("frozen_len", &self.frozen_len)
+            .field("chunks", &self.ends.len())
+            .field("tail", &self.tail)
+            .field("codec", &self.codec)
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<F: SliceMapping, T: Mapping, C: Codec> FrozenMapping<F, T, C> {
+    /// Creates a new `FrozenMapping` from a frozen file, and the rest of the pile.
+    pub fn new(frozen: F, tail: T, codec: C) -> io::Result<Self> {
+        Self::with_capacity(frozen, tail, codec, DEFAULT_CAPACITY)
+    }
+
+    /// Creates a new `FrozenMapping` whose cache holds at most `capacity` decompressed bytes.
+    pub fn with_capacity(frozen: F, tail: T, codec: C, capacity: usize) -> io::Result<Self> {
+        let bytes = frozen.as_bytes();
+        if bytes.len() < HEADER_SIZE || bytes[.. 8] != MAGIC {
+            return Err(invalid_data("not a frozen file"));
+        }
+
+        let field = |i: usize| u64::from_le_bytes(bytes[i .. i + 8].try_into().unwrap());
+        let version = u32::from_le_bytes(bytes[8 .. 12].try_into().unwrap());
+        let chunk_size = u32::from_le_bytes(bytes[12 .. 16].try_into().unwrap()) as usize;
+        let frozen_len = field(16) as usize;
+        let count = field(24) as usize;
+
+        if version != 0 {
+            return Err(invalid_data("unsupported frozen file version"));
+        } else if chunk_size == 0 {
+            return Err(invalid_data("invalid chunk size"));
+        }
+
+        // The header is untrusted, so frozen_len may be large enough to overflow.
+        let expected_count = frozen_len.checked_add(chunk_size - 1)
+                                       .map(|n| n / chunk_size);
+        if expected_count != Some(count) {
+            return Err(invalid_data("invalid chunk count"));
+        }
+
+        let data_start = count.checked_mul(mem::size_of::<u64>())
+                              .and_then(|table_len| table_len.checked_add(HEADER_SIZE))
+                              .filter(|data_start| *data_start <= bytes.len())
+                              .ok_or_else(|| invalid_data("truncated translation table"))?;
+
+        let ends: Vec<usize> = (0 .. count).map(|i| field(HEADER_SIZE + i * 8) as usize).collect();
+        let mut prev = 0;
+        for end in ends.iter() {
+            if *end < prev || *end > bytes.len() - data_start {
+                return Err(invalid_data("invalid translation table"));
+            }
+            prev = *end;
+        }
+
+        Ok(Self {
+            frozen, tail, codec,
+            chunk_size, frozen_len, ends, data_start,
+            cache: PageCache::new(capacity),
+        })
+    }
+}
+
+impl<F, T, C> FrozenMapping<F, T, C> {
+    /// Returns the length of the frozen prefix.
+    pub fn frozen_len(&self) -> usize {
+        self.frozen_len
+    }
+
+    pub fn tail(&self) -> &T {
+        &self.tail
+    }
+
+    /// Returns the number of decompressed bytes currently cached.
+    ///
+    /// Doesn't include chunks that have been evicted, but are still pinned by a `FrozenView`.
+    pub fn cached(&self) -> usize {
+        self.cache.cached()
+    }
+
+    /// Creates a view of the mapping.
+    pub fn view(self: &Arc<Self>) -> FrozenView<F, T, C> {
+        FrozenView {
+            mapping: Arc::clone(self),
+            pinned: Pinned::default(),
+        }
+    }
+}
+
+impl<F: SliceMapping, T, C: Codec> FrozenMapping<F, T, C> {
+    /// Gets decompressed chunk `idx`.
+    fn chunk(&self, idx: usize) -> Option<Arc<[u8]>> {
+        self.cache.get_or_load(idx, || {
+            let start = idx * self.chunk_size;
+            let end = cmp::min(start + self.chunk_size, self.frozen_len);
+            let mut buf = vec![0; end - start].into_boxed_slice();
+
+            let data = &self.frozen.as_bytes()[self.data_start ..];
+            let compressed_start = if idx > 0 { self.ends[idx - 1] } else { 0 };
+            let compressed = &data[compressed_start .. self.ends[idx]];
+
+            // Corrupt data just means the bytes aren't available.
+            self.codec.decompress(compressed, &mut buf).ok()?;
+            Some(buf)
+        })
+    }
+}
+
+/// A `Mapping` of a `FrozenMapping`.
+///
+/// Pins the decompressed chunks it hands out references to; clones start out with nothing pinned.
+pub struct FrozenView<F, T, C = Rle> {
+    mapping: Arc<FrozenMapping<F, T, C>>,
+    pinned: Pinned,
+}
+
+impl<F, T, C> Clone for FrozenView<F, T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            mapping: Arc::clone(&self.mapping),
+            pinned: Pinned::default(),
+        }
+    }
+}
+
+impl<F, T: fmt::Debug, C: fmt::Debug> fmt::Debug for FrozenView<F, T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FrozenView")
+            .field("mapping", &self.mapping)
+            .field("pinned", &self.pinned)
+            .finish()
+    }
+}
+
+impl<F, T, C> FrozenView<F, T, C> {
+    pub fn mapping(&self) -> &Arc<FrozenMapping<F, T, C>> {
+        &self.mapping
+    }
+
+    /// Returns the number of decompressed bytes pinned by this view.
+    pub fn pinned(&self) -> usize {
+        self.pinned.pinned()
+    }
+
+    /// Unpins every chunk pinned by this view.
+    ///
+    /// Chunks that are still in the cache aren't decompressed again.
+    pub fn unpin(&mut self) {
+        self.pinned.clear()
+    }
+}
+
+unsafe impl<F, T, C> Mapping for FrozenView<F, T, C>
+where F: SliceMapping,
+      T: Mapping,
+      C: Codec,
+{
+    #[inline]
+    fn len(&self) -> usize {
+        self.mapping.frozen_len + self.mapping.tail.len()
+    }
+
+    fn get(&self, range: Range<usize>) -> Option<&[u8]> {
+        let frozen_len = self.mapping.frozen_len;
+        if range.start > range.end || range.end > self.len() {
+            None
+        } else if range.start >= frozen_len {
+            self.mapping.tail.get(range.start - frozen_len .. range.end - frozen_len)
+        } else if range.start == range.end {
+            Some(&[])
+        } else if range.end <= frozen_len {
+            self.pinned.get(range, self.mapping.chunk_size, |idx| self.mapping.chunk(idx))
+        } else {
+            // Piles are frozen at commit boundaries, so no blob should span the frozen prefix and
+            // the tail.
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use leint::Le;
+
+    use crate::pile::{TryPile, TryPileMut};
+    use crate::pile::error::ErrorKind;
+    use crate::zone::{Alloc, OwnedPtr, TryGet};
+
+    fn array_ptr<'p, 'v>(offset: usize) -> OwnedPtr<[Le<u64>; 8], TryPile<'p, 'v>> {
+        let ptr = TryPile::new_valid_ptr::<[Le<u64>; 8]>(offset, ());
+        unsafe { OwnedPtr::new_unchecked(ptr) }
+    }
+
+    /// Appends `n` commits, the `i`th at offset `i * 72`.
+    fn build_pile(bytes: &mut Vec<u8>, n: u64) {
+        for _ in 0 .. n {
+            let i = (bytes.len() as u64) / 72;
+            let pile = unsafe { TryPile::from_mapping_unchecked(&*bytes) };
+            let pile = TryPileMut::from(pile);
+            let commit = pile.encode_dirty(&pile.alloc([Le::new(i); 8]));
+            bytes.extend_from_slice(&commit);
+        }
+    }
+
+    #[test]
+    fn frozen_mapping() -> io::Result<()> {
+        let mut bytes = vec![];
+        build_pile(&mut bytes, 100);
+        let frozen_len = bytes.len();
+
+        let mut frozen = vec![];
+        freeze(&bytes, 256, &Rle, &mut frozen)?;
+        assert!(frozen.len() < frozen_len * 2 / 3);
+
+        // Commits made after freezing, that point into the frozen prefix.
+        build_pile(&mut bytes, 10);
+        let tail = bytes[frozen_len ..].to_vec();
+
+        let mapping = Arc::new(FrozenMapping::with_capacity(frozen, tail, Rle, 1024)?);
+        assert_eq!(mapping.frozen_len(), frozen_len);
+
+        let view = mapping.view();
+        assert_eq!(view.len(), bytes.len());
+
+        let pile = unsafe { TryPile::from_mapping_unchecked(&view) };
+        for i in 0 .. 110 {
+            let ptr = array_ptr(i * 72);
+            assert_eq!(&pile.try_get(&ptr).unwrap()[..], &[Le::new(i as u64); 8]);
+        }
+
+        // The cache is bounded, but the view keeps every chunk it's read pinned.
+        assert!(mapping.cached() <= 1024);
+        assert!(view.pinned() >= frozen_len);
+
+        assert_eq!(view.get(250 .. 260), Some(&bytes[250 .. 260]));
+        assert_eq!(view.get(frozen_len - 1 .. frozen_len + 1), None);
+
+        let mut view = mapping.view();
+        assert_eq!(view.pinned(), 0);
+        assert_eq!(view.get(0 .. 8), Some(&bytes[0 .. 8]));
+        assert_eq!(view.pinned(), 256);
+
+        view.unpin();
+        assert_eq!(view.pinned(), 0);
+        assert_eq!(view.get(0 .. 8), Some(&bytes[0 .. 8]));
+        Ok(())
+    }
+
+    #[test]
+    fn frozen_corrupt() -> io::Result<()> {
+        let mut bytes = vec![];
+        build_pile(&mut bytes, 10);
+
+        let mut frozen = vec![];
+        freeze(&bytes, 256, &Rle, &mut frozen)?;
+
+        assert!(FrozenMapping::new(&frozen[.. 40], &b""[..], Rle).is_err());
+        assert!(FrozenMapping::new(&b"not frozen"[..], &b""[..], Rle).is_err());
+
+        // A length that overflows when rounded up to whole chunks.
+        let mut overflow = frozen.clone();
+        overflow[16 .. 24].copy_from_slice(&u64::max_value().to_le_bytes());
+        let err = FrozenMapping::new(&overflow[..], &b""[..], Rle).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut zero_chunk_size = frozen.clone();
+        zero_chunk_size[12 .. 16].copy_from_slice(&0u32.to_le_bytes());
+        let err = FrozenMapping::new(&zero_chunk_size[..], &b""[..], Rle).unwrap_err();
+        assert_eq!(err.to_string(), "invalid chunk size");
+
+        // Corrupt the first compressed chunk.
+        let data_start = HEADER_SIZE + 3 * 8;
+        frozen[data_start] = 0xff;
+
+        let mapping = Arc::new(FrozenMapping::new(&frozen[..], &b""[..], Rle)?);
+        let view = mapping.view();
+        let pile = unsafe { TryPile::from_mapping_unchecked(&view) };
+
+        let ptr = array_ptr(0);
+        assert!(matches!(pile.try_get(&ptr).unwrap_err().kind(), ErrorKind::Offset));
+
+        // Other chunks are unaffected.
+        let ptr = array_ptr(648);
+        assert_eq!(&pile.try_get(&ptr).unwrap()[..], &[Le::new(9); 8]);
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+//! Run-length encoding.
+//!
+//! Piles are full of runs of zeros - padding, and the high bytes of little-endian integers - so
+//! even this simple scheme does well on them. The encoding is a sequence of tokens, each starting
+//! with a control byte `c`:
+//!
+//! * `c < 128`: `c + 1` literal bytes follow.
+//! * `c >= 128`: a single byte follows, repeated `c - 128 + MIN_RUN` times.
+
+use super::{Codec, DecompressError};
+
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = 127 + MIN_RUN;
+const MAX_LITERAL: usize = 128;
+
+/// Run-length encoding `Codec`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rle;
+
+fn run_len(src: &[u8]) -> usize {
+    let first = src[0];
+    src.iter().take(MAX_RUN).take_while(|b| **b == first).count()
+}
+
+impl Codec for Rle {
+    fn compress(&self, src: &[u8], dst: &mut Vec<u8>) {
+        let mut literal_start = 0;
+        let mut i = 0;
+
+        let flush_literals = |src: &[u8], dst: &mut Vec<u8>| {
+            for chunk in src.chunks(MAX_LITERAL) {
+                dst.push((chunk.len() - 1) as u8);
+                dst.extend_from_slice(chunk);
+            }
+        };
+
+        while i < src.len() {
+            let run = run_len(&src[i ..]);
+            if run >= MIN_RUN {
+                flush_literals(&src[literal_start .. i], dst);
+                dst.push((run - MIN_RUN + 128) as u8);
+                dst.push(src[i]);
+
+                i += run;
+                literal_start = i;
+            } else {
+                i += 1;
+            }
+        }
+        flush_literals(&src[literal_start ..], dst);
+    }
+
+    fn decompress(&self, mut src: &[u8], dst: &mut [u8]) -> Result<(), DecompressError> {
+        let mut written = 0;
+        while let Some((&control, rest)) = src.split_first() {
+            let control = control as usize;
+            let (len, rest) = if control < 128 {
+                let len = control + 1;
+                let literal = rest.get(.. len).ok_or(DecompressError)?;
+                dst.get_mut(written .. written + len).ok_or(DecompressError)?
+                   .copy_from_slice(literal);
+                (len, &rest[len ..])
+            } else {
+                let len = control - 128 + MIN_RUN;
+                let (&byte, rest) = rest.split_first().ok_or(DecompressError)?;
+                for b in dst.get_mut(written .. written + len).ok_or(DecompressError)? {
+                    *b = byte;
+                }
+                (len, rest)
+            };
+            written += len;
+            src = rest;
+        }
+
+        if written == dst.len() {
+            Ok(())
+        } else {
+            Err(DecompressError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(src: &[u8]) -> Vec<u8> {
+        let mut compressed = vec![];
+        Rle.compress(src, &mut compressed);
+
+        let mut decompressed = vec![0xff; src.len()];
+        Rle.decompress(&compressed, &mut decompressed).unwrap();
+        assert_eq!(decompressed, src);
+
+        compressed
+    }
+
+    #[test]
+    fn rle_roundtrip() {
+        assert_eq!(roundtrip(&[]), &[]);
+        assert_eq!(roundtrip(&[1]), &[0, 1]);
+        assert_eq!(roundtrip(&[1, 2, 2]), &[2, 1, 2, 2]);
+        assert_eq!(roundtrip(&[1, 0, 0, 0, 0, 2]), &[0, 1, 129, 0, 0, 2]);
+
+        assert_eq!(roundtrip(&[0; 1000]).len(), 16);
+
+        let literals: Vec<u8> = (0 .. 1000).map(|i| i as u8).collect();
+        assert_eq!(roundtrip(&literals).len(), 1000 + 8);
+    }
+
+    #[test]
+    fn rle_invalid() {
+        let mut dst = [0; 4];
+        assert_eq!(Rle.decompress(&[3, 1, 2, 3], &mut dst), Err(DecompressError));
+        assert_eq!(Rle.decompress(&[128, 0], &mut dst), Err(DecompressError));
+        assert_eq!(Rle.decompress(&[130], &mut dst), Err(DecompressError));
+        assert_eq!(Rle.decompress(&[129, 0], &mut dst[.. 3]), Err(DecompressError));
+        assert_eq!(Rle.decompress(&[129, 0], &mut dst[.. 4]), Ok(()));
+    }
+}
@@ -1,10 +1,24 @@
 use core::fmt;
 use core::cmp;
 use core::hash;
+use core::ops::Range;
+
+use std::sync::Arc;
 
 //use super::error::DerefError;
 
+/// The bytes backing a pile.
+///
+/// # Safety
+///
+/// `len()` must always return the same length, and `get()` must always return the same bytes for
+/// a given range.
 pub unsafe trait Mapping : fmt::Debug {
+    fn len(&self) -> usize;
+
+    /// Gets the bytes in `range`, returning `None` if they aren't available.
+    fn get(&self, range: Range<usize>) -> Option<&[u8]>;
+
     /*
     fn handle_deref_error<'p>(&'p self, err: DerefError<'p,'_>) -> ! {
         panic!("dereference failed: {:?}", err)
@@ -12,12 +26,64 @@ pub unsafe trait Mapping : fmt::Debug {
     */
 }
 
-unsafe impl Mapping for &'_ [u8] {
+/// A `Mapping` backed by a single contiguous slice.
+///
+/// # Safety
+///
+/// `as_bytes()` must return the same slice every time it is called, and `Mapping` must be
+/// implemented in terms of it.
+pub unsafe trait SliceMapping : Mapping {
+    fn as_bytes(&self) -> &[u8];
+}
+
+macro_rules! impl_mapping_for_slice_mapping {
+    ($( $t:ty ),* $(,)?) => {$(
+        unsafe impl Mapping for $t {
+            #[inline(always)]
+            fn len(&self) -> usize {
+                self.as_bytes().len()
+            }
+
+            #[inline(always)]
+            fn get(&self, range: Range<usize>) -> Option<&[u8]> {
+                self.as_bytes().get(range)
+            }
+        }
+    )*}
+}
+
+impl_mapping_for_slice_mapping!(&'_ [u8], Vec<u8>);
+
+unsafe impl SliceMapping for &'_ [u8] {
+    #[inline(always)]
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+}
+
+unsafe impl SliceMapping for Vec<u8> {
+    #[inline(always)]
+    fn as_bytes(&self) -> &[u8] {
+        &self[..]
+    }
+}
+
+unsafe impl<M: ?Sized + Mapping> Mapping for Arc<M> {
+    #[inline(always)]
+    fn len(&self) -> usize {
+        (**self).len()
+    }
+
+    #[inline(always)]
+    fn get(&self, range: Range<usize>) -> Option<&[u8]> {
+        (**self).get(range)
+    }
 }
 
-pub fn mapping_to_slice(mapping: &dyn Mapping) -> &&[u8] {
-    unsafe {
-        &*(mapping as *const dyn Mapping as *const &[u8])
+unsafe impl<M: ?Sized + SliceMapping> SliceMapping for Arc<M> {
+    #[inline(always)]
+    fn as_bytes(&self) -> &[u8] {
+        (**self).as_bytes()
     }
 }
 
@@ -54,8 +120,10 @@ mod tests {
         let orig_slice: &&[u8] = &&[1,2,3][..];
         let mapping: &dyn Mapping = orig_slice;
 
-        let slice = mapping_to_slice(mapping);
-        assert!(std::ptr::eq(orig_slice, slice));
+        assert_eq!(mapping.len(), 3);
+        let slice = mapping.get(0 .. 3).unwrap();
+        assert!(std::ptr::eq(*orig_slice, slice));
+        assert_eq!(mapping.get(1 .. 4), None);
 
         assert_eq!(format!("{:?}", mapping), "[1, 2, 3]");
     }
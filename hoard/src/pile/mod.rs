@@ -34,6 +34,7 @@ use crate::marshal::encode::*;
 use crate::marshal::load::*;
 use crate::marshal::save::*;
 use crate::marshal::blob::*;
+use crate::marshal::counting::{CountingDumper, SaveStats};
 use crate::marshal::*;
 
 pub mod offset;
@@ -48,6 +49,25 @@ use self::error::*;
 pub mod mapping;
 use self::mapping::Mapping;
 
+pub mod snapshot;
+use self::snapshot::Snapshot;
+
+pub mod compact;
+
+pub mod arena;
+
+pub mod pagecache;
+
+#[cfg(unix)]
+pub mod pread;
+
+pub mod sealed;
+
+pub mod frozen;
+
+pub mod stats;
+pub use self::arena::{Arena, TryPileArena};
+
 /// Fallible, unverified, `Pile`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TryPile<'pile, 'version> {
@@ -83,6 +103,13 @@ impl<'p, 'v> From<Pile<'p, 'v>> for TryPile<'p,'v> {
 }
 
 
+impl<'p, 'v> From<TryPile<'p, 'v>> for TryPileMut<'p,'v> {
+    #[inline(always)]
+    fn from(trypile: TryPile<'p,'v>) -> Self {
+        Self(trypile)
+    }
+}
+
 impl<'p,'v> ops::Deref for Pile<'p,'v> {
     type Target = TryPile<'p,'v>;
 
@@ -102,22 +129,38 @@ impl<'p> From<Unique<'p, &&[u8]>> for TryPile<'p, 'p> {
     }
 }
 
+impl<'p, 'v> TryPile<'p, 'v> {
+    /// Creates a new `TryPile` from a mapping.
+    ///
+    /// # Safety
+    ///
+    /// The callee must ensure that the `'pile` and `'version` lifetimes are correct for the
+    /// mapping: every `Offset<'p, 'v>` in existence must have been created for this mapping, or a
+    /// prefix of it.
+    #[inline]
+    pub unsafe fn from_mapping_unchecked(mapping: &'p dyn Mapping) -> Self {
+        Self {
+            marker: PhantomData,
+            mapping,
+        }
+    }
+}
+
 pub trait PileZone<'p, 'v>
 : Zone<Error = Error<'p,'v>,
-       PersistPtr = Offset<'static, 'static>>
+       PersistPtr: Into<usize>>
 {
     fn get_try_pile(&self) -> TryPile<'p, 'v>;
 
     fn mapping(&self) -> &'p dyn Mapping;
 
-    fn slice(&self) -> &'p &'p [u8] {
-        unsafe {
-            &*(self.mapping() as *const dyn Mapping as *const &'p [u8])
-        }
+    /// The length of the pile, in bytes.
+    fn len(&self) -> usize {
+        self.mapping().len()
     }
 }
 
-pub trait PileZoneMut<'p, 'v> : PileZone<'p, 'v> + Zone<Ptr = OffsetMut<'p,'v>>
+pub trait PileZoneMut<'p, 'v> : PileZone<'p, 'v> + Zone<Ptr = OffsetMut<'p,'v>, PersistPtr = Offset<'static, 'static>>
 {}
 
 impl<'p, 'v> PileZone<'p, 'v> for TryPile<'p, 'v> {
@@ -234,7 +277,7 @@ impl<'p,'v> TryPile<'p, 'v> {
     /// ```
     pub fn try_get_tip<T: Decode<Self>>(&self) -> Result<Ref<'p, T, Self>, Error<'p,'v>> {
         // By using saturating_sub we don't have to handle the too-large case ourselves.
-        let offset = self.slice().len().saturating_sub(mem::size_of::<T>());
+        let offset = self.len().saturating_sub(mem::size_of::<T>());
 
         let ptr = FatPtr::<T,_> {
             raw: Offset::new(offset.into()).unwrap(),
@@ -260,9 +303,9 @@ where T: ?Sized + PersistPointee,
 
     // It's impossible for this to overflow as the maximum offset is just a quarter of
     // usize::MAX
-    let start = ptr.raw.get();
+    let start: usize = ptr.raw.into();
     let end = start + layout.size();
-    match zone.slice().get(start .. end) {
+    match zone.mapping().get(start .. end) {
         None => Err(Error::new(zone, ptr, ErrorKind::Offset)),
         Some(slice) => {
             let ptr = T::Persist::make_fat_ptr(slice.as_ptr() as *const (), ptr.metadata);
@@ -276,7 +319,7 @@ where T: ?Sized + PersistPointee,
     }
 }
 
-fn try_get_impl<'a, 'p: 'a, 'v, T, Z>(
+pub(crate) fn try_get_impl<'a, 'p: 'a, 'v, T, Z>(
     zone: &Z,
     ptr: &FatPtr<T, Z::Persist>,
 )
@@ -478,8 +521,13 @@ impl<'p,'v> Zone for TryPileMut<'p,'v> {
         Self(self.0)
     }
 
-    fn clone_ptr<T>(ptr: &ValidPtr<T, Self>) -> OwnedPtr<T, Self> {
-        todo!()
+    fn clone_ptr<T: Clone>(ptr: &ValidPtr<T, Self>) -> OwnedPtr<T, Self> {
+        match Self::try_get_dirty(ptr) {
+            Ok(value) => OffsetMut::alloc(value.clone()),
+
+            // SAFETY: persisted values aren't owned by the pointer, so offsets can be duplicated.
+            Err(_) => unsafe { OwnedPtr::new_unchecked(ValidPtr::new_unchecked(**ptr)) },
+        }
     }
 
     fn try_get_dirty<T: ?Sized + Pointee>(ptr: &ValidPtr<T, Self>) -> Result<&T, FatPtr<T, Self::Persist>> {
@@ -567,6 +615,35 @@ impl<'p, 'v> TryGet for TryPileMut<'p, 'v> {
     }
 }
 
+/// Dumpers whose output can be read back, so `save_dirty()` can swap in the saved pointers.
+pub(crate) trait SavedBytes {
+    /// Returns everything written by the dumper so far.
+    fn saved_bytes(&self) -> &[u8];
+}
+
+/// Saves the dirty parts of `value` with `dumper`, returning `value` with the saved pointers
+/// swapped in.
+///
+/// The implementation of the `save_dirty()` methods of every pile.
+pub(crate) fn save_dirty_impl<'a, Z, T, D>(value: &'a T, dumper: D) -> Result<(T, D::BlobPtr, SaveStats), D::Error>
+    where T: Encode<'a, Z, Encoded = T>,
+          D: Dumper<Z> + SavedBytes,
+{
+    let mut state = value.make_encode_state();
+    let dumper = value.encode_poll(&mut state, CountingDumper::new(dumper))?;
+    let (dumper, offset) = dumper.encode_value(value, &state)?;
+    let (dumper, stats) = dumper.into_parts();
+
+    // SAFETY: value is always the last blob written, and as T is its own encoding, it's
+    // size_of::<T>() bytes of valid T.
+    let saved = unsafe {
+        let saved = dumper.saved_bytes();
+        let encoded = &saved[saved.len() - mem::size_of::<T>() ..];
+        ptr::read_unaligned(encoded.as_ptr() as *const T)
+    };
+    Ok((saved, offset, stats))
+}
+
 fn try_get_mut_impl<'a, 'p: 'a, 'v, T, Z>(
     zone: &Z,
     ptr: &'a mut ValidPtr<T,Z>,
@@ -640,8 +717,14 @@ impl<'a,'p,'v,Z> VecDumper<'a, 'p, 'v, Z> {
     }
 }
 
+impl<Z> SavedBytes for VecDumper<'_, '_, '_, Z> {
+    fn saved_bytes(&self) -> &[u8] {
+        self.buf
+    }
+}
+
 impl<'a,'p,'v, Z> Dumper<Z> for VecDumper<'a,'p,'v, Z>
-where Z: PileZone<'p, 'v>
+where Z: PileZone<'p, 'v> + Zone<PersistPtr = Offset<'static, 'static>>
 {
     type Error = !;
     type BlobPtr = Offset<'static, 'static>;
@@ -667,7 +750,7 @@ where Z: PileZone<'p, 'v>
         f: impl FnOnce(Self::WriteBlob) -> Result<Self::WriteBlobOk, Self::WriteBlobError>
     ) -> Result<(Self, Offset<'static, 'static>), !>
     {
-        let offset = self.pile.slice().len() + self.buf.len();
+        let offset = self.pile.len() + self.buf.len();
 
         self.buf.reserve(size);
 
@@ -688,6 +771,82 @@ where Z: PileZone<'p, 'v>
     }
 }
 
+/// `Dumper` that streams blobs to an `io::Write`.
+///
+/// Blobs are written in the same order, and with the same offsets, as `VecDumper`: the output
+/// appended to the pile is itself a valid pile.
+#[derive(Debug)]
+pub struct WriteDumper<'p, 'v, Z, W> {
+    marker: PhantomData<TryPile<'p, 'v>>,
+    pile: Z,
+    dst: W,
+    offset: usize,
+}
+
+impl<'p, 'v, Z, W> WriteDumper<'p, 'v, Z, W>
+where Z: PileZone<'p, 'v>,
+      W: io::Write,
+{
+    pub fn new(pile: Z, dst: W) -> Self {
+        Self {
+            marker: PhantomData,
+            offset: pile.len(),
+            pile, dst,
+        }
+    }
+
+    /// Returns the offset the next blob will be written at.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> W {
+        self.dst
+    }
+}
+
+impl<'p, 'v, Z, W> Dumper<Z> for WriteDumper<'p, 'v, Z, W>
+where Z: PileZone<'p, 'v> + Zone<PersistPtr = Offset<'static, 'static>>,
+      W: io::Write,
+{
+    type Error = io::Error;
+    type BlobPtr = Offset<'static, 'static>;
+
+    type WriteBlob = IoWriteBlob<W>;
+    type WriteBlobOk = W;
+    type WriteBlobError = io::Error;
+
+    fn try_save_ptr<'ptr, T: ?Sized + Pointee>(
+        &self,
+        ptr: &'ptr ValidPtr<T, Z>
+    ) -> Result<Offset<'static, 'static>, &'ptr T>
+    {
+        match Z::try_get_dirty(ptr) {
+            Ok(r) => Err(r),
+            Err(ptr) => Ok(ptr.raw),
+        }
+    }
+
+    fn save_blob(
+        self,
+        size: usize,
+        f: impl FnOnce(Self::WriteBlob) -> Result<Self::WriteBlobOk, Self::WriteBlobError>
+    ) -> Result<(Self, Offset<'static, 'static>), io::Error>
+    {
+        let Self { marker, pile, dst, offset } = self;
+
+        let dst = f(IoWriteBlob::new(dst, size))?;
+
+        Ok((Self { marker, pile, dst, offset: offset + size },
+            Offset::new(offset).unwrap()))
+    }
+
+    #[inline(always)]
+    fn blob_ptr_to_zone_ptr(ptr: Self::BlobPtr) -> Z::PersistPtr {
+        ptr
+    }
+}
+
 impl<'p, 'v> TryPileMut<'p,'v> {
     pub fn encode_dirty<'a, T>(&self, value: &'a T) -> Vec<u8>
         where T: Encode<'a, Self>
@@ -702,6 +861,53 @@ impl<'p, 'v> TryPileMut<'p,'v> {
         let (_dumper, _offset) = dumper.encode_value(value, &state).unwrap();
         dst
     }
+
+    /// Like `encode_dirty()`, but streams the encoding to an `io::Write`.
+    pub fn encode_dirty_to<'a, T, W>(&self, value: &'a T, dst: W) -> io::Result<W>
+        where T: Encode<'a, Self>,
+              W: io::Write,
+    {
+        let dumper = WriteDumper::new(*self, dst);
+
+        let mut state = value.make_encode_state();
+        let dumper = value.encode_poll(&mut state, dumper)?;
+
+        let (dumper, _offset) = dumper.encode_value(value, &state)?;
+        Ok(dumper.into_inner())
+    }
+
+    /// Saves the dirty parts of `value`, appending them to `dst`.
+    ///
+    /// Only dirty values - and `value` itself - are written; clean pointers are reused as-is.
+    /// Returns the offset of `value`, which is always the last blob written.
+    ///
+    /// Also returns a copy of `value` with every dirty pointer swapped for the offset it was saved
+    /// at; replace `value` with it to drop the dirty values. Those offsets point past the end of
+    /// the pile until `dst` has been appended to it; until then they simply fail to resolve. The
+    /// next save only writes what changed in the meantime.
+    pub fn save_dirty<'a, T>(&self, value: &'a T, dst: &mut Vec<u8>) -> (T, Offset<'static, 'static>, SaveStats)
+        where T: Encode<'a, Self, Encoded = T>
+    {
+        match save_dirty_impl(value, VecDumper::<Self>::new(*self, dst)) {
+            Ok(r) => r,
+            Err(never) => never,
+        }
+    }
+
+    /// Takes the value at the tip of the pile.
+    pub fn try_take_tip<T: Decode<Self>>(&self) -> Result<Own<T, Self>, Error<'p,'v>> {
+        let offset = self.len().saturating_sub(mem::size_of::<T>());
+
+        let ptr = FatPtr::<T, TryPile> {
+            raw: Offset::new(offset).unwrap(),
+            metadata: ()
+        };
+        let r = try_get_impl(self, &ptr)?;
+        Ok(Own {
+            this: unsafe { T::assume_valid(r) },
+            zone: *self,
+        })
+    }
 }
 
 impl<'p,'v> SavePtr<Self> for TryPileMut<'p, 'v> {
@@ -709,10 +915,7 @@ impl<'p,'v> SavePtr<Self> for TryPileMut<'p, 'v> {
         -> Result<Offset<'static, 'static>, &'a T>
         where D: Dumper<Self>
     {
-        match Self::try_get_dirty(ptr) {
-            Ok(r) => Err(r),
-            Err(FatPtr { raw, metadata: _ }) => Ok(raw),
-        }
+        dumper.try_save_ptr(ptr)
     }
 }
 
@@ -773,4 +976,61 @@ pub mod test {
                     109, 0, 0, 0, 0, 0, 0, 0,
                     ][..]);
     }
+
+    #[test]
+    fn trypile_encode_dirty_to() {
+        let pile = TryPileMut::default();
+        let x = [[pile.alloc(1u8), pile.alloc(2u8), pile.alloc(3u8)],
+                 [pile.alloc(4u8), pile.alloc(5u8), pile.alloc(6u8)]];
+
+        let buf = pile.encode_dirty_to(&x, vec![]).unwrap();
+        assert_eq!(buf, pile.encode_dirty(&x));
+
+        TryPile::new(&buf, |pile| {
+            let tip = pile.try_get_tip::<[[OwnedPtr<u8, TryPile>; 3]; 2]>().unwrap();
+
+            for (ptr, expected) in tip.iter().flatten().zip(1u8 ..) {
+                assert_eq!(**pile.try_get(ptr).unwrap(), expected);
+            }
+        })
+    }
+
+    #[test]
+    fn trypilemut_save_dirty() {
+        let pile = TryPileMut::default();
+        let x = [pile.alloc(1u8), pile.alloc(2u8), pile.alloc(3u8)];
+
+        let encoded = pile.encode_dirty(&x);
+        let mut buf = vec![];
+        let (x, offset, stats) = pile.save_dirty(&x, &mut buf);
+        assert_eq!(offset.get(), 3);
+        assert_eq!(stats, SaveStats { blobs_written: 4, bytes_written: 27, clean_reused: 0 });
+        assert_eq!(buf, encoded);
+
+        // Swapped for the offsets they were saved at.
+        assert!(x.iter().all(|ptr| !ptr.is_dirty()));
+        assert!(pile.try_get(&x[0]).is_err());
+
+        TryPile::new(buf.clone(), |new_pile| {
+            let pile = TryPileMut::from(new_pile);
+
+            // TryPile::new() picks a fresh brand, but this is the same pile with buf appended.
+            let mut x: [OwnedPtr<u8, TryPileMut>; 3] = unsafe { mem::transmute(x) };
+            assert_eq!(**pile.try_get(&x[2]).unwrap(), 3);
+
+            *x[1].make_mut(&pile).unwrap().this = 42;
+
+            let mut buf2 = vec![];
+            let (x, offset, stats) = pile.save_dirty(&x, &mut buf2);
+            assert_eq!(offset.get(), buf.len() + 1);
+            assert_eq!(stats, SaveStats { blobs_written: 2, bytes_written: 25, clean_reused: 2 });
+            assert_eq!(buf2,
+                       &[42,
+                          1, 0, 0, 0, 0, 0, 0, 0,
+                         55, 0, 0, 0, 0, 0, 0, 0,
+                          5, 0, 0, 0, 0, 0, 0, 0,
+                       ]);
+            assert!(!x[1].is_dirty());
+        })
+    }
 }
@@ -70,6 +70,7 @@ use std::ptr::NonNull;
 
 use thiserror::Error;
 use leint::Le;
+use nonzero::NonZero;
 
 use crate::coerce::TryCoerce;
 use crate::marshal::*;
@@ -79,13 +80,15 @@ use crate::marshal::encode::*;
 
 use super::Pile;
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, TryCoerce, NonZero)]
 #[repr(transparent)]
+#[try_coerce(for<'p, 'v> Offset<'p, 'v>)]
 pub struct Offset<'pile, 'version> {
     marker: PhantomData<(
         fn(&Pile<'pile, 'version>) -> &'pile (),
         &'version (),
     )>,
+    #[nonzero]
     pub(super) raw: Le<NonZeroU64>,
 }
 
@@ -207,10 +210,6 @@ impl<Z> Encode<'_, Z> for Offset<'_, '_> {
 }
 impl Primitive for Offset<'_, '_> {}
 
-unsafe impl<'p, 'v> TryCoerce<Offset<'p,'v>> for Offset<'_, '_> {
-    type Error = !;
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -8,6 +8,7 @@ use core::ptr::NonNull;
 
 use thiserror::Error;
 
+use nonzero::NonZero;
 use owned::{Take, IntoOwned};
 
 use crate::coerce::TryCoerce;
@@ -19,14 +20,12 @@ use crate::zone::*;
 
 use super::offset::*;
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, TryCoerce, NonZero)]
 #[repr(transparent)]
+#[try_coerce(for<'p2, 'v2> OffsetMut<'p2, 'v2>)]
+#[try_coerce(from for<'p2, 'v2> Offset<'p2, 'v2>)]
 pub struct OffsetMut<'p,'v>(Offset<'p,'v>);
 
-unsafe impl<'p, 'v> TryCoerce<OffsetMut<'p, 'v>> for Offset<'_, '_> {
-    type Error = !;
-}
-
 unsafe impl<'p, 'v> TryCoerce<Offset<'p, 'v>> for OffsetMut<'_, '_> {
     type Error = TryCoerceOffsetMutError;
 
@@ -0,0 +1,274 @@
+//! Page caches for `Mapping`s whose bytes aren't in memory.
+//!
+//! A `PageCache` holds recently used pages, shared by every reader, and never grows past its
+//! capacity: inserting a page evicts the least recently used ones. Evicted pages can't simply be
+//! freed though, as `Mapping::get()` hands out references to them. So readers go through a
+//! `Pinned` set of pages instead, which keeps every page it has handed out references to alive
+//! until the `Pinned` itself is dropped or cleared.
+//!
+//! Since clearing takes `&mut self`, pins are scoped to a borrow of their reader: once the
+//! references handed out have gone out of scope, the reader can release its pins, and continue
+//! with an empty set. Long-running readers such as hoard verification do exactly that after every
+//! chunk they read, keeping what they pin bounded.
+//!
+//! Pinning also keeps reads consistent. Every range is served from the pinned pages, with ranges
+//! spanning pages copied out of them, so while a page is pinned a given range always returns the
+//! same bytes, even if the underlying file changes behind our back and the page is evicted and
+//! reloaded for other readers.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Bounded, least recently used, cache of pages.
+pub struct PageCache {
+    capacity: usize,
+    inner: Mutex<Cache>,
+}
+
+#[derive(Default)]
+struct Cache {
+    pages: HashMap<usize, CachedPage>,
+    used: usize,
+    tick: u64,
+}
+
+struct CachedPage {
+    page: Arc<[u8]>,
+    last_used: u64,
+}
+
+impl fmt::Debug for PageCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PageCache")
+            .field("capacity", &self.capacity)
+            .field("cached", &self.cached())
+            .finish()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl PageCache {
+    /// Creates a new cache that holds at most `capacity` bytes of pages.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of bytes currently cached.
+    pub fn cached(&self) -> usize {
+        lock(&self.inner).used
+    }
+
+    /// Gets page `idx`, loading it with `load` if it isn't cached.
+    ///
+    /// Pages are loaded with the cache locked, so concurrent readers never load the same page
+    /// twice. Least recently used pages are evicted to make room; a page bigger than the capacity
+    /// is returned without being cached at all.
+    pub fn get_or_load(&self, idx: usize, load: impl FnOnce() -> Option<Box<[u8]>>) -> Option<Arc<[u8]>> {
+        let mut cache = lock(&self.inner);
+        cache.tick += 1;
+        let tick = cache.tick;
+
+        if let Some(cached) = cache.pages.get_mut(&idx) {
+            cached.last_used = tick;
+            return Some(Arc::clone(&cached.page));
+        }
+
+        let page: Arc<[u8]> = load()?.into();
+        if page.len() <= self.capacity {
+            while cache.used + page.len() > self.capacity {
+                let (&lru, _) = cache.pages.iter()
+                                     .min_by_key(|(_, cached)| cached.last_used)
+                                     .expect("used bytes without pages");
+                let evicted = cache.pages.remove(&lru).unwrap();
+                cache.used -= evicted.page.len();
+            }
+
+            cache.used += page.len();
+            cache.pages.insert(idx, CachedPage { page: Arc::clone(&page), last_used: tick });
+        }
+        Some(page)
+    }
+}
+
+/// The pages a reader has handed out references to.
+#[derive(Default)]
+pub struct Pinned {
+    inner: Mutex<PinnedPages>,
+}
+
+#[derive(Default)]
+struct PinnedPages {
+    pages: HashMap<usize, Arc<[u8]>>,
+
+    /// Copies of ranges that span pages, keyed by their first and last pages.
+    spans: HashMap<(usize, usize), Box<[u8]>>,
+}
+
+impl fmt::Debug for Pinned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pinned")
+            .field("pinned", &self.pinned())
+            .finish()
+    }
+}
+
+impl Pinned {
+    /// Returns the number of bytes pinned, including copies of ranges spanning pages.
+    pub fn pinned(&self) -> usize {
+        let inner = lock(&self.inner);
+        inner.pages.values().map(|page| page.len())
+             .chain(inner.spans.values().map(|span| span.len()))
+             .sum()
+    }
+
+    /// Unpins every page.
+    ///
+    /// Taking `&mut self` guarantees none of the references handed out by `get()` are still
+    /// alive.
+    pub fn clear(&mut self) {
+        self.inner = Mutex::default();
+    }
+
+    /// Gets the bytes in `range`, from pages of `page_size` bytes.
+    ///
+    /// Pages that aren't already pinned are pinned with `page()`. The range must be non-empty, and
+    /// within the pages; a page shorter than it should be makes the range unavailable.
+    pub fn get(
+        &self,
+        range: Range<usize>,
+        page_size: usize,
+        mut page: impl FnMut(usize) -> Option<Arc<[u8]>>,
+    ) -> Option<&[u8]>
+    {
+        debug_assert!(range.start < range.end);
+        let first = range.start / page_size;
+        let last = (range.end - 1) / page_size;
+        let span_start = first * page_size;
+
+        let mut inner = lock(&self.inner);
+
+        if !inner.spans.contains_key(&(first, last)) {
+            for idx in first ..= last {
+                if !inner.pages.contains_key(&idx) {
+                    let page = page(idx)?;
+                    inner.pages.insert(idx, page);
+                }
+            }
+
+            if first != last {
+                let mut span = Vec::with_capacity(range.end - span_start);
+                for idx in first ..= last {
+                    span.extend_from_slice(&inner.pages[&idx]);
+                }
+                inner.spans.insert((first, last), span.into_boxed_slice());
+            }
+        }
+
+        let bytes: &[u8] = if first == last {
+            &inner.pages[&first]
+        } else {
+            &inner.spans[&(first, last)]
+        };
+
+        // SAFETY: Pinned pages and spans are heap allocated, so their addresses don't change when
+        // the maps are modified, and they're only removed by clear(), which borrows self mutably.
+        let bytes: &[u8] = unsafe { &*(bytes as *const [u8]) };
+        bytes.get(range.start - span_start .. range.end - span_start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(idx: usize) -> Option<Box<[u8]>> {
+        Some(vec![idx as u8; 4].into_boxed_slice())
+    }
+
+    #[test]
+    fn cache_bounded_on_insert() {
+        let cache = PageCache::new(8);
+
+        let page0 = cache.get_or_load(0, || page(0)).unwrap();
+        cache.get_or_load(1, || page(1)).unwrap();
+        assert_eq!(cache.cached(), 8);
+
+        // Page 0 is the most recently used, so page 1 is evicted.
+        cache.get_or_load(0, || panic!("page 0 should be cached")).unwrap();
+        cache.get_or_load(2, || page(2)).unwrap();
+        assert_eq!(cache.cached(), 8);
+        cache.get_or_load(0, || panic!("page 0 should be cached")).unwrap();
+        assert!(cache.get_or_load(1, || None).is_none());
+
+        // Evicted pages live on while referenced.
+        cache.get_or_load(3, || page(3)).unwrap();
+        cache.get_or_load(4, || page(4)).unwrap();
+        assert_eq!(&page0[..], &[0; 4]);
+
+        // Too big to cache at all.
+        let big = cache.get_or_load(5, || Some(vec![5; 16].into_boxed_slice())).unwrap();
+        assert_eq!(big.len(), 16);
+        assert_eq!(cache.cached(), 8);
+    }
+
+    #[test]
+    fn pinned_consistent() {
+        let cache = PageCache::new(4);
+        let pinned = Pinned::default();
+        let mut version = 0;
+
+        let mut load = |idx: usize| {
+            cache.get_or_load(idx, || {
+                version += 1;
+                Some(vec![version; 4].into_boxed_slice())
+            })
+        };
+
+        assert_eq!(pinned.get(1 .. 3, 4, &mut load), Some(&[1, 1][..]));
+
+        // Spans pages, and evicts page 0 from the cache.
+        assert_eq!(pinned.get(2 .. 6, 4, &mut load), Some(&[1, 1, 2, 2][..]));
+
+        // Page 0 is served from the pinned copy, rather than reloaded.
+        assert_eq!(pinned.get(0 .. 4, 4, &mut load), Some(&[1, 1, 1, 1][..]));
+        assert_eq!(pinned.pinned(), 4 + 4 + 8);
+
+        assert_eq!(pinned.get(4 .. 12, 4, |_| None), None);
+    }
+
+    #[test]
+    fn pinned_clear() {
+        let cache = PageCache::new(4);
+        let mut pinned = Pinned::default();
+        let mut version = 0;
+
+        let mut load = |idx: usize| {
+            cache.get_or_load(idx, || {
+                version += 1;
+                Some(vec![version; 4].into_boxed_slice())
+            })
+        };
+
+        assert_eq!(pinned.get(0 .. 4, 4, &mut load), Some(&[1; 4][..]));
+        assert_eq!(pinned.get(4 .. 8, 4, &mut load), Some(&[2; 4][..]));
+        assert_eq!(pinned.pinned(), 8);
+
+        // Page 0 was evicted from the cache, so once unpinned it's reloaded.
+        pinned.clear();
+        assert_eq!(pinned.pinned(), 0);
+        assert_eq!(pinned.get(0 .. 4, 4, &mut load), Some(&[3; 4][..]));
+        assert_eq!(pinned.pinned(), 4);
+    }
+}
@@ -0,0 +1,273 @@
+//! `Mapping` that reads a file with `pread` rather than memory-mapping it.
+//!
+//! A memory-mapped file that is truncated or modified behind our back crashes the process with
+//! `SIGBUS` the next time the affected pages are touched. `PreadMapping` trades some speed for
+//! safety: bytes are read on demand into a page cache, and a read that fails simply makes the
+//! bytes unavailable, which piles report as an out-of-range `Error`.
+//!
+//! The `PreadMapping` itself holds a `PageCache` shared by all its readers, bounded to its
+//! capacity. Piles read through a `PreadView`, which pins the pages it hands out references to
+//! until `unpin()` is called; see `pagecache` for details.
+
+use std::cmp;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::sync::Arc;
+
+use super::mapping::Mapping;
+use super::pagecache::{PageCache, Pinned};
+
+/// Size of a page in the cache.
+pub const PAGE_SIZE: usize = 4096;
+
+/// Default cache capacity, in bytes.
+const DEFAULT_CAPACITY: usize = 16 * 1024 * 1024;
+
+/// Read-through, `pread` based, page cache of a file.
+pub struct PreadMapping {
+    fd: File,
+    len: usize,
+    cache: PageCache,
+}
+
+impl fmt::Debug for PreadMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PreadMapping")
+            .field("fd", &self.fd)
+            .field("len", &self.len)
+            .field("cache", &self.cache)
+            .finish()
+    }
+}
+
+impl PreadMapping {
+    /// Creates a new mapping of the current contents of a file.
+    ///
+    /// Later changes to the length of the file are ignored.
+    pub fn new(fd: File) -> io::Result<Self> {
+        Self::with_capacity(fd, DEFAULT_CAPACITY)
+    }
+
+    /// Creates a new mapping whose cache holds at most `capacity` bytes.
+    pub fn with_capacity(fd: File, capacity: usize) -> io::Result<Self> {
+        let len = fd.metadata()?.len() as usize;
+        Ok(Self {
+            fd, len,
+            cache: PageCache::new(capacity),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the number of bytes currently cached.
+    ///
+    /// Doesn't include pages that have been evicted, but are still pinned by a `PreadView`.
+    pub fn cached(&self) -> usize {
+        self.cache.cached()
+    }
+
+    /// Creates a view of the whole file.
+    pub fn view(self: &Arc<Self>) -> PreadView {
+        PreadView {
+            mapping: Arc::clone(self),
+            start: 0,
+            len: self.len,
+            pinned: Pinned::default(),
+        }
+    }
+
+    fn page(&self, idx: usize) -> Option<Arc<[u8]>> {
+        self.cache.get_or_load(idx, || {
+            let start = idx * PAGE_SIZE;
+            let end = cmp::min(start + PAGE_SIZE, self.len);
+            let mut buf = vec![0; end - start].into_boxed_slice();
+
+            // A failed read - eg due to the file being truncated - just means the bytes
+            // aren't available.
+            self.fd.read_exact_at(&mut buf, start as u64).ok()?;
+            Some(buf)
+        })
+    }
+}
+
+/// A `Mapping` of a range of a `PreadMapping`.
+///
+/// Each view pins pages independently: clones start out with nothing pinned.
+#[derive(Debug)]
+pub struct PreadView {
+    mapping: Arc<PreadMapping>,
+    start: usize,
+    len: usize,
+    pinned: Pinned,
+}
+
+impl Clone for PreadView {
+    fn clone(&self) -> Self {
+        Self {
+            mapping: Arc::clone(&self.mapping),
+            start: self.start,
+            len: self.len,
+            pinned: Pinned::default(),
+        }
+    }
+}
+
+impl PreadView {
+    pub fn mapping(&self) -> &Arc<PreadMapping> {
+        &self.mapping
+    }
+
+    /// Narrows the view to `range`, relative to the current view.
+    ///
+    /// Returns `None` if `range` is out of bounds.
+    pub fn slice(mut self, range: Range<usize>) -> Option<Self> {
+        if range.start > range.end || range.end > self.len {
+            None
+        } else {
+            self.start += range.start;
+            self.len = range.end - range.start;
+            Some(self)
+        }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.len = cmp::min(self.len, len);
+    }
+
+    /// Returns the number of bytes pinned by this view.
+    pub fn pinned(&self) -> usize {
+        self.pinned.pinned()
+    }
+
+    /// Unpins every page pinned by this view.
+    ///
+    /// Later reads may see different bytes, if the file has been changed since.
+    pub fn unpin(&mut self) {
+        self.pinned.clear()
+    }
+}
+
+unsafe impl Mapping for PreadView {
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, range: Range<usize>) -> Option<&[u8]> {
+        if range.start > range.end || range.end > self.len {
+            None
+        } else if range.start == range.end {
+            Some(&[])
+        } else {
+            let range = self.start + range.start .. self.start + range.end;
+            self.pinned.get(range, PAGE_SIZE, |idx| self.mapping.page(idx))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use crate::pile::TryPile;
+    use crate::pile::error::ErrorKind;
+    use crate::zone::{TryGet, OwnedPtr};
+
+    fn tempfile_with(bytes: &[u8]) -> File {
+        let mut fd = tempfile::tempfile().unwrap();
+        fd.write_all(bytes).unwrap();
+        fd
+    }
+
+    #[test]
+    fn pread_get() {
+        let bytes: Vec<u8> = (0 .. 3 * PAGE_SIZE).map(|i| i as u8).collect();
+        let mapping = Arc::new(PreadMapping::with_capacity(tempfile_with(&bytes), PAGE_SIZE).unwrap());
+        let view = mapping.view();
+
+        assert_eq!(view.len(), bytes.len());
+        assert_eq!(view.get(1 .. 4), Some(&bytes[1 .. 4]));
+        assert_eq!(mapping.cached(), PAGE_SIZE);
+
+        // Spans pages, which evicts the first page from the cache.
+        assert_eq!(view.get(10 .. PAGE_SIZE + 10), Some(&bytes[10 .. PAGE_SIZE + 10]));
+        assert_eq!(mapping.cached(), PAGE_SIZE);
+
+        assert_eq!(view.get(2 * PAGE_SIZE .. 3 * PAGE_SIZE), Some(&bytes[2 * PAGE_SIZE ..]));
+        assert_eq!(view.get(3 * PAGE_SIZE - 1 .. 3 * PAGE_SIZE + 1), None);
+        assert_eq!(mapping.cached(), PAGE_SIZE);
+        assert_eq!(view.pinned(), 3 * PAGE_SIZE + 2 * PAGE_SIZE);
+
+        let view = view.slice(PAGE_SIZE .. 2 * PAGE_SIZE + 1).unwrap();
+        assert_eq!(view.get(0 .. 2), Some(&bytes[PAGE_SIZE .. PAGE_SIZE + 2]));
+        assert_eq!(view.get(PAGE_SIZE .. PAGE_SIZE + 2), None);
+    }
+
+    #[test]
+    fn pread_consistent() {
+        let fd = tempfile_with(&[1; 2 * PAGE_SIZE]);
+        let mapping = Arc::new(PreadMapping::with_capacity(fd.try_clone().unwrap(), PAGE_SIZE).unwrap());
+        let view = mapping.view();
+
+        assert_eq!(view.get(0 .. 1), Some(&[1][..]));
+
+        fd.write_all_at(&[2; 2 * PAGE_SIZE], 0).unwrap();
+
+        // The second page is read after the change, but the first is the pinned copy.
+        assert_eq!(view.get(PAGE_SIZE - 1 .. PAGE_SIZE + 1), Some(&[1, 2][..]));
+        assert_eq!(view.get(0 .. 1), Some(&[1][..]));
+
+        // A new view gets a fresh set of pages, as does a clone.
+        assert_eq!(mapping.view().get(0 .. 1), Some(&[2][..]));
+        assert_eq!(view.clone().get(0 .. 1), Some(&[2][..]));
+    }
+
+    #[test]
+    fn pread_unpin() {
+        let bytes: Vec<u8> = (0 .. 3 * PAGE_SIZE).map(|i| i as u8).collect();
+        let fd = tempfile_with(&bytes);
+        let mapping = Arc::new(PreadMapping::with_capacity(fd.try_clone().unwrap(), PAGE_SIZE).unwrap());
+        let mut view = mapping.view();
+
+        for i in 0 .. 3 {
+            let page = i * PAGE_SIZE .. (i + 1) * PAGE_SIZE;
+            assert_eq!(view.get(page.clone()), Some(&bytes[page]));
+            assert_eq!(view.pinned(), PAGE_SIZE);
+            view.unpin();
+            assert_eq!(view.pinned(), 0);
+        }
+
+        // Unpinned pages are reread.
+        fd.write_all_at(&[42], 0).unwrap();
+        assert_eq!(view.get(0 .. 1), Some(&[42][..]));
+    }
+
+    #[test]
+    fn pread_truncated() {
+        let fd = tempfile_with(&[42; 2 * PAGE_SIZE]);
+        let mapping = Arc::new(PreadMapping::new(fd.try_clone().unwrap()).unwrap());
+        let view = mapping.view();
+        let pile = unsafe { TryPile::from_mapping_unchecked(&view) };
+
+        let ptr1 = TryPile::new_valid_ptr::<u8>(5, ());
+        let ptr1: OwnedPtr<u8, TryPile> = unsafe { OwnedPtr::new_unchecked(ptr1) };
+        assert_eq!(**pile.try_get(&ptr1).unwrap(), 42);
+
+        fd.set_len(10).unwrap();
+
+        // Already pinned, so unaffected by the truncation.
+        assert_eq!(**pile.try_get(&ptr1).unwrap(), 42);
+
+        let ptr2 = TryPile::new_valid_ptr::<u8>(PAGE_SIZE + 5, ());
+        let ptr2: OwnedPtr<u8, TryPile> = unsafe { OwnedPtr::new_unchecked(ptr2) };
+        let err = pile.try_get(&ptr2).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Offset));
+    }
+}
@@ -0,0 +1,403 @@
+//! Piles encrypted at rest.
+//!
+//! A sealed pile is stored as a sequence of segments, each encrypted and authenticated with an
+//! AEAD provided by the caller:
+//!
+//! ```text
+//! len: Le<u64>        length of the plaintext
+//! nonce: [u8; 16]     random
+//! ciphertext: [u8; len]
+//! tag: [u8; A::TAG_LEN]
+//! ```
+//!
+//! The nonce is chosen at random every time a segment is sealed, so sealing two plaintexts at the
+//! same position - eg after a crash lost the first attempt - never reuses a nonce. The header, and
+//! the segment's position in the pile, are authenticated as associated data.
+//!
+//! The plaintexts of the segments, concatenated, form the pile. Typically each segment is the
+//! output of `encode_dirty()` on a pile of the segments before it.
+//!
+//! Piles can't be read directly from the sealed bytes. Instead `SealedMapping` decrypts every
+//! segment into memory, giving up zero-copy access in exchange for confidentiality. Once
+//! decrypted, the `SealedMapping` is an ordinary `Mapping`, and piles created from it return
+//! ordinary `Ref`s.
+//!
+//! Note that removing whole segments from the end of a sealed pile can't be detected: like any
+//! pile, a prefix of a sealed pile is itself a valid pile.
+
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ops::Range;
+
+use thiserror::Error;
+
+use super::mapping::{Mapping, SliceMapping};
+
+/// Length of a `SegmentNonce`.
+pub const NONCE_LEN: usize = 16;
+
+/// Per-segment nonce material, stored in the segment header.
+///
+/// Random, so unique with overwhelming probability even across piles sharing a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SegmentNonce(pub [u8; NONCE_LEN]);
+
+impl SegmentNonce {
+    /// Creates a new random nonce.
+    pub fn random() -> io::Result<Self> {
+        let mut r = [0; NONCE_LEN];
+        getrandom::getrandom(&mut r)?;
+        Ok(Self(r))
+    }
+
+    pub fn to_bytes(&self) -> [u8; NONCE_LEN] {
+        self.0
+    }
+}
+
+/// Position of a segment within a sealed pile.
+///
+/// Authenticated as part of the associated data, so segments can't be reordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SegmentPosition {
+    /// Index of the segment, counting from zero.
+    pub index: u64,
+
+    /// Offset of the segment's plaintext within the pile.
+    pub offset: u64,
+}
+
+impl SegmentPosition {
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut r = [0; 16];
+        r[.. 8].copy_from_slice(&self.index.to_le_bytes());
+        r[8 ..].copy_from_slice(&self.offset.to_le_bytes());
+        r
+    }
+}
+
+/// Authenticated encryption with associated data.
+///
+/// The key, and how the actual cipher nonce is derived from a `SegmentNonce`, are up to the
+/// implementation.
+pub trait Aead {
+    /// Length of the authentication tag.
+    const TAG_LEN: usize;
+
+    /// Encrypts `buf` in place, writing the authentication tag to `tag`.
+    fn seal(&self, nonce: SegmentNonce, aad: &[u8], buf: &mut [u8], tag: &mut [u8]);
+
+    /// Decrypts `buf` in place, after checking the authentication tag.
+    ///
+    /// On failure the contents of `buf` are unspecified.
+    fn open(&self, nonce: SegmentNonce, aad: &[u8], buf: &mut [u8], tag: &[u8]) -> Result<(), AuthError>;
+}
+
+/// Authentication failed, due to either the wrong key or a modified ciphertext.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("authentication failed")]
+pub struct AuthError;
+
+/// Error returned when a sealed pile can't be opened.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SealedError {
+    #[error("segment {segment} at offset {offset} truncated")]
+    Truncated {
+        segment: u64,
+        offset: usize,
+    },
+
+    #[error("segment {segment} at offset {offset} failed authentication")]
+    Auth {
+        segment: u64,
+        offset: usize,
+    },
+}
+
+const LEN_SIZE: usize = mem::size_of::<u64>();
+const HEADER_SIZE: usize = LEN_SIZE + NONCE_LEN;
+
+/// Associated data of a segment: its header, followed by its position.
+fn segment_aad(header: &[u8], position: SegmentPosition) -> [u8; HEADER_SIZE + 16] {
+    let mut aad = [0; HEADER_SIZE + 16];
+    aad[.. HEADER_SIZE].copy_from_slice(header);
+    aad[HEADER_SIZE ..].copy_from_slice(&position.to_bytes());
+    aad
+}
+
+/// The decrypted contents of a sealed pile.
+pub struct SealedMapping {
+    plaintext: Vec<u8>,
+    segments: u64,
+}
+
+impl fmt::Debug for SealedMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Don't leak the plaintext into logs.
+        f.debug_struct("SealedMapping")
+            .field("len", &self.plaintext.len())
+            .field("segments", &self.segments)
+            .finish()
+    }
+}
+
+impl SealedMapping {
+    /// Creates an empty mapping, with no segments.
+    pub fn empty() -> Self {
+        Self {
+            plaintext: vec![],
+            segments: 0,
+        }
+    }
+
+    /// Decrypts a sealed pile.
+    pub fn open<A: Aead>(aead: &A, sealed: &[u8]) -> Result<Self, SealedError> {
+        let mut this = Self::empty();
+        this.append(aead, sealed)?;
+        Ok(this)
+    }
+
+    /// Returns the number of segments.
+    pub fn segments(&self) -> u64 {
+        self.segments
+    }
+
+    fn next_position(&self) -> SegmentPosition {
+        SegmentPosition {
+            index: self.segments,
+            offset: self.plaintext.len() as u64,
+        }
+    }
+
+    /// Seals `plaintext` as the next segment, returning the bytes to append to the sealed pile.
+    ///
+    /// The mapping itself is unchanged; use `append()` to add the sealed segment. Fails only if a
+    /// random nonce couldn't be generated.
+    pub fn seal<A: Aead>(&self, aead: &A, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = SegmentNonce::random()?;
+        let mut sealed = vec![0; HEADER_SIZE + plaintext.len() + A::TAG_LEN];
+
+        let (header, rest) = sealed.split_at_mut(HEADER_SIZE);
+        let (buf, tag) = rest.split_at_mut(plaintext.len());
+
+        header[.. LEN_SIZE].copy_from_slice(&(plaintext.len() as u64).to_le_bytes());
+        header[LEN_SIZE ..].copy_from_slice(&nonce.to_bytes());
+        buf.copy_from_slice(plaintext);
+        aead.seal(nonce, &segment_aad(header, self.next_position()), buf, tag);
+
+        Ok(sealed)
+    }
+
+    /// Decrypts and appends one or more sealed segments.
+    ///
+    /// On failure the mapping is left unchanged.
+    pub fn append<A: Aead>(&mut self, aead: &A, sealed: &[u8]) -> Result<(), SealedError> {
+        let orig_len = self.plaintext.len();
+        let orig_segments = self.segments;
+
+        let mut offset = 0;
+        while offset < sealed.len() {
+            match self.append_segment(aead, sealed, offset) {
+                Ok(end) => offset = end,
+                Err(err) => {
+                    self.plaintext.truncate(orig_len);
+                    self.segments = orig_segments;
+                    return Err(err);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the segment at `offset`, returning the offset of the next segment.
+    fn append_segment<A: Aead>(&mut self, aead: &A, sealed: &[u8], offset: usize) -> Result<usize, SealedError> {
+        let segment = self.segments;
+        let truncated = SealedError::Truncated { segment, offset };
+
+        let header = sealed.get(offset .. offset.saturating_add(HEADER_SIZE)).ok_or(truncated)?;
+        let len = u64::from_le_bytes(header[.. LEN_SIZE].try_into().unwrap());
+        let nonce = SegmentNonce(header[LEN_SIZE ..].try_into().unwrap());
+
+        let end = len.try_into().ok()
+                     .and_then(|len: usize| len.checked_add(offset + HEADER_SIZE + A::TAG_LEN))
+                     .filter(|end| *end <= sealed.len())
+                     .ok_or(truncated)?;
+
+        let ciphertext = &sealed[offset + HEADER_SIZE .. end - A::TAG_LEN];
+        let tag = &sealed[end - A::TAG_LEN .. end];
+
+        let aad = segment_aad(header, self.next_position());
+        let start = self.plaintext.len();
+        self.plaintext.extend_from_slice(ciphertext);
+
+        aead.open(nonce, &aad, &mut self.plaintext[start ..], tag)
+            .map_err(|AuthError| SealedError::Auth { segment, offset })?;
+
+        self.segments += 1;
+        Ok(end)
+    }
+}
+
+unsafe impl Mapping for SealedMapping {
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.plaintext.len()
+    }
+
+    #[inline(always)]
+    fn get(&self, range: Range<usize>) -> Option<&[u8]> {
+        self.plaintext.get(range)
+    }
+}
+
+unsafe impl SliceMapping for SealedMapping {
+    #[inline(always)]
+    fn as_bytes(&self) -> &[u8] {
+        &self.plaintext
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sha2::{Sha256, Digest as _};
+
+    use leint::Le;
+
+    use crate::pile::{TryPile, TryPileMut};
+    use crate::zone::{Alloc, OwnedPtr, TryGet};
+
+    /// Toy AEAD for testing: a SHA-256 keystream, with a truncated SHA-256 MAC.
+    ///
+    /// Not to be used for anything real.
+    #[derive(Debug)]
+    struct ToyAead([u8; 32]);
+
+    impl ToyAead {
+        fn keystream(&self, nonce: SegmentNonce, buf: &mut [u8]) {
+            for (i, chunk) in buf.chunks_mut(32).enumerate() {
+                let block = Sha256::new()
+                                .chain(&self.0)
+                                .chain(&nonce.to_bytes())
+                                .chain(&(i as u64).to_le_bytes())
+                                .result();
+                for (b, k) in chunk.iter_mut().zip(block.iter()) {
+                    *b ^= k;
+                }
+            }
+        }
+
+        fn mac(&self, nonce: SegmentNonce, aad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+            let digest = Sha256::new()
+                             .chain(&self.0)
+                             .chain(&nonce.to_bytes())
+                             .chain(aad)
+                             .chain(ciphertext)
+                             .result();
+            digest[.. 16].try_into().unwrap()
+        }
+    }
+
+    impl Aead for ToyAead {
+        const TAG_LEN: usize = 16;
+
+        fn seal(&self, nonce: SegmentNonce, aad: &[u8], buf: &mut [u8], tag: &mut [u8]) {
+            self.keystream(nonce, buf);
+            tag.copy_from_slice(&self.mac(nonce, aad, buf));
+        }
+
+        fn open(&self, nonce: SegmentNonce, aad: &[u8], buf: &mut [u8], tag: &[u8]) -> Result<(), AuthError> {
+            if self.mac(nonce, aad, buf)[..] == *tag {
+                self.keystream(nonce, buf);
+                Ok(())
+            } else {
+                Err(AuthError)
+            }
+        }
+    }
+
+    fn sealed_pile(aead: &ToyAead) -> Vec<u8> {
+        let mut mapping = SealedMapping::empty();
+        let mut sealed = vec![];
+
+        for i in 0u32 .. 3 {
+            let pile = unsafe { TryPile::from_mapping_unchecked(&mapping) };
+            let pile = TryPileMut::from(pile);
+            let plaintext = pile.encode_dirty(&pile.alloc(Le::new(i)));
+
+            let segment = mapping.seal(aead, &plaintext).unwrap();
+            mapping.append(aead, &segment).unwrap();
+            sealed.extend_from_slice(&segment);
+        }
+        sealed
+    }
+
+    #[test]
+    fn sealed_roundtrip() {
+        let aead = ToyAead([42; 32]);
+        let sealed = sealed_pile(&aead);
+
+        let mapping = SealedMapping::open(&aead, &sealed).unwrap();
+        assert_eq!(mapping.segments(), 3);
+
+        let pile = unsafe { TryPile::from_mapping_unchecked(&mapping) };
+        let tip = pile.try_get_tip::<OwnedPtr<Le<u32>, TryPile>>().unwrap();
+        assert_eq!(**pile.try_get(&tip).unwrap(), 2);
+
+        // The plaintext doesn't appear in the sealed bytes.
+        assert_ne!(&sealed[24 .. 28], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn sealed_wrong_key_or_modified() {
+        let aead = ToyAead([42; 32]);
+        let sealed = sealed_pile(&aead);
+
+        assert_eq!(SealedMapping::open(&ToyAead([43; 32]), &sealed).unwrap_err(),
+                   SealedError::Auth { segment: 0, offset: 0 });
+
+        // Flip a bit in the second segment's ciphertext.
+        let first_len = 24 + 12 + 16;
+        let mut modified = sealed.clone();
+        modified[first_len + 24] ^= 1;
+        assert_eq!(SealedMapping::open(&aead, &modified).unwrap_err(),
+                   SealedError::Auth { segment: 1, offset: first_len });
+
+        // Reordering segments changes their nonces.
+        let mut reordered = sealed[first_len ..].to_vec();
+        reordered.extend_from_slice(&sealed[.. first_len]);
+        assert!(matches!(SealedMapping::open(&aead, &reordered),
+                         Err(SealedError::Auth { segment: 0, .. })));
+
+        // So does modifying a nonce.
+        let mut modified = sealed.clone();
+        modified[first_len + 8] ^= 1;
+        assert_eq!(SealedMapping::open(&aead, &modified).unwrap_err(),
+                   SealedError::Auth { segment: 1, offset: first_len });
+
+        let err = SealedMapping::open(&aead, &sealed[.. sealed.len() - 1]).unwrap_err();
+        assert!(matches!(err, SealedError::Truncated { segment: 2, .. }));
+    }
+
+    #[test]
+    fn sealed_nonces_unique() {
+        let aead = ToyAead([42; 32]);
+        let mapping = SealedMapping::empty();
+
+        // Same plaintext, same position.
+        let segment1 = mapping.seal(&aead, &[1, 2, 3, 4]).unwrap();
+        let segment2 = mapping.seal(&aead, &[1, 2, 3, 4]).unwrap();
+
+        assert_ne!(&segment1[8 .. 24], &segment2[8 .. 24]);
+        assert_ne!(&segment1[24 .. 28], &segment2[24 .. 28]);
+
+        // Either can be opened.
+        for segment in &[segment1, segment2] {
+            let mapping = SealedMapping::open(&aead, segment).unwrap();
+            assert_eq!(mapping.as_bytes(), &[1, 2, 3, 4]);
+        }
+    }
+}
@@ -1,14 +1,9 @@
-use core::any::Any;
-use core::fmt;
-use core::mem;
 use core::marker::PhantomData;
 use core::ops;
 use core::slice::SliceIndex;
 use core::slice;
 
-use std::sync::Arc;
-
-use super::Offset;
+use super::mapping::{Mapping, SliceMapping};
 
 #[derive(Debug, Clone)]
 pub struct Snapshot<'p, M: ?Sized = dyn Mapping> {
@@ -19,30 +14,27 @@ pub struct Snapshot<'p, M: ?Sized = dyn Mapping> {
     mapping: M,
 }
 
-pub unsafe trait Mapping : fmt::Debug + Any + Sync {
-    fn as_bytes(&self) -> &[u8];
-}
+unsafe impl<M: Sync> Sync for Snapshot<'_, M> {}
 
-unsafe impl Mapping for &'static [u8] {
-    fn as_bytes(&self) -> &[u8] {
-        self
+unsafe impl<M: ?Sized + Mapping> Mapping for Snapshot<'_, M> {
+    #[inline(always)]
+    fn len(&self) -> usize {
+        self.slice_len
     }
-}
 
-unsafe impl Mapping for Vec<u8> {
-    fn as_bytes(&self) -> &[u8] {
-        &self[..]
+    #[inline(always)]
+    fn get(&self, range: ops::Range<usize>) -> Option<&[u8]> {
+        self[..].get(range)
     }
 }
 
-unsafe impl<M: Mapping + Send> Mapping for Arc<M> {
+unsafe impl<M: ?Sized + Mapping> SliceMapping for Snapshot<'_, M> {
+    #[inline(always)]
     fn as_bytes(&self) -> &[u8] {
-        (**self).as_bytes()
+        self
     }
 }
 
-unsafe impl<M: Sync> Sync for Snapshot<'_, M> {}
-
 pub static EMPTY_SNAPSHOT: Snapshot<&'static [u8]> =
     Snapshot {
 	marker: PhantomData,
@@ -52,7 +44,7 @@ pub static EMPTY_SNAPSHOT: Snapshot<&'static [u8]> =
     };
 
 
-impl<'m, M: Mapping> Snapshot<'m, M> {
+impl<'m, M: SliceMapping> Snapshot<'m, M> {
     pub unsafe fn new_unchecked(mapping: M) -> Self {
         Self::new_unchecked_with_range(mapping, ..).unwrap()
     }
@@ -0,0 +1,236 @@
+//! Space usage statistics.
+//!
+//! `space_stats()` walks everything reachable from a root, the same way validation does, and
+//! tallies the blobs found by type. Blobs reachable from more than one parent are only counted
+//! once; the bytes saved by that sharing are reported separately.
+
+use std::any::type_name;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::marshal::PtrValidator;
+use crate::marshal::blob::{BlobError, ValidateBlob, padding::CountPadding};
+use crate::marshal::decode::Decode;
+use crate::marshal::load::{Load, PersistPointee, ValidatePointeeChildren};
+use crate::pointee::Pointee;
+use crate::zone::FatPtr;
+
+use super::{TryPile, PileZone, Offset, get_blob_impl};
+use super::error::{Error, ErrorKind};
+
+/// Blob count and size, for a single type.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TypeStats {
+    pub blobs: usize,
+    pub bytes: usize,
+}
+
+/// Space usage of everything reachable from a root.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SpaceStats {
+    types: BTreeMap<&'static str, TypeStats>,
+    padding: usize,
+    shared: TypeStats,
+}
+
+impl SpaceStats {
+    /// Returns the statistics of each type, by type name.
+    pub fn types(&self) -> &BTreeMap<&'static str, TypeStats> {
+        &self.types
+    }
+
+    /// Returns the statistics of a single type.
+    pub fn get<T: ?Sized>(&self) -> TypeStats {
+        self.types.get(type_name::<T>()).copied().unwrap_or_default()
+    }
+
+    /// Returns the total of all types.
+    pub fn total(&self) -> TypeStats {
+        self.types.values().fold(TypeStats::default(), |total, stats| {
+            TypeStats {
+                blobs: total.blobs + stats.blobs,
+                bytes: total.bytes + stats.bytes,
+            }
+        })
+    }
+
+    /// Returns the number of padding bytes, as reported to the `PaddingValidator`.
+    pub fn padding(&self) -> usize {
+        self.padding
+    }
+
+    /// Returns the extra references to, and bytes of, blobs reachable from more than one parent.
+    ///
+    /// A blob referenced three times counts as two extra references.
+    pub fn shared(&self) -> TypeStats {
+        self.shared
+    }
+}
+
+impl fmt::Display for SpaceStats {
+    /// Lists types from largest to smallest.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut types: Vec<_> = self.types.iter().collect();
+        types.sort_by(|(_, a), (_, b)| b.bytes.cmp(&a.bytes));
+
+        for (name, stats) in types {
+            writeln!(f, "{:>12} bytes {:>8} blobs  {}", stats.bytes, stats.blobs, name)?;
+        }
+
+        let total = self.total();
+        writeln!(f, "{:>12} bytes {:>8} blobs  total", total.bytes, total.blobs)?;
+        writeln!(f, "{:>12} bytes           padding", self.padding)?;
+        write!(f, "{:>12} bytes {:>8} refs   shared", self.shared.bytes, self.shared.blobs)
+    }
+}
+
+struct StatsValidator<'z, 'p, 'v, Z> {
+    marker: PhantomData<TryPile<'p, 'v>>,
+    zone: &'z Z,
+    stats: RefCell<SpaceStats>,
+    seen: RefCell<HashSet<(usize, usize, &'static str)>>,
+}
+
+impl<'z, 'p, 'v, Z> StatsValidator<'z, 'p, 'v, Z>
+where Z: PileZone<'p, 'v>
+{
+    /// Tallies a blob, returning it if it hasn't been seen before.
+    fn visit<T>(&self, ptr: &FatPtr<T, Z::Persist>) -> Result<Option<&'p T::Persist>, Error<'p, 'v>>
+        where T: ?Sized + PersistPointee
+    {
+        let blob = get_blob_impl(self.zone, ptr)?;
+        let size = T::try_layout(ptr.metadata).map(|layout| layout.size())
+                      .expect("get_blob_impl() checks the layout");
+
+        let mut stats = self.stats.borrow_mut();
+        if !self.seen.borrow_mut().insert((ptr.raw.into(), size, type_name::<T>())) {
+            stats.shared.blobs += 1;
+            stats.shared.bytes += size;
+            return Ok(None)
+        }
+
+        let padding = Cell::new(0);
+        let value = match T::Persist::validate(blob.into_cursor_with(CountPadding::new(&padding))) {
+            Ok(valid_blob) => valid_blob.to_ref(),
+            Err(BlobError::Error(err)) => return Err(Error::new(self.zone, ptr, ErrorKind::Value(err.into()))),
+            Err(BlobError::Padding(never)) => match never {},
+        };
+
+        stats.padding += padding.get();
+        let type_stats = stats.types.entry(type_name::<T>()).or_default();
+        type_stats.blobs += 1;
+        type_stats.bytes += size;
+
+        Ok(Some(value))
+    }
+}
+
+impl<'z, 'p, 'v, Z> PtrValidator<Z> for StatsValidator<'z, 'p, 'v, Z>
+where Z: PileZone<'p, 'v>
+{
+    type Error = Error<'p, 'v>;
+
+    fn validate_ptr<'a, T>(&self, ptr: &'a FatPtr<T::Persist, Z::Persist>) -> Result<Option<&'a T::Persist>, Self::Error>
+        where T: ?Sized + ValidatePointeeChildren<'a, Z>,
+    {
+        let ptr = FatPtr::<T, Z::Persist> { raw: ptr.raw, metadata: ptr.metadata };
+        let value = self.visit(&ptr)?;
+
+        // SAFETY: The validator is private to space_stats(), which only ever calls this with
+        // pointers found in the pile itself, so 'a can't outlive 'p.
+        Ok(value.map(|value| unsafe { &*(value as *const T::Persist) }))
+    }
+}
+
+/// Calculates the space used by everything reachable from `ptr`, including the value it points to.
+pub fn space_stats<'p, 'v, T, Z>(zone: &Z, ptr: &FatPtr<T, Z::Persist>) -> Result<SpaceStats, Error<'p, 'v>>
+    where T: ?Sized + Load<Z>,
+          Z: PileZone<'p, 'v>,
+{
+    let validator = StatsValidator {
+        marker: PhantomData,
+        zone,
+        stats: RefCell::default(),
+        seen: RefCell::default(),
+    };
+
+    if let Some(value) = validator.visit(ptr)? {
+        let mut state = T::validate_children(value);
+        T::poll(value, &mut state, &validator)?;
+    }
+
+    Ok(validator.stats.into_inner())
+}
+
+impl<'p, 'v> TryPile<'p, 'v> {
+    /// Calculates the space used by the value at the tip of the pile, and everything reachable
+    /// from it.
+    pub fn space_stats_tip<T: Decode<Self>>(&self) -> Result<SpaceStats, Error<'p, 'v>> {
+        let offset = self.len().saturating_sub(std::mem::size_of::<T>());
+        let ptr = FatPtr::<T, _> {
+            raw: Offset::new(offset).unwrap(),
+            metadata: (),
+        };
+        space_stats(self, &ptr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use leint::Le;
+
+    use crate::pile::TryPileMut;
+    use crate::zone::{Alloc, OwnedPtr};
+
+    #[test]
+    fn space_stats_shared() {
+        let mut bytes = {
+            let pile = TryPileMut::from(TryPile::empty());
+            pile.encode_dirty(&pile.alloc(Le::new(42u32)))
+        };
+
+        // Point to the same Le<u32> twice.
+        let ptr_bytes = bytes[4 ..].to_vec();
+        bytes.extend_from_slice(&ptr_bytes);
+        bytes.extend_from_slice(&ptr_bytes);
+
+        TryPile::new(&bytes, |pile| {
+            type Root<'p, 'v> = [OwnedPtr<Le<u32>, TryPile<'p, 'v>>; 2];
+
+            let stats = pile.space_stats_tip::<Root>().unwrap();
+            assert_eq!(stats.get::<Le<u32>>(), TypeStats { blobs: 1, bytes: 4 });
+            assert_eq!(stats.get::<Root>(), TypeStats { blobs: 1, bytes: 16 });
+            assert_eq!(stats.total(), TypeStats { blobs: 2, bytes: 20 });
+            assert_eq!(stats.shared(), TypeStats { blobs: 1, bytes: 4 });
+            assert_eq!(stats.padding(), 0);
+
+            let display = stats.to_string();
+            assert!(display.lines().next().unwrap().ends_with(type_name::<Root>()));
+
+            // The unreferenced pointer at offset 4 isn't included.
+            let stats = pile.space_stats_tip::<OwnedPtr<Le<u32>, TryPile>>().unwrap();
+            assert_eq!(stats.total(), TypeStats { blobs: 2, bytes: 12 });
+            assert_eq!(stats.shared(), TypeStats::default());
+        });
+    }
+
+    #[test]
+    fn space_stats_invalid() {
+        // Not a valid offset.
+        TryPile::new(&[0xff; 8][..], |pile| {
+            let err = pile.space_stats_tip::<OwnedPtr<Le<u32>, TryPile>>().unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::Value(_)));
+        });
+
+        // Offset 100, past the end of the pile.
+        TryPile::new(&[201, 0, 0, 0, 0, 0, 0, 0][..], |pile| {
+            let err = pile.space_stats_tip::<OwnedPtr<Le<u32>, TryPile>>().unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::Offset));
+            assert_eq!(err.offset(), 100);
+        });
+    }
+}
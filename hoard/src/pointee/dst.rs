@@ -0,0 +1,454 @@
+//! Custom dynamically sized types.
+//!
+//! `impl_pointee_for_dst!` implements `Pointee` and `IntoOwned` for `#[repr(C)]` structs whose
+//! last field is unsized. The struct must be generic over that field, so that the same struct with
+//! a zero-sized tail - the *header* - can be used to find the tail's offset:
+//!
+//! ```ignore
+//! #[repr(C)]
+//! pub struct Node<Tail: ?Sized = [Le<u32>]> {
+//!     flags: u8,
+//!     items: Tail,
+//! }
+//!
+//! hoard::impl_pointee_for_dst! {
+//!     unsafe impl[] Pointee for Node {
+//!         type Metadata = Le<u64>;
+//!         type Header = Node<[Le<u32>; 0]>;
+//!         tail = items: [Le<u32>];
+//!     }
+//! }
+//! ```
+//!
+//! There are three forms:
+//!
+//! * Slice tails, with `Box<Self>` as the owned type. The metadata is the length of the slice, as
+//!   a `SliceMetadata`.
+//!
+//! * Slice tails with zero-sized elements, with `type Owned = ...;` given. Here the slice length is
+//!   a way of attaching metadata to a pointer without storing it in the value, and the owned type
+//!   is the same struct with the metadata itself as the tail. `Borrow`, `BorrowMut` and `Take` are
+//!   implemented for the owned type.
+//!
+//! * Any other unsized tail, in which case the metadata is the tail's own `Pointee::Metadata` and
+//!   the owned type is `Box<Self>`.
+//!
+//! # Safety
+//!
+//! The header, and the owned type if given, must be the same `#[repr(C)]` struct as `Self`,
+//! differing only in the type of the tail. The header's tail must be zero-sized, with the same
+//! alignment as the elements of the slice tail; `[T; 0]` always works.
+
+use std::alloc::{self, Layout, LayoutErr};
+use std::convert::TryInto;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::ptr::{self, NonNull};
+
+use leint::Le;
+
+use super::{Metadata, Pointee};
+
+#[doc(hidden)]
+pub use owned::{IntoOwned, Take};
+
+/// Metadata that is the length of a slice.
+pub trait SliceMetadata : Metadata + Copy {
+    /// Converts a slice length to metadata, returning `None` if the length is out of range.
+    fn from_len(len: usize) -> Option<Self>;
+
+    /// Converts the metadata to a slice length.
+    fn to_len(self) -> usize;
+}
+
+impl SliceMetadata for Le<u64> {
+    #[inline(always)]
+    fn from_len(len: usize) -> Option<Self> {
+        Some(Le::new(len.try_into().ok()?))
+    }
+
+    #[inline(always)]
+    fn to_len(self) -> usize {
+        self.get().try_into().expect("slice length overflowed usize")
+    }
+}
+
+/// Returns the offset of the zero-sized tail of a header.
+///
+/// # Safety
+///
+/// `field` must return a pointer to a field of the header it's given, without reading it.
+#[doc(hidden)]
+#[inline(always)]
+pub unsafe fn tail_offset<H, F>(field: impl FnOnce(*const H) -> *const F) -> usize {
+    assert_eq!(mem::size_of::<F>(), 0, "header tail must be zero-sized");
+
+    let header = MaybeUninit::<H>::uninit();
+    let base = header.as_ptr();
+    field(base) as usize - base as usize
+}
+
+/// Returns the layout of a struct with a slice tail.
+#[doc(hidden)]
+#[inline(always)]
+pub fn slice_tail_layout<H, E>(offset: usize, len: usize) -> Result<Layout, LayoutErr> {
+    let header = Layout::from_size_align(offset, mem::align_of::<H>())?;
+    let (layout, _) = header.extend(Layout::array::<E>(len)?)?;
+    Ok(layout.pad_to_align())
+}
+
+/// Returns the layout of a struct with an unsized tail.
+#[doc(hidden)]
+#[inline(always)]
+pub fn dst_tail_layout<H, T: ?Sized + Pointee>(offset: usize, metadata: T::Metadata)
+    -> Result<Layout, DstLayoutError<T::LayoutError>>
+{
+    let tail = T::try_layout(metadata).map_err(DstLayoutError::Tail)?;
+    let header = Layout::from_size_align(offset, mem::align_of::<H>())?;
+    let (layout, _) = header.extend(tail)?;
+    Ok(layout.pad_to_align())
+}
+
+/// Error returned when the layout of a struct with an unsized tail is invalid.
+#[derive(thiserror::Error, Debug)]
+pub enum DstLayoutError<E: 'static + std::error::Error> {
+    #[error("invalid tail layout")]
+    Tail(#[source] E),
+
+    #[error(transparent)]
+    Layout(#[from] LayoutErr),
+}
+
+/// Makes a raw slice pointer, for use in `make_fat_ptr()`.
+#[doc(hidden)]
+#[inline(always)]
+pub fn slice_ptr<E>(thin: *const (), len: usize) -> *const [E] {
+    ptr::slice_from_raw_parts(thin as *const E, len)
+}
+
+/// Makes a mutable raw slice pointer, for use in `make_fat_ptr_mut()`.
+#[doc(hidden)]
+#[inline(always)]
+pub fn slice_ptr_mut<E>(thin: *mut (), len: usize) -> *mut [E] {
+    ptr::slice_from_raw_parts_mut(thin as *mut E, len)
+}
+
+/// Moves an unsized value into a new `Box`.
+///
+/// # Safety
+///
+/// The value is moved: the caller must not drop it, or use it again.
+#[doc(hidden)]
+pub unsafe fn into_boxed<T: ?Sized + Pointee>(this: &mut ManuallyDrop<T>) -> Box<T> {
+    let this: &T = &**this;
+    let metadata = T::metadata(this);
+    let layout = Layout::for_value(this);
+
+    let dst = if layout.size() == 0 {
+        NonNull::new_unchecked(layout.align() as *mut u8)
+    } else {
+        NonNull::new(alloc::alloc(layout))
+                .unwrap_or_else(|| alloc::handle_alloc_error(layout))
+    };
+    ptr::copy_nonoverlapping(this as *const T as *const u8, dst.as_ptr(), layout.size());
+
+    Box::from_raw(T::make_fat_ptr_mut(dst.as_ptr() as *mut (), metadata))
+}
+
+/// Moves an unsized value with a zero-sized tail into its sized, owned, form.
+///
+/// # Safety
+///
+/// `O` must be a valid owned form of `T` - see the module docs - and `set_tail` must write the
+/// metadata to the tail of the `O` it's given, without reading it.
+#[doc(hidden)]
+pub unsafe fn into_owned_with_tail<T, O>(this: &mut ManuallyDrop<T>, set_tail: impl FnOnce(*mut O, T::Metadata)) -> O
+    where T: ?Sized + Pointee
+{
+    let this: &T = &**this;
+    let metadata = T::metadata(this);
+    let size = mem::size_of_val(this);
+    assert!(size <= mem::size_of::<O>());
+
+    let mut owned = MaybeUninit::<O>::uninit();
+    ptr::copy_nonoverlapping(this as *const T as *const u8, owned.as_mut_ptr() as *mut u8, size);
+    set_tail(owned.as_mut_ptr(), metadata);
+    owned.assume_init()
+}
+
+/// Implements `Pointee` and `IntoOwned` for a `#[repr(C)]` struct with an unsized tail.
+///
+/// See the `pointee::dst` module docs for details.
+#[macro_export]
+macro_rules! impl_pointee_for_dst {
+    (
+        unsafe impl[$($gen:tt)*] Pointee for $t:ty {
+            type Metadata = $m:ty;
+            type Header = $h:ty;
+            tail = $tail:ident: [$elem:ty];
+        }
+    ) => {
+        $crate::impl_pointee_for_dst!(@slice [$($gen)*] $t, $m, $h, $tail, $elem);
+
+        unsafe impl<$($gen)*> $crate::pointee::dst::IntoOwned for $t {
+            type Owned = ::std::boxed::Box<Self>;
+
+            #[inline]
+            unsafe fn into_owned_unchecked(this: &mut ::core::mem::ManuallyDrop<Self>) -> Self::Owned {
+                $crate::pointee::dst::into_boxed(this)
+            }
+        }
+    };
+
+    (
+        unsafe impl[$($gen:tt)*] Pointee for $t:ty {
+            type Metadata = $m:ty;
+            type Header = $h:ty;
+            type Owned = $o:ty;
+            tail = $tail:ident: [$elem:ty];
+        }
+    ) => {
+        $crate::impl_pointee_for_dst!(@slice [$($gen)*] $t, $m, $h, $tail, $elem);
+
+        unsafe impl<$($gen)*> $crate::pointee::dst::IntoOwned for $t {
+            type Owned = $o;
+
+            #[inline]
+            unsafe fn into_owned_unchecked(this: &mut ::core::mem::ManuallyDrop<Self>) -> Self::Owned {
+                assert_eq!(::core::mem::size_of::<$elem>(), 0, "tail elements must be zero-sized");
+                $crate::pointee::dst::into_owned_with_tail(this, |owned: *mut $o, metadata| {
+                    ::core::ptr::write(&mut (*owned).$tail, metadata)
+                })
+            }
+        }
+
+        impl<$($gen)*> ::core::borrow::Borrow<$t> for $o {
+            #[inline(always)]
+            fn borrow(&self) -> &$t {
+                assert_eq!(::core::mem::size_of::<$elem>(), 0, "tail elements must be zero-sized");
+                let thin = self as *const Self as *const ();
+                unsafe { &*<$t as $crate::pointee::Pointee>::make_fat_ptr(thin, self.$tail) }
+            }
+        }
+
+        impl<$($gen)*> ::core::borrow::BorrowMut<$t> for $o {
+            #[inline(always)]
+            fn borrow_mut(&mut self) -> &mut $t {
+                assert_eq!(::core::mem::size_of::<$elem>(), 0, "tail elements must be zero-sized");
+                let metadata = self.$tail;
+                let thin = self as *mut Self as *mut ();
+                unsafe { &mut *<$t as $crate::pointee::Pointee>::make_fat_ptr_mut(thin, metadata) }
+            }
+        }
+
+        unsafe impl<$($gen)*> $crate::pointee::dst::Take<$t> for $o {
+            #[inline(always)]
+            fn take_unsized<F, R>(self, f: F) -> R
+                where F: FnOnce(&mut ::core::mem::ManuallyDrop<$t>) -> R
+            {
+                let mut this = ::core::mem::ManuallyDrop::new(self);
+                let this: &mut $t = ::core::borrow::BorrowMut::borrow_mut(&mut *this);
+                f(unsafe { &mut *(this as *mut $t as *mut ::core::mem::ManuallyDrop<$t>) })
+            }
+        }
+    };
+
+    (
+        unsafe impl[$($gen:tt)*] Pointee for $t:ty {
+            type Metadata = $m:ty;
+            type Header = $h:ty;
+            tail = $tail:ident: $tail_ty:ty;
+        }
+    ) => {
+        unsafe impl<$($gen)*> $crate::pointee::Pointee for $t {
+            type Metadata = $m;
+            type LayoutError = $crate::pointee::dst::DstLayoutError<<$tail_ty as $crate::pointee::Pointee>::LayoutError>;
+
+            #[inline]
+            fn try_layout(metadata: $m) -> Result<::core::alloc::Layout, Self::LayoutError> {
+                let offset = unsafe {
+                    $crate::pointee::dst::tail_offset(|header: *const $h| &(*header).$tail)
+                };
+                $crate::pointee::dst::dst_tail_layout::<$h, $tail_ty>(offset, metadata)
+            }
+
+            #[inline(always)]
+            fn metadata_from_dropped(dropped: &$crate::pointee::MaybeDropped<Self>) -> $m {
+                let tail = unsafe { $crate::pointee::MaybeDropped::from_ref(&dropped.get_unchecked().$tail) };
+                <$tail_ty as $crate::pointee::Pointee>::metadata_from_dropped(tail)
+            }
+
+            #[inline(always)]
+            fn make_fat_ptr(thin: *const (), metadata: $m) -> *const Self {
+                <$tail_ty as $crate::pointee::Pointee>::make_fat_ptr(thin, metadata) as *const Self
+            }
+
+            #[inline(always)]
+            fn make_fat_ptr_mut(thin: *mut (), metadata: $m) -> *mut Self {
+                <$tail_ty as $crate::pointee::Pointee>::make_fat_ptr_mut(thin, metadata) as *mut Self
+            }
+        }
+
+        unsafe impl<$($gen)*> $crate::pointee::dst::IntoOwned for $t {
+            type Owned = ::std::boxed::Box<Self>;
+
+            #[inline]
+            unsafe fn into_owned_unchecked(this: &mut ::core::mem::ManuallyDrop<Self>) -> Self::Owned {
+                $crate::pointee::dst::into_boxed(this)
+            }
+        }
+    };
+
+    (@slice [$($gen:tt)*] $t:ty, $m:ty, $h:ty, $tail:ident, $elem:ty) => {
+        unsafe impl<$($gen)*> $crate::pointee::Pointee for $t {
+            type Metadata = $m;
+            type LayoutError = ::core::alloc::LayoutErr;
+
+            #[inline]
+            fn try_layout(metadata: $m) -> Result<::core::alloc::Layout, ::core::alloc::LayoutErr> {
+                let offset = unsafe {
+                    $crate::pointee::dst::tail_offset(|header: *const $h| &(*header).$tail)
+                };
+                let len = $crate::pointee::dst::SliceMetadata::to_len(metadata);
+                $crate::pointee::dst::slice_tail_layout::<$h, $elem>(offset, len)
+            }
+
+            #[inline(always)]
+            fn metadata_from_dropped(dropped: &$crate::pointee::MaybeDropped<Self>) -> $m {
+                let len = unsafe { dropped.get_unchecked().$tail.len() };
+                match $crate::pointee::dst::SliceMetadata::from_len(len) {
+                    Some(metadata) => metadata,
+                    None => unreachable!("slice length {} out of range", len),
+                }
+            }
+
+            #[inline(always)]
+            fn make_fat_ptr(thin: *const (), metadata: $m) -> *const Self {
+                let len = $crate::pointee::dst::SliceMetadata::to_len(metadata);
+                $crate::pointee::dst::slice_ptr::<$elem>(thin, len) as *const Self
+            }
+
+            #[inline(always)]
+            fn make_fat_ptr_mut(thin: *mut (), metadata: $m) -> *mut Self {
+                let len = $crate::pointee::dst::SliceMetadata::to_len(metadata);
+                $crate::pointee::dst::slice_ptr_mut::<$elem>(thin, len) as *mut Self
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::borrow::{Borrow, BorrowMut};
+
+    use crate::pointee::MaybeDropped;
+
+    #[repr(C)]
+    #[derive(Debug)]
+    struct Items<Tail: ?Sized = [u16]> {
+        flags: u8,
+        n: u32,
+        items: Tail,
+    }
+
+    crate::impl_pointee_for_dst! {
+        unsafe impl[] Pointee for Items {
+            type Metadata = Le<u64>;
+            type Header = Items<[u16; 0]>;
+            tail = items: [u16];
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Debug)]
+    struct Tagged<Tail: ?Sized = Items> {
+        tag: u8,
+        inner: Tail,
+    }
+
+    crate::impl_pointee_for_dst! {
+        unsafe impl[] Pointee for Tagged {
+            type Metadata = Le<u64>;
+            type Header = Tagged<[u32; 0]>;
+            tail = inner: Items;
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Debug)]
+    struct Heighted<T, Tail: ?Sized = [()]> {
+        value: T,
+        height: Tail,
+    }
+
+    crate::impl_pointee_for_dst! {
+        unsafe impl[T] Pointee for Heighted<T> {
+            type Metadata = Le<u64>;
+            type Header = Heighted<T, ()>;
+            type Owned = Heighted<T, Le<u64>>;
+            tail = height: [()];
+        }
+    }
+
+    fn items<'a>(sized: &'a Items<[u16; 3]>) -> &'a Items {
+        sized
+    }
+
+    #[test]
+    fn slice_tail() {
+        assert_eq!(<Items>::try_layout(0.into()).unwrap(), Layout::from_size_align(8, 4).unwrap());
+        assert_eq!(<Items>::try_layout(2.into()).unwrap(), Layout::from_size_align(12, 4).unwrap());
+        assert_eq!(<Items>::try_layout(3.into()).unwrap(), Layout::from_size_align(16, 4).unwrap());
+        assert!(<Items>::try_layout(u64::max_value().into()).is_err());
+
+        let sized: Items<[u16; 3]> = Items { flags: 1, n: 3, items: [10, 11, 12] };
+        let unsized_items = items(&sized);
+        assert_eq!(<Items>::metadata(unsized_items), 3);
+
+        let ptr = <Items>::make_fat_ptr(&sized as *const _ as *const (), 3.into());
+        let unsized_items = unsafe { &*ptr };
+        assert_eq!(unsized_items.items, [10, 11, 12]);
+        assert_eq!(mem::size_of_val(unsized_items), 16);
+
+        let mut this = ManuallyDrop::new(sized);
+        let this: &mut Items = &mut *this;
+        let this = unsafe { &mut *(this as *mut Items as *mut ManuallyDrop<Items>) };
+        let boxed: Box<Items> = unsafe { <Items as IntoOwned>::into_owned_unchecked(this) };
+        assert_eq!(boxed.flags, 1);
+        assert_eq!(boxed.items, [10, 11, 12]);
+    }
+
+    #[test]
+    fn dst_tail() {
+        assert_eq!(<Tagged>::try_layout(0.into()).unwrap(), Layout::from_size_align(12, 4).unwrap());
+        assert_eq!(<Tagged>::try_layout(1.into()).unwrap(), Layout::from_size_align(16, 4).unwrap());
+        assert!(<Tagged>::try_layout(u64::max_value().into()).is_err());
+
+        let sized: Tagged<Items<[u16; 2]>> = Tagged { tag: 7, inner: Items { flags: 1, n: 2, items: [10, 11] } };
+        let tagged = unsafe { &*<Tagged>::make_fat_ptr(&sized as *const _ as *const (), 2.into()) };
+        assert_eq!(<Tagged>::metadata(tagged), 2);
+        assert_eq!(tagged.inner.items, [10, 11]);
+        assert_eq!(mem::size_of_val(tagged), mem::size_of_val(&sized));
+    }
+
+    #[test]
+    fn zero_sized_tail() {
+        assert_eq!(<Heighted<Le<u32>>>::try_layout(5.into()).unwrap(), Layout::new::<Le<u32>>());
+
+        let mut owned = Heighted { value: Le::new(42u32), height: Le::new(5u64) };
+        let borrowed: &Heighted<Le<u32>> = owned.borrow();
+        assert_eq!(borrowed.value, 42);
+        assert_eq!(Pointee::metadata(borrowed), 5);
+
+        let borrowed: &mut Heighted<Le<u32>> = owned.borrow_mut();
+        borrowed.value = 43.into();
+
+        let owned2 = Take::<Heighted<Le<u32>>>::take_owned(owned);
+        assert_eq!(owned2.value, 43);
+        assert_eq!(owned2.height, 5);
+
+        let dropped = MaybeDropped::from_ref(owned2.borrow());
+        assert_eq!(<Heighted<Le<u32>>>::metadata_from_dropped(dropped), 5);
+    }
+}
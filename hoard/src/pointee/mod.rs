@@ -13,14 +13,59 @@ use leint::Le;
 mod maybedropped;
 pub use self::maybedropped::MaybeDropped;
 
+pub mod dst;
+
 pub trait Metadata : 'static + crate::marshal::Primitive + fmt::Debug + Send + Sync {
     fn kind(&self) -> MetadataKind;
 }
 
-#[derive(Debug)]
+/// A description of pointer metadata, for error messages and debug output.
+///
+/// The `Display` implementation describes the metadata as a phrase, eg "height 17".
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum MetadataKind {
+    /// No metadata: the pointee is `Sized`.
     Sized,
+
+    /// A length.
     Len(u64),
+
+    /// The height of a tree node.
+    Height(u64),
+
+    /// User-defined metadata, made up of named fields.
+    Struct {
+        name: &'static str,
+        fields: Vec<(&'static str, MetadataKind)>,
+    },
+}
+
+impl MetadataKind {
+    pub fn is_sized(&self) -> bool {
+        match self {
+            MetadataKind::Sized => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for MetadataKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetadataKind::Sized => write!(f, "sized"),
+            MetadataKind::Len(len) => write!(f, "length {}", len),
+            MetadataKind::Height(height) => write!(f, "height {}", height),
+            MetadataKind::Struct { name, fields } => {
+                write!(f, "{} {{", name)?;
+                for (i, (field, kind)) in fields.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{}: {}", sep, field, kind)?;
+                }
+                write!(f, " }}")
+            },
+        }
+    }
 }
 
 impl Metadata for () {
@@ -30,6 +75,13 @@ impl Metadata for () {
     }
 }
 
+impl Metadata for Le<u64> {
+    #[inline(always)]
+    fn kind(&self) -> MetadataKind {
+        MetadataKind::Len(self.get())
+    }
+}
+
 /// A target of a pointer.
 ///
 /// # Safety
@@ -137,4 +189,17 @@ mod tests {
     #[test]
     fn test_layout() {
     }
+
+    #[test]
+    fn metadata_kind_display() {
+        assert_eq!(().kind().to_string(), "sized");
+        assert_eq!(Le::new(3u64).kind().to_string(), "length 3");
+        assert_eq!(MetadataKind::Height(17).to_string(), "height 17");
+
+        let kind = MetadataKind::Struct {
+            name: "Span",
+            fields: vec![("start", MetadataKind::Len(1)), ("height", MetadataKind::Height(2))],
+        };
+        assert_eq!(kind.to_string(), "Span { start: length 1, height: height 2 }");
+    }
 }
@@ -0,0 +1,293 @@
+//! Conformance tests for marshal implementations.
+//!
+//! Every `ValidateBlob`/`Encode`/`Decode` implementation should pass the same battery of checks,
+//! so rather than each crate reinventing them, they're collected here. Enable with the `testing`
+//! feature, usually as a dev-dependency:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! hoard = { path = "../hoard", features = ["testing"] }
+//! ```
+//!
+//! Then give `check_marshal` a `TestDumper` for the zone, and a value generator:
+//!
+//! ```ignore
+//! hoard::testing::check_marshal(&NeverDumper, |rng| Le::new(rng.next_u64() as u32));
+//! ```
+//!
+//! `NeverDumper` encodes values in the `!` zone, which can't have pointers. To check values with
+//! pointers, encode them in a pile instead: `TryPileMut` is a `TestDumper` too, writing everything
+//! the value points to ahead of it.
+//!
+//! Values with pointers usually can't be compared, so the checks compare encodings instead: a
+//! decoded value has to re-encode to the bytes it was decoded from.
+
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::mem;
+
+use crate::marshal::blob::{Blob, BlobError, ValidateBlob};
+use crate::marshal::blob::padding::PaddingError;
+use crate::marshal::decode::{Decode, Persist};
+use crate::marshal::encode::{Encode, Encoded};
+use crate::pile::TryPileMut;
+
+/// Number of generated values, and of random blobs, that `check_marshal` checks.
+pub const ROUNDS: usize = 256;
+
+/// Deterministic xorshift pseudo-random number generator.
+///
+/// Not remotely cryptographic; it just makes failures reproducible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // An all-zero state would only ever produce zeros.
+        Self(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    pub fn next_bool(&mut self) -> bool {
+        self.next_u64() & 1 == 1
+    }
+
+    pub fn fill_bytes(&mut self, dst: &mut [u8]) {
+        for chunk in dst.chunks_mut(8) {
+            let n = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&n[.. chunk.len()]);
+        }
+    }
+}
+
+/// Encodes values in the zone `Z` for the checks.
+///
+/// # Safety
+///
+/// The checks decode values without validating their children, so values decoded in `Z` must be
+/// safe to use regardless of what their pointers point to; eg because every load through them is
+/// validated anyway.
+pub unsafe trait TestDumper<Z> {
+    /// Encodes `value`, along with anything it points to.
+    ///
+    /// The encoding of `value` itself must come last.
+    fn encode<'a, T: Encode<'a, Z>>(&self, value: &'a T) -> Vec<u8>;
+}
+
+/// Encodes values in the `!` zone, which can't have pointers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NeverDumper;
+
+unsafe impl TestDumper<!> for NeverDumper {
+    fn encode<'a, T: Encode<'a, !>>(&self, value: &'a T) -> Vec<u8> {
+        let state = value.make_encode_state();
+        match value.encode_blob(&state, vec![]) {
+            Ok(buf) => buf,
+            Err(never) => never,
+        }
+    }
+}
+
+// SAFETY: every load from a TryPileMut is validated.
+unsafe impl<'p, 'v> TestDumper<TryPileMut<'p, 'v>> for TryPileMut<'p, 'v> {
+    fn encode<'a, T: Encode<'a, Self>>(&self, value: &'a T) -> Vec<u8> {
+        self.encode_dirty(value)
+    }
+}
+
+/// Encodes a value, returning just the encoding of the value itself.
+pub fn encode<'a, Z, D, T>(dumper: &D, value: &'a T) -> Vec<u8>
+    where D: TestDumper<Z>,
+          T: Encode<'a, Z>,
+{
+    let mut buf = dumper.encode(value);
+    let size = mem::size_of::<T::Encoded>();
+    assert!(buf.len() >= size, "encoding of value is smaller than Encoded");
+    buf.drain(.. buf.len() - size);
+    buf
+}
+
+/// Validates and decodes a value, checking padding.
+///
+/// The value's children are *not* validated; see `TestDumper`.
+pub fn try_decode<Z, D, T>(_: &D, buf: &[u8]) -> Result<T, BlobError<T::Error, PaddingError>>
+    where D: TestDumper<Z>,
+          T: Decode<Z>,
+{
+    let blob = Blob::<T::Persist>::try_from(buf).expect("buffer is the wrong size");
+    let valid = T::Persist::validate(blob.into_cursor())?;
+
+    // SAFETY: validated above, and the TestDumper guarantees that T's children don't need to be.
+    Ok(unsafe { T::assume_valid(valid.to_ref()) })
+}
+
+/// Validates and decodes a value, ignoring padding.
+///
+/// The value's children are *not* validated; see `TestDumper`.
+pub fn try_decode_ignore_padding<Z, D, T>(_: &D, buf: &[u8]) -> Result<T, T::Error>
+    where D: TestDumper<Z>,
+          T: Decode<Z>,
+{
+    let blob = Blob::<T::Persist>::try_from(buf).expect("buffer is the wrong size");
+    match T::Persist::validate(blob.into_cursor_ignore_padding()) {
+        // SAFETY: validated, and the TestDumper guarantees that T's children don't need to be.
+        Ok(valid) => Ok(unsafe { T::assume_valid(valid.to_ref()) }),
+        Err(BlobError::Error(err)) => Err(err),
+        Err(BlobError::Padding(never)) => never,
+    }
+}
+
+/// Checks that encoding a value, then decoding it, gives a value with the same encoding; and that
+/// the encoding has the size of `Encoded`.
+pub fn check_roundtrip<Z, D, T>(dumper: &D, value: &T)
+    where D: TestDumper<Z>,
+          T: Decode<Z> + for<'a> Encode<'a, Z> + Debug
+{
+    let buf = encode(dumper, value);
+    assert_eq!(buf.len(), mem::size_of::<<T as Encoded<Z>>::Encoded>(),
+               "encoding of {:?} is not the size of Encoded", value);
+    assert_eq!(buf.len(), mem::size_of::<T::Persist>(),
+               "encoding of {:?} is not the size of Persist", value);
+
+    match try_decode::<Z, D, T>(dumper, &buf) {
+        Ok(decoded) => assert_eq!(encode(dumper, &decoded), buf,
+                                  "decoding {:?} changed its encoding to that of {:?}", value, decoded),
+        Err(err) => panic!("encoding of {:?} failed to validate: {:?}", value, err),
+    }
+}
+
+/// Checks that non-zero padding in the encoding of a value is rejected.
+///
+/// A byte is considered padding if changing it leaves the encoding of the decoded value unchanged.
+pub fn check_padding<Z, D, T>(dumper: &D, value: &T)
+    where D: TestDumper<Z>,
+          T: Decode<Z> + for<'a> Encode<'a, Z> + Debug
+{
+    let buf = encode(dumper, value);
+    for i in 0 .. buf.len() {
+        let mut modified = buf.clone();
+        modified[i] ^= 0xff;
+
+        match try_decode_ignore_padding::<Z, D, T>(dumper, &modified) {
+            Ok(ref decoded) if encode(dumper, decoded) == buf => {
+                assert!(try_decode::<Z, D, T>(dumper, &modified).is_err(),
+                        "non-zero padding at byte {} of {:?} accepted", i, value);
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Checks that validating random bytes never panics, and that anything valid re-encodes to the
+/// same bytes.
+pub fn check_random_blobs<Z, D, T>(dumper: &D, rng: &mut Rng, count: usize)
+    where D: TestDumper<Z>,
+          T: Decode<Z> + for<'a> Encode<'a, Z> + Debug
+{
+    let mut buf = vec![0; mem::size_of::<T::Persist>()];
+    for _ in 0 .. count {
+        rng.fill_bytes(&mut buf);
+
+        let _ = try_decode_ignore_padding::<Z, D, T>(dumper, &buf);
+        if let Ok(value) = try_decode::<Z, D, T>(dumper, &buf) {
+            assert_eq!(encode(dumper, &value), buf,
+                       "{:?} did not re-encode to the bytes it was decoded from", value);
+        }
+    }
+}
+
+/// Runs every check against `ROUNDS` values from `gen`, and `ROUNDS` random blobs.
+pub fn check_marshal<Z, D, T>(dumper: &D, mut gen: impl FnMut(&mut Rng) -> T)
+    where D: TestDumper<Z>,
+          T: Decode<Z> + for<'a> Encode<'a, Z> + Debug
+{
+    let mut rng = Rng::new(0);
+    for _ in 0 .. ROUNDS {
+        let value = gen(&mut rng);
+        check_roundtrip(dumper, &value);
+        check_padding(dumper, &value);
+    }
+    check_random_blobs::<Z, D, T>(dumper, &mut rng, ROUNDS);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::num::NonZeroU32;
+
+    use leint::{Le, Be};
+
+    use crate::impls::scalar::{Canonical, PersistChar};
+    use crate::zone::{Alloc, OwnedPtr};
+
+    #[test]
+    fn rng() {
+        let mut a = Rng::new(0);
+        let mut b = Rng::new(0);
+        assert_eq!(a.next_u64(), b.next_u64());
+        assert_ne!(a.next_u64(), 0);
+
+        let mut buf = [0u8; 11];
+        a.fill_bytes(&mut buf);
+        assert!(buf.iter().any(|b| *b != 0));
+    }
+
+    #[test]
+    fn scalars() {
+        check_marshal(&NeverDumper, |_| ());
+        check_marshal(&NeverDumper, |rng| rng.next_bool());
+        check_marshal(&NeverDumper, |rng| rng.next_u64() as u8);
+        check_marshal(&NeverDumper, |rng| Le::new(rng.next_u64() as u32));
+        check_marshal(&NeverDumper, |rng| Le::new(NonZeroU32::new(rng.next_u64() as u32 | 1).unwrap()));
+        check_marshal(&NeverDumper, |rng| Be::new(rng.next_u64() as i64));
+        check_marshal(&NeverDumper, |rng| Be::new(NonZeroU32::new(rng.next_u64() as u32 | 1).unwrap()));
+    }
+
+    #[test]
+    fn floats_and_chars() {
+        check_marshal(&NeverDumper, |rng| Le::new(f32::from_bits(rng.next_u64() as u32 & 0x7f7f_ffff)));
+        check_marshal(&NeverDumper, |rng| Le::new(rng.next_u64() as f64 / 3.0));
+        check_marshal(&NeverDumper, |rng| Canonical::<Le<f32>>::new(f32::from_bits(rng.next_u64() as u32)));
+        check_marshal(&NeverDumper, |rng| Canonical::<Le<f64>>::new(f64::from_bits(rng.next_u64())));
+
+        let nan = 0x7fc0_0001u32.to_le_bytes();
+        assert!(try_decode::<_, _, Le<f32>>(&NeverDumper, &nan).is_ok());
+        assert!(try_decode::<_, _, Canonical<Le<f32>>>(&NeverDumper, &nan).is_err());
+        let nan = try_decode::<_, _, Canonical<Le<f32>>>(&NeverDumper, &0x7fc0_0000u32.to_le_bytes());
+        assert!(nan.unwrap().get().is_nan());
+
+        let gen_char = |rng: &mut Rng| std::char::from_u32(rng.next_u64() as u32 % 0xd800).unwrap();
+        check_marshal(&NeverDumper, |rng| PersistChar::from(gen_char(rng)));
+
+        assert!(try_decode::<_, _, PersistChar>(&NeverDumper, &0xd800u32.to_le_bytes()).is_err());
+        assert!(try_decode::<_, _, PersistChar>(&NeverDumper, &0x11_0000u32.to_le_bytes()).is_err());
+        let c: char = try_decode::<_, _, PersistChar>(&NeverDumper, &('λ' as u32).to_le_bytes()).unwrap().into();
+        assert_eq!(c, 'λ');
+    }
+
+    #[test]
+    fn arrays() {
+        check_marshal(&NeverDumper, |rng| [rng.next_bool(), rng.next_bool(), rng.next_bool()]);
+        check_marshal(&NeverDumper, |rng| [Le::new(rng.next_u64()); 2]);
+    }
+
+    #[test]
+    fn pointers() {
+        let pile = TryPileMut::default();
+        check_marshal(&pile, |rng| pile.alloc(Le::new(rng.next_u64() as u32)));
+        check_marshal(&pile, |rng| pile.alloc(pile.alloc(rng.next_bool())));
+
+        // Clean pointers are encoded as-is.
+        let ptr = try_decode::<_, _, OwnedPtr<u8, TryPileMut>>(&pile, &[5, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(encode(&pile, &ptr), &[5, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,372 @@
+//! Deep `Debug` formatting through zones.
+//!
+//! By itself, the `Debug` implementation of a `ValidPtr` can only show dirty values: it has no
+//! zone to load clean values from, so all it can show is the `FatPtr`. `DebugDeep` formats values
+//! through the `FmtDeep` trait instead, which is given a `DeepContext` holding the zone, so that
+//! every pointer formatted within it loads its value:
+//!
+//! ```ignore
+//! println!("{:#?}", DebugDeep::new(&tree, &pile).max_depth(4));
+//! ```
+//!
+//! Loaded values are prefixed with their pointer, eg `@42 Foo { .. }`. A pointer that has already
+//! been shown is printed as `<shared @42>` instead of being loaded again; pointers beyond the
+//! depth limit are printed as `@42 ..`.
+
+use std::any::type_name;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::num;
+
+use leint::{Le, Be};
+
+use crate::impls::scalar::{Canonical, PersistChar};
+use crate::marshal::load::Load;
+use crate::pointee::{Pointee, Metadata, MetadataKind};
+
+use super::{Zone, TryGet, ValidPtr, OwnedPtr};
+
+pub use hoard_derive::FmtDeep;
+
+/// Values that `DebugDeep` can format, loading pointers from a zone.
+///
+/// Can be derived for structs and enums, formatting them as `#[derive(Debug)]` would.
+pub trait FmtDeep<Z: Zone> {
+    fn fmt_deep(&self, ctx: &DeepContext<Z>, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+/// `Debug` adapter that loads through pointers.
+///
+/// See the module docs.
+pub struct DebugDeep<'a, T: ?Sized, Z> {
+    value: &'a T,
+    zone: &'a Z,
+    max_depth: Option<usize>,
+    compact: bool,
+}
+
+impl<'a, T: ?Sized, Z> DebugDeep<'a, T, Z> {
+    pub fn new(value: &'a T, zone: &'a Z) -> Self {
+        Self {
+            value,
+            zone,
+            max_depth: None,
+            compact: false,
+        }
+    }
+
+    /// Sets the maximum number of pointers followed from the value.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Sets compact mode, which leaves out pointers in front of loaded values, and always formats
+    /// on a single line, even with `{:#?}`.
+    pub fn compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+}
+
+impl<T: ?Sized + FmtDeep<Z>, Z: Zone> fmt::Debug for DebugDeep<'_, T, Z> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ctx = DeepContext {
+            zone: self.zone,
+            max_depth: self.max_depth,
+            compact: self.compact,
+            depth: Cell::new(0),
+            seen: SeenPtrs::default(),
+        };
+
+        if self.compact {
+            write!(f, "{:?}", ctx.deep(self.value))
+        } else {
+            self.value.fmt_deep(&ctx, f)
+        }
+    }
+}
+
+/// The state of a single `DebugDeep` formatting call.
+pub struct DeepContext<'a, Z: Zone> {
+    zone: &'a Z,
+    max_depth: Option<usize>,
+    compact: bool,
+    depth: Cell<usize>,
+
+    seen: SeenPtrs<Z::PersistPtr>,
+}
+
+/// The persistent pointers followed so far by a deep walk of a value.
+///
+/// Pointers are only the same if their pointee types and metadata are too, so eg a `[u8]` of a
+/// different length at the same offset isn't. Types are told apart by name, which is only used
+/// to decide what to show, so needn't be perfectly unique.
+pub(crate) struct SeenPtrs<P> {
+    seen: RefCell<HashSet<(P, &'static str, MetadataKind)>>,
+}
+
+impl<P: Eq + Hash> Default for SeenPtrs<P> {
+    fn default() -> Self {
+        Self { seen: RefCell::default() }
+    }
+}
+
+impl<P: Copy + Eq + Hash> SeenPtrs<P> {
+    /// Returns true if a pointer has already been followed.
+    pub(crate) fn contains<T: ?Sized + Pointee>(&self, raw: P, metadata: T::Metadata) -> bool {
+        self.seen.borrow().contains(&(raw, type_name::<T>(), metadata.kind()))
+    }
+
+    /// Records that a pointer has been followed.
+    ///
+    /// Only pointers that were successfully loaded should be recorded: pointers that can't be
+    /// loaded, such as those in `Missing`, needn't be unique.
+    pub(crate) fn insert<T: ?Sized + Pointee>(&self, raw: P, metadata: T::Metadata) {
+        self.seen.borrow_mut().insert((raw, type_name::<T>(), metadata.kind()));
+    }
+}
+
+impl<'a, Z: Zone> DeepContext<'a, Z> {
+    pub fn zone(&self) -> &'a Z {
+        self.zone
+    }
+
+    /// Wraps `value` in a `Debug` adapter that formats it with this context.
+    ///
+    /// For use with the `Formatter::debug_*` builders:
+    ///
+    /// ```ignore
+    /// f.debug_struct("Pair")
+    ///  .field("left", &ctx.deep(&self.left))
+    ///  .finish()
+    /// ```
+    pub fn deep<'c, T: ?Sized + FmtDeep<Z>>(&'c self, value: &'c T) -> Deep<'c, 'a, T, Z> {
+        Deep { value, ctx: self }
+    }
+
+    fn depth_exceeded(&self) -> bool {
+        self.max_depth.map(|max| self.depth.get() >= max).unwrap_or(false)
+    }
+
+    fn nested(&self, f: impl FnOnce() -> fmt::Result) -> fmt::Result {
+        self.depth.set(self.depth.get() + 1);
+        let r = f();
+        self.depth.set(self.depth.get() - 1);
+        r
+    }
+
+}
+
+/// `Debug` adapter returned by `DeepContext::deep()`.
+pub struct Deep<'c, 'a, T: ?Sized, Z: Zone> {
+    value: &'c T,
+    ctx: &'c DeepContext<'a, Z>,
+}
+
+impl<T: ?Sized + FmtDeep<Z>, Z: Zone> fmt::Debug for Deep<'_, '_, T, Z> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt_deep(self.ctx, f)
+    }
+}
+
+macro_rules! impl_fmt_deep_for_debug {
+    ($( $t:ty, )+) => {$(
+        impl<Z: Zone> FmtDeep<Z> for $t {
+            fn fmt_deep(&self, _: &DeepContext<Z>, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::Debug::fmt(self, f)
+            }
+        }
+    )+}
+}
+
+impl_fmt_deep_for_debug! {
+    (), bool, u8, i8,
+    Le<u16>, Le<u32>, Le<u64>, Le<u128>,
+    Le<i16>, Le<i32>, Le<i64>, Le<i128>,
+    Be<u16>, Be<u32>, Be<u64>, Be<u128>,
+    Be<i16>, Be<i32>, Be<i64>, Be<i128>,
+    Le<f32>, Le<f64>, Canonical<Le<f32>>, Canonical<Le<f64>>,
+    char, PersistChar,
+    num::NonZeroU8, num::NonZeroI8,
+    Le<num::NonZeroU16>, Le<num::NonZeroU32>, Le<num::NonZeroU64>, Le<num::NonZeroU128>,
+    Le<num::NonZeroI16>, Le<num::NonZeroI32>, Le<num::NonZeroI64>, Le<num::NonZeroI128>,
+}
+
+impl<Z: Zone, T: FmtDeep<Z>, const N: usize> FmtDeep<Z> for [T; N] {
+    fn fmt_deep(&self, ctx: &DeepContext<Z>, f: &mut fmt::Formatter) -> fmt::Result {
+        self[..].fmt_deep(ctx, f)
+    }
+}
+
+impl<Z: Zone, T: FmtDeep<Z>> FmtDeep<Z> for [T] {
+    fn fmt_deep(&self, ctx: &DeepContext<Z>, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+         .entries(self.iter().map(|item| ctx.deep(item)))
+         .finish()
+    }
+}
+
+impl<Z: Zone, T: ?Sized + FmtDeep<Z>> FmtDeep<Z> for Box<T> {
+    fn fmt_deep(&self, ctx: &DeepContext<Z>, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt_deep(ctx, f)
+    }
+}
+
+macro_rules! impl_fmt_deep_for_tuples {
+    ($( ( $($t:ident: $i:tt),+ ), )+) => {$(
+        impl<Z: Zone, $($t: FmtDeep<Z>),+> FmtDeep<Z> for ($($t,)+) {
+            fn fmt_deep(&self, ctx: &DeepContext<Z>, f: &mut fmt::Formatter) -> fmt::Result {
+                f.debug_tuple("")
+                 $( .field(&ctx.deep(&self.$i)) )+
+                 .finish()
+            }
+        }
+    )+}
+}
+
+impl_fmt_deep_for_tuples! {
+    (A: 0),
+    (A: 0, B: 1),
+    (A: 0, B: 1, C: 2),
+    (A: 0, B: 1, C: 2, D: 3),
+    (A: 0, B: 1, C: 2, D: 3, E: 4),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7),
+}
+
+impl<Z: Zone, T: FmtDeep<Z>> FmtDeep<Z> for Option<T> {
+    fn fmt_deep(&self, ctx: &DeepContext<Z>, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            None => f.write_str("None"),
+            Some(value) => f.debug_tuple("Some").field(&ctx.deep(value)).finish(),
+        }
+    }
+}
+
+impl<Z: TryGet, T: ?Sized + Load<Z> + FmtDeep<Z>> FmtDeep<Z> for ValidPtr<T, Z> {
+    fn fmt_deep(&self, ctx: &DeepContext<Z>, f: &mut fmt::Formatter) -> fmt::Result {
+        let depth_exceeded = ctx.depth_exceeded();
+
+        match Z::try_get_dirty(self) {
+            Ok(_) if depth_exceeded => write!(f, ".."),
+            Ok(value) => ctx.nested(|| value.fmt_deep(ctx, f)),
+            Err(fatptr) if depth_exceeded => write!(f, "@{:?} ..", fatptr.raw),
+            Err(fatptr) => {
+                if ctx.seen.contains::<T>(fatptr.raw, fatptr.metadata) {
+                    return write!(f, "<shared @{:?}>", fatptr.raw);
+                }
+
+                match ctx.zone.try_get(self) {
+                    Ok(r) => {
+                        ctx.seen.insert::<T>(fatptr.raw, fatptr.metadata);
+                        if !ctx.compact {
+                            write!(f, "@{:?} ", fatptr.raw)?;
+                        }
+                        ctx.nested(|| r.this.fmt_deep(ctx, f))
+                    },
+                    Err(err) => write!(f, "@{:?} <{}>", fatptr.raw, err),
+                }
+            },
+        }
+    }
+}
+
+impl<Z: TryGet, T: ?Sized + Load<Z> + FmtDeep<Z>> FmtDeep<Z> for OwnedPtr<T, Z> {
+    fn fmt_deep(&self, ctx: &DeepContext<Z>, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt_deep(ctx, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pile::{TryPile, TryPileMut};
+    use crate::zone::{Alloc, Missing};
+
+    #[test]
+    fn debug_deep() {
+        TryPile::new(&[1, 0][..], |pile| {
+            let pair = [TryPile::new_valid_ptr::<Le<u16>>(0, ()),
+                        TryPile::new_valid_ptr::<Le<u16>>(0, ())];
+
+            assert_eq!(format!("{:?}", pair), "[FatPtr { raw: 0 }, FatPtr { raw: 0 }]");
+            assert_eq!(format!("{:?}", DebugDeep::new(&pair, &pile)),
+                       "[@0 Le(1), <shared @0>]");
+            assert_eq!(format!("{:?}", DebugDeep::new(&pair, &pile).compact(true)),
+                       "[Le(1), <shared @0>]");
+            assert_eq!(format!("{:?}", DebugDeep::new(&pair, &pile).max_depth(0)),
+                       "[@0 .., @0 ..]");
+            assert_eq!(format!("{:#?}", DebugDeep::new(&pair, &pile).compact(true)),
+                       "[Le(1), <shared @0>]");
+        });
+
+        // Pointers are loaded through recursively.
+        let pile = TryPileMut::default();
+        let nested = pile.alloc(pile.alloc(Le::new(2u16)));
+        let buf = pile.encode_dirty(&nested);
+        TryPile::new(buf, |pile| {
+            let pile = TryPileMut::from(pile);
+            let nested = pile.try_take_tip::<OwnedPtr<OwnedPtr<Le<u16>, TryPileMut>, TryPileMut>>()
+                             .unwrap().this;
+
+            assert_eq!(format!("{:?}", DebugDeep::new(&nested, &pile)), "@2 @0 Le(2)");
+            assert_eq!(format!("{:?}", DebugDeep::new(&nested, &pile).max_depth(1)), "@2 @0 ..");
+        });
+    }
+
+    #[test]
+    fn debug_deep_tuples_and_boxes() {
+        let value = (Box::new(Le::new(1u16)), true, [Box::new(2u8)]);
+        assert_eq!(format!("{:?}", DebugDeep::new(&value, &Missing)), "(Le(1), true, [2])");
+        assert_eq!(format!("{:?}", DebugDeep::new(&(1u8,), &Missing)), "(1,)");
+    }
+
+    struct Pair<Z: Zone> {
+        left: ValidPtr<Le<u16>, Z>,
+        right: ValidPtr<u8, Z>,
+    }
+
+    impl<Z: TryGet> FmtDeep<Z> for Pair<Z> {
+        fn fmt_deep(&self, ctx: &DeepContext<Z>, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("Pair")
+             .field("left", &ctx.deep(&self.left))
+             .field("right", &ctx.deep(&self.right))
+             .finish()
+        }
+    }
+
+    #[test]
+    fn debug_deep_same_offset() {
+        TryPile::new(&[1, 0][..], |pile| {
+            // Different types at the same offset aren't the same pointer.
+            let pair = Pair {
+                left: TryPile::new_valid_ptr(0, ()),
+                right: TryPile::new_valid_ptr(0, ()),
+            };
+            assert_eq!(format!("{:?}", DebugDeep::new(&pair, &pile)),
+                       "Pair { left: @0 Le(1), right: @0 1 }");
+        });
+    }
+
+    #[test]
+    fn debug_deep_missing_not_shared() {
+        let pair = [Missing.alloc(1u8), Missing.alloc(2u8)];
+        assert_eq!(format!("{:?}", DebugDeep::new(&pair, &Missing)),
+                   "[@() <missing>, @() <missing>]");
+    }
+
+    #[test]
+    fn debug_deep_invalid() {
+        // Offset 100, past the end of the pile.
+        TryPile::new(&[201, 0, 0, 0, 0, 0, 0, 0][..], |pile| {
+            let ptr = TryPile::new_valid_ptr::<Le<u32>>(100, ());
+            let s = format!("{:?}", DebugDeep::new(&ptr, &pile));
+            assert!(s.starts_with("@100 <failed to load"), "{}", s);
+        });
+    }
+}
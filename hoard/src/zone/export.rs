@@ -0,0 +1,750 @@
+//! Exporting values to human-readable formats.
+//!
+//! `Export` walks a value, loading through every pointer on the way, so that a persisted root can
+//! be dumped for debugging, diffing, or for tools that will never link hoard. Values are exported
+//! to an `Exporter`, whose data model follows serde's. The simplest exporter builds a `Value`
+//! tree:
+//!
+//! ```ignore
+//! println!("{}", root.export(&pile).to_json());
+//! ```
+//!
+//! With the `serde` feature, `Value` implements `Serialize`, as does the `Exported` adapter.
+//! `Exported` serializes the value as it's walked, without building a `Value` first:
+//!
+//! ```ignore
+//! serde_json::to_writer(&mut out, &Exported::new(&root, &pile))?;
+//! ```
+//!
+//! Loaded pointers are transparent, with the pointee taking their place. As with `DebugDeep`, a
+//! pointer that has already been exported becomes a placeholder, eg `<shared @42>`, rather than
+//! being loaded again; pointers that can't be loaded, such as everything in `Missing`, become a
+//! placeholder with the reason why.
+//!
+//! `Export` can be derived for structs and enums.
+
+use std::fmt::{self, Write};
+use std::num;
+
+use leint::{Le, Be};
+
+use crate::impls::scalar::{Canonical, PersistChar};
+use crate::marshal::load::Load;
+
+use super::{Zone, TryGet, ValidPtr, OwnedPtr};
+use super::debug::SeenPtrs;
+
+pub use hoard_derive::Export;
+
+/// An exported value.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    U128(u128),
+    I128(i128),
+    F64(f64),
+    Char(char),
+    Seq(Vec<Value>),
+    Struct {
+        name: &'static str,
+        fields: Vec<(&'static str, Value)>,
+    },
+    Variant {
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        fields: Vec<(&'static str, Value)>,
+    },
+
+    /// A pointer that wasn't loaded, with the reason why.
+    Placeholder(String),
+}
+
+/// Values that can be exported, loading pointers from a zone.
+///
+/// Can be derived for structs and enums; see `hoard_derive::Export`.
+pub trait Export<Z: Zone> {
+    /// Exports the value to `exporter`, loading pointers with `ctx`.
+    fn export_to<E: Exporter>(&self, ctx: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error>;
+
+    /// Exports the value as a `Value` tree.
+    fn export(&self, zone: &Z) -> Value {
+        to_value(&ExportContext::new(zone), self)
+    }
+}
+
+fn to_value<Z: Zone, T: ?Sized + Export<Z>>(ctx: &ExportContext<Z>, value: &T) -> Value {
+    match value.export_to(ctx, ValueExporter) {
+        Ok(value) => value,
+        Err(never) => never,
+    }
+}
+
+/// The state of a single export.
+pub struct ExportContext<'a, Z: Zone> {
+    zone: &'a Z,
+    seen: SeenPtrs<Z::PersistPtr>,
+}
+
+impl<'a, Z: Zone> ExportContext<'a, Z> {
+    pub fn new(zone: &'a Z) -> Self {
+        Self {
+            zone,
+            seen: SeenPtrs::default(),
+        }
+    }
+
+    pub fn zone(&self) -> &'a Z {
+        self.zone
+    }
+}
+
+/// A destination for exported values.
+///
+/// Compound values are exported in two steps: `export_seq()`, `export_struct()` and
+/// `export_variant()` return an `ExportSeq` or `ExportFields`, which is given each item in turn.
+pub trait Exporter: Sized {
+    type Ok;
+    type Error;
+
+    type Seq: ExportSeq<Ok = Self::Ok, Error = Self::Error>;
+    type Fields: ExportFields<Ok = Self::Ok, Error = Self::Error>;
+
+    fn export_unit(self) -> Result<Self::Ok, Self::Error>;
+    fn export_bool(self, v: bool) -> Result<Self::Ok, Self::Error>;
+    fn export_u64(self, v: u64) -> Result<Self::Ok, Self::Error>;
+    fn export_i64(self, v: i64) -> Result<Self::Ok, Self::Error>;
+    fn export_u128(self, v: u128) -> Result<Self::Ok, Self::Error>;
+    fn export_i128(self, v: i128) -> Result<Self::Ok, Self::Error>;
+    fn export_f64(self, v: f64) -> Result<Self::Ok, Self::Error>;
+    fn export_char(self, v: char) -> Result<Self::Ok, Self::Error>;
+
+    /// Exports a placeholder for a pointer that wasn't loaded, with the reason why.
+    fn export_placeholder(self, reason: &dyn fmt::Display) -> Result<Self::Ok, Self::Error>;
+
+    fn export_seq(self, len: usize) -> Result<Self::Seq, Self::Error>;
+    fn export_struct(self, name: &'static str, len: usize) -> Result<Self::Fields, Self::Error>;
+    fn export_variant(self, name: &'static str, index: u32, variant: &'static str, len: usize)
+        -> Result<Self::Fields, Self::Error>;
+}
+
+/// Returned by `Exporter::export_seq()`.
+pub trait ExportSeq {
+    type Ok;
+    type Error;
+
+    fn element<Z: Zone, T: ?Sized + Export<Z>>(&mut self, ctx: &ExportContext<Z>, value: &T)
+        -> Result<(), Self::Error>;
+
+    fn end(self) -> Result<Self::Ok, Self::Error>;
+}
+
+/// Returned by `Exporter::export_struct()` and `Exporter::export_variant()`.
+pub trait ExportFields {
+    type Ok;
+    type Error;
+
+    fn field<Z: Zone, T: ?Sized + Export<Z>>(&mut self, ctx: &ExportContext<Z>, name: &'static str, value: &T)
+        -> Result<(), Self::Error>;
+
+    fn end(self) -> Result<Self::Ok, Self::Error>;
+}
+
+/// `Exporter` that builds a `Value`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ValueExporter;
+
+/// Returned by `ValueExporter::export_seq()`.
+#[derive(Debug)]
+pub struct ValueSeq(Vec<Value>);
+
+/// Returned by `ValueExporter::export_struct()` and `ValueExporter::export_variant()`.
+#[derive(Debug)]
+pub struct ValueFields {
+    name: &'static str,
+    variant: Option<(u32, &'static str)>,
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl Exporter for ValueExporter {
+    type Ok = Value;
+    type Error = !;
+
+    type Seq = ValueSeq;
+    type Fields = ValueFields;
+
+    fn export_unit(self) -> Result<Value, !> {
+        Ok(Value::Unit)
+    }
+
+    fn export_bool(self, v: bool) -> Result<Value, !> {
+        Ok(Value::Bool(v))
+    }
+
+    fn export_u64(self, v: u64) -> Result<Value, !> {
+        Ok(Value::U64(v))
+    }
+
+    fn export_i64(self, v: i64) -> Result<Value, !> {
+        Ok(Value::I64(v))
+    }
+
+    fn export_u128(self, v: u128) -> Result<Value, !> {
+        Ok(Value::U128(v))
+    }
+
+    fn export_i128(self, v: i128) -> Result<Value, !> {
+        Ok(Value::I128(v))
+    }
+
+    fn export_f64(self, v: f64) -> Result<Value, !> {
+        Ok(Value::F64(v))
+    }
+
+    fn export_char(self, v: char) -> Result<Value, !> {
+        Ok(Value::Char(v))
+    }
+
+    fn export_placeholder(self, reason: &dyn fmt::Display) -> Result<Value, !> {
+        Ok(Value::Placeholder(reason.to_string()))
+    }
+
+    fn export_seq(self, len: usize) -> Result<ValueSeq, !> {
+        Ok(ValueSeq(Vec::with_capacity(len)))
+    }
+
+    fn export_struct(self, name: &'static str, len: usize) -> Result<ValueFields, !> {
+        Ok(ValueFields { name, variant: None, fields: Vec::with_capacity(len) })
+    }
+
+    fn export_variant(self, name: &'static str, index: u32, variant: &'static str, len: usize)
+        -> Result<ValueFields, !>
+    {
+        Ok(ValueFields { name, variant: Some((index, variant)), fields: Vec::with_capacity(len) })
+    }
+}
+
+impl ExportSeq for ValueSeq {
+    type Ok = Value;
+    type Error = !;
+
+    fn element<Z: Zone, T: ?Sized + Export<Z>>(&mut self, ctx: &ExportContext<Z>, value: &T) -> Result<(), !> {
+        self.0.push(to_value(ctx, value));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, !> {
+        Ok(Value::Seq(self.0))
+    }
+}
+
+impl ExportFields for ValueFields {
+    type Ok = Value;
+    type Error = !;
+
+    fn field<Z: Zone, T: ?Sized + Export<Z>>(&mut self, ctx: &ExportContext<Z>, name: &'static str, value: &T)
+        -> Result<(), !>
+    {
+        self.fields.push((name, to_value(ctx, value)));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, !> {
+        Ok(match self.variant {
+            None => Value::Struct { name: self.name, fields: self.fields },
+            Some((index, variant)) => Value::Variant { name: self.name, index, variant, fields: self.fields },
+        })
+    }
+}
+
+impl Value {
+    /// Renders the value as JSON.
+    ///
+    /// Structs become objects nested in an object keyed by their name, eg `{"Pair":{"left":1}}`,
+    /// and enum variants the same, keyed by `Enum::Variant`. Placeholders become strings in angle
+    /// brackets, eg `"<missing>"`. Like other JSON encoders, NaN and infinities become `null`.
+    pub fn to_json(&self) -> String {
+        let mut s = String::new();
+        self.write_json(&mut s).expect("writing to a String can't fail");
+        s
+    }
+
+    fn write_json(&self, dst: &mut String) -> fmt::Result {
+        match self {
+            Value::Unit => dst.write_str("null"),
+            Value::Bool(b) => write!(dst, "{}", b),
+            Value::U64(n) => write!(dst, "{}", n),
+            Value::I64(n) => write!(dst, "{}", n),
+            Value::U128(n) => write!(dst, "{}", n),
+            Value::I128(n) => write!(dst, "{}", n),
+            Value::F64(n) if n.is_finite() => write!(dst, "{:?}", n),
+            Value::F64(_) => dst.write_str("null"),
+            Value::Char(c) => write_json_str(dst, c.encode_utf8(&mut [0; 4])),
+            Value::Seq(items) => {
+                dst.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        dst.write_char(',')?;
+                    }
+                    item.write_json(dst)?;
+                }
+                dst.write_char(']')
+            },
+            Value::Struct { name, fields } => {
+                dst.write_char('{')?;
+                write_json_str(dst, name)?;
+                dst.write_char(':')?;
+                write_json_fields(dst, fields)?;
+                dst.write_char('}')
+            },
+            Value::Variant { name, variant, fields, .. } => {
+                dst.write_char('{')?;
+                write_json_str(dst, &format!("{}::{}", name, variant))?;
+                dst.write_char(':')?;
+                write_json_fields(dst, fields)?;
+                dst.write_char('}')
+            },
+            Value::Placeholder(reason) => write_json_str(dst, &format!("<{}>", reason)),
+        }
+    }
+}
+
+fn write_json_fields(dst: &mut String, fields: &[(&'static str, Value)]) -> fmt::Result {
+    dst.write_char('{')?;
+    for (i, (name, value)) in fields.iter().enumerate() {
+        if i > 0 {
+            dst.write_char(',')?;
+        }
+        write_json_str(dst, name)?;
+        dst.write_char(':')?;
+        value.write_json(dst)?;
+    }
+    dst.write_char('}')
+}
+
+fn write_json_str(dst: &mut String, s: &str) -> fmt::Result {
+    dst.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => dst.write_str("\\\"")?,
+            '\\' => dst.write_str("\\\\")?,
+            c if (c as u32) < 0x20 => write!(dst, "\\u{:04x}", c as u32)?,
+            c => dst.write_char(c)?,
+        }
+    }
+    dst.write_char('"')
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Value {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeStruct, SerializeStructVariant};
+
+        match self {
+            Value::Unit => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::U64(n) => serializer.serialize_u64(*n),
+            Value::I64(n) => serializer.serialize_i64(*n),
+            Value::U128(n) => serializer.serialize_u128(*n),
+            Value::I128(n) => serializer.serialize_i128(*n),
+            Value::F64(n) => serializer.serialize_f64(*n),
+            Value::Char(c) => serializer.serialize_char(*c),
+            Value::Seq(items) => serializer.collect_seq(items),
+            Value::Struct { name, fields } => {
+                let mut s = serializer.serialize_struct(name, fields.len())?;
+                for (field, value) in fields {
+                    s.serialize_field(field, value)?;
+                }
+                s.end()
+            },
+            Value::Variant { name, index, variant, fields } => {
+                let mut s = serializer.serialize_struct_variant(name, *index, variant, fields.len())?;
+                for (field, value) in fields {
+                    s.serialize_field(field, value)?;
+                }
+                s.end()
+            },
+            Value::Placeholder(reason) => serializer.collect_str(&format_args!("<{}>", reason)),
+        }
+    }
+}
+
+/// `Serialize` adapter that exports a value from a zone.
+///
+/// See the module docs.
+#[cfg(feature = "serde")]
+pub struct Exported<'a, T: ?Sized, Z> {
+    value: &'a T,
+    zone: &'a Z,
+}
+
+#[cfg(feature = "serde")]
+impl<'a, T: ?Sized, Z> Exported<'a, T, Z> {
+    pub fn new(value: &'a T, zone: &'a Z) -> Self {
+        Self { value, zone }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: ?Sized + Export<Z>, Z: Zone> serde::Serialize for Exported<'_, T, Z> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.export_to(&ExportContext::new(self.zone), SerdeExporter(serializer))
+    }
+}
+
+/// `Exporter` that serializes with a `serde::Serializer`.
+#[cfg(feature = "serde")]
+struct SerdeExporter<S>(S);
+
+/// A value that's part of an export, serialized with the export's context.
+#[cfg(feature = "serde")]
+struct InContext<'c, 'a, T: ?Sized, Z: Zone> {
+    ctx: &'c ExportContext<'a, Z>,
+    value: &'c T,
+}
+
+#[cfg(feature = "serde")]
+impl<T: ?Sized + Export<Z>, Z: Zone> serde::Serialize for InContext<'_, '_, T, Z> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.export_to(self.ctx, SerdeExporter(serializer))
+    }
+}
+
+#[cfg(feature = "serde")]
+enum SerdeFields<S, V> {
+    Struct(S),
+    Variant(V),
+}
+
+#[cfg(feature = "serde")]
+impl<S: serde::Serializer> Exporter for SerdeExporter<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    type Seq = SerdeExporter<S::SerializeSeq>;
+    type Fields = SerdeFields<S::SerializeStruct, S::SerializeStructVariant>;
+
+    fn export_unit(self) -> Result<S::Ok, S::Error> {
+        self.0.serialize_unit()
+    }
+
+    fn export_bool(self, v: bool) -> Result<S::Ok, S::Error> {
+        self.0.serialize_bool(v)
+    }
+
+    fn export_u64(self, v: u64) -> Result<S::Ok, S::Error> {
+        self.0.serialize_u64(v)
+    }
+
+    fn export_i64(self, v: i64) -> Result<S::Ok, S::Error> {
+        self.0.serialize_i64(v)
+    }
+
+    fn export_u128(self, v: u128) -> Result<S::Ok, S::Error> {
+        self.0.serialize_u128(v)
+    }
+
+    fn export_i128(self, v: i128) -> Result<S::Ok, S::Error> {
+        self.0.serialize_i128(v)
+    }
+
+    fn export_f64(self, v: f64) -> Result<S::Ok, S::Error> {
+        self.0.serialize_f64(v)
+    }
+
+    fn export_char(self, v: char) -> Result<S::Ok, S::Error> {
+        self.0.serialize_char(v)
+    }
+
+    fn export_placeholder(self, reason: &dyn fmt::Display) -> Result<S::Ok, S::Error> {
+        self.0.collect_str(&format_args!("<{}>", reason))
+    }
+
+    fn export_seq(self, len: usize) -> Result<Self::Seq, S::Error> {
+        self.0.serialize_seq(Some(len)).map(SerdeExporter)
+    }
+
+    fn export_struct(self, name: &'static str, len: usize) -> Result<Self::Fields, S::Error> {
+        self.0.serialize_struct(name, len).map(SerdeFields::Struct)
+    }
+
+    fn export_variant(self, name: &'static str, index: u32, variant: &'static str, len: usize)
+        -> Result<Self::Fields, S::Error>
+    {
+        self.0.serialize_struct_variant(name, index, variant, len).map(SerdeFields::Variant)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<S: serde::ser::SerializeSeq> ExportSeq for SerdeExporter<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn element<Z: Zone, T: ?Sized + Export<Z>>(&mut self, ctx: &ExportContext<Z>, value: &T)
+        -> Result<(), S::Error>
+    {
+        self.0.serialize_element(&InContext { ctx, value })
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.0.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<S, V> ExportFields for SerdeFields<S, V>
+where S: serde::ser::SerializeStruct,
+      V: serde::ser::SerializeStructVariant<Ok = S::Ok, Error = S::Error>,
+{
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn field<Z: Zone, T: ?Sized + Export<Z>>(&mut self, ctx: &ExportContext<Z>, name: &'static str, value: &T)
+        -> Result<(), S::Error>
+    {
+        match self {
+            SerdeFields::Struct(s) => s.serialize_field(name, &InContext { ctx, value }),
+            SerdeFields::Variant(v) => v.serialize_field(name, &InContext { ctx, value }),
+        }
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        match self {
+            SerdeFields::Struct(s) => s.end(),
+            SerdeFields::Variant(v) => v.end(),
+        }
+    }
+}
+
+impl<Z: Zone> Export<Z> for () {
+    fn export_to<E: Exporter>(&self, _: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+        exporter.export_unit()
+    }
+}
+
+macro_rules! impl_export_for_scalars {
+    ($( $t:ty => $method:ident, )+) => {$(
+        impl<Z: Zone> Export<Z> for $t {
+            fn export_to<E: Exporter>(&self, _: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+                exporter.$method(self.get().into())
+            }
+        }
+    )+}
+}
+
+impl_export_for_scalars! {
+    Le<u16> => export_u64, Le<u32> => export_u64, Le<u64> => export_u64, Le<u128> => export_u128,
+    Le<i16> => export_i64, Le<i32> => export_i64, Le<i64> => export_i64, Le<i128> => export_i128,
+    Be<u16> => export_u64, Be<u32> => export_u64, Be<u64> => export_u64, Be<u128> => export_u128,
+    Be<i16> => export_i64, Be<i32> => export_i64, Be<i64> => export_i64, Be<i128> => export_i128,
+    num::NonZeroU8 => export_u64, num::NonZeroI8 => export_i64,
+    Le<f32> => export_f64, Le<f64> => export_f64,
+    Canonical<Le<f32>> => export_f64, Canonical<Le<f64>> => export_f64,
+    PersistChar => export_char,
+}
+
+macro_rules! impl_export_for_nonzero_ints {
+    ($( $t:ty => $method:ident, )+) => {$(
+        impl<Z: Zone> Export<Z> for Le<$t> {
+            fn export_to<E: Exporter>(&self, _: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+                exporter.$method(self.get().get().into())
+            }
+        }
+
+        impl<Z: Zone> Export<Z> for Be<$t> {
+            fn export_to<E: Exporter>(&self, _: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+                exporter.$method(self.get().get().into())
+            }
+        }
+    )+}
+}
+
+impl_export_for_nonzero_ints! {
+    num::NonZeroU16 => export_u64, num::NonZeroU32 => export_u64,
+    num::NonZeroU64 => export_u64, num::NonZeroU128 => export_u128,
+    num::NonZeroI16 => export_i64, num::NonZeroI32 => export_i64,
+    num::NonZeroI64 => export_i64, num::NonZeroI128 => export_i128,
+}
+
+macro_rules! impl_export_for_primitives {
+    ($( $t:ty => $method:ident, )+) => {$(
+        impl<Z: Zone> Export<Z> for $t {
+            fn export_to<E: Exporter>(&self, _: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+                exporter.$method((*self).into())
+            }
+        }
+    )+}
+}
+
+impl_export_for_primitives! {
+    bool => export_bool,
+    char => export_char,
+    u8 => export_u64,
+    i8 => export_i64,
+}
+
+impl<Z: Zone, T: Export<Z>, const N: usize> Export<Z> for [T; N] {
+    fn export_to<E: Exporter>(&self, ctx: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+        self[..].export_to(ctx, exporter)
+    }
+}
+
+impl<Z: Zone, T: Export<Z>> Export<Z> for [T] {
+    fn export_to<E: Exporter>(&self, ctx: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+        let mut seq = exporter.export_seq(self.len())?;
+        for item in self {
+            seq.element(ctx, item)?;
+        }
+        seq.end()
+    }
+}
+
+/// Tuples are exported as sequences.
+macro_rules! impl_export_for_tuples {
+    ($( ( $($t:ident: $i:tt),+ ), )+) => {$(
+        impl<Z: Zone, $($t: Export<Z>),+> Export<Z> for ($($t,)+) {
+            fn export_to<X: Exporter>(&self, ctx: &ExportContext<Z>, exporter: X) -> Result<X::Ok, X::Error> {
+                let mut seq = exporter.export_seq([$($i),+].len())?;
+                $( seq.element(ctx, &self.$i)?; )+
+                seq.end()
+            }
+        }
+    )+}
+}
+
+impl_export_for_tuples! {
+    (A: 0),
+    (A: 0, B: 1),
+    (A: 0, B: 1, C: 2),
+    (A: 0, B: 1, C: 2, D: 3),
+    (A: 0, B: 1, C: 2, D: 3, E: 4),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7),
+}
+
+impl<Z: Zone, T: ?Sized + Export<Z>> Export<Z> for Box<T> {
+    fn export_to<E: Exporter>(&self, ctx: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+        (**self).export_to(ctx, exporter)
+    }
+}
+
+/// `None` is exported as a unit, and `Some` as the value itself.
+impl<Z: Zone, T: Export<Z>> Export<Z> for Option<T> {
+    fn export_to<E: Exporter>(&self, ctx: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+        match self {
+            None => exporter.export_unit(),
+            Some(value) => value.export_to(ctx, exporter),
+        }
+    }
+}
+
+impl<Z: TryGet, T: ?Sized + Load<Z> + Export<Z>> Export<Z> for ValidPtr<T, Z> {
+    fn export_to<E: Exporter>(&self, ctx: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+        match Z::try_get_dirty(self) {
+            Ok(value) => value.export_to(ctx, exporter),
+            Err(fatptr) if ctx.seen.contains::<T>(fatptr.raw, fatptr.metadata) => {
+                exporter.export_placeholder(&format_args!("shared @{:?}", fatptr.raw))
+            },
+            Err(fatptr) => {
+                match ctx.zone.try_get(self) {
+                    Ok(r) => {
+                        ctx.seen.insert::<T>(fatptr.raw, fatptr.metadata);
+                        r.this.export_to(ctx, exporter)
+                    },
+                    Err(err) => exporter.export_placeholder(&err),
+                }
+            },
+        }
+    }
+}
+
+impl<Z: TryGet, T: ?Sized + Load<Z> + Export<Z>> Export<Z> for OwnedPtr<T, Z> {
+    fn export_to<E: Exporter>(&self, ctx: &ExportContext<Z>, exporter: E) -> Result<E::Ok, E::Error> {
+        (**self).export_to(ctx, exporter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pile::{TryPile, TryPileMut};
+    use crate::zone::{Alloc, Missing};
+
+    #[derive(Export)]
+    #[hoard(zone = Z)]
+    struct Pair<Z: TryGet> {
+        left: OwnedPtr<Le<u32>, Z>,
+        right: [bool; 2],
+    }
+
+    #[derive(Export)]
+    #[hoard(zone = Z)]
+    enum Node<Z: TryGet> {
+        Leaf(u8),
+        Branch {
+            left: OwnedPtr<Le<u32>, Z>,
+        },
+    }
+
+    #[test]
+    fn export_json() {
+        assert_eq!(Le::new(-3i16).export(&Missing).to_json(), "-3");
+        assert_eq!(Le::new(u128::max_value()).export(&Missing).to_json(),
+                   "340282366920938463463374607431768211455");
+        assert_eq!([(), ()].export(&Missing).to_json(), "[null,null]");
+        assert_eq!([Le::new(1.5f32), Le::new(std::f32::NAN)].export(&Missing).to_json(), "[1.5,null]");
+        assert_eq!(PersistChar::new('"').export(&Missing).to_json(), r#""\"""#);
+        assert_eq!((Box::new(1u8), (true,)).export(&Missing).to_json(), "[1,[true]]");
+
+        let pair = Pair { left: Missing.alloc(Le::new(1u32)), right: [true, false] };
+        assert_eq!(pair.export(&Missing).to_json(),
+                   r#"{"Pair":{"left":"<missing>","right":[true,false]}}"#);
+
+        assert_eq!(Node::<Missing>::Leaf(3).export(&Missing).to_json(), r#"{"Node::Leaf":{"0":3}}"#);
+        let branch = Node::Branch { left: Missing.alloc(Le::new(1u32)) };
+        assert_eq!(branch.export(&Missing).to_json(), r#"{"Node::Branch":{"left":"<missing>"}}"#);
+
+        assert_eq!(Value::Placeholder("a \"b\"\n".into()).to_json(),
+                   r#""<a \"b\"\u000a>""#);
+    }
+
+    #[test]
+    fn export_loads_pointers() {
+        let pile = TryPileMut::default();
+        let nested = pile.alloc(pile.alloc(Le::new(2u16)));
+        let buf = pile.encode_dirty(&nested);
+        TryPile::new(buf, |pile| {
+            let pile = TryPileMut::from(pile);
+            let nested = pile.try_take_tip::<OwnedPtr<OwnedPtr<Le<u16>, TryPileMut>, TryPileMut>>()
+                             .unwrap().this;
+
+            assert_eq!(nested.export(&pile), Value::U64(2));
+        });
+
+        // Both pointers point to the same u16, which is only exported once.
+        TryPile::new(&[1, 0][..], |pile| {
+            let pair = [TryPile::new_valid_ptr::<Le<u16>>(0, ()),
+                        TryPile::new_valid_ptr::<Le<u16>>(0, ())];
+            assert_eq!(pair.export(&pile).to_json(), r#"[1,"<shared @0>"]"#);
+
+            // But different types at the same offset are exported separately.
+            let pair = (TryPile::new_valid_ptr::<Le<u16>>(0, ()), TryPile::new_valid_ptr::<u8>(0, ()));
+            assert_eq!(pair.export(&pile).to_json(), "[1,1]");
+        });
+
+        // Offset 100, past the end of the pile.
+        TryPile::new(&[201, 0, 0, 0, 0, 0, 0, 0][..], |pile| {
+            let ptr = TryPile::new_valid_ptr::<Le<u32>>(100, ());
+            match ptr.export(&pile) {
+                Value::Placeholder(reason) => assert!(reason.starts_with("failed to load"), "{}", reason),
+                value => panic!("expected a placeholder, got {:?}", value),
+            }
+        });
+    }
+}
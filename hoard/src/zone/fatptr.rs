@@ -10,7 +10,7 @@ use std::mem;
 use thiserror::Error;
 
 use crate::coerce::TryCoerce;
-use crate::pointee::Pointee;
+use crate::pointee::{Metadata, Pointee};
 
 use crate::marshal::*;
 use crate::marshal::blob::*;
@@ -128,10 +128,14 @@ where Z::Ptr: Primitive
 
 impl<T: ?Sized + Pointee, Z: Zone> fmt::Debug for FatPtr<T, Z> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("FatPtr")
-            .field("raw", &self.raw)
-            .field("metadata", &self.metadata)
-            .finish()
+        let mut debug = f.debug_struct("FatPtr");
+        debug.field("raw", &self.raw);
+
+        let kind = self.metadata.kind();
+        if !kind.is_sized() {
+            debug.field("metadata", &format_args!("{}", kind));
+        }
+        debug.finish()
     }
 }
 
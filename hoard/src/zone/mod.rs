@@ -1,6 +1,6 @@
 //! Zones where data can be stored.
 
-use core::any::{Any, type_name};
+use core::any::{Any, TypeId, type_name};
 use core::borrow::Borrow;
 use core::mem::ManuallyDrop;
 use core::fmt;
@@ -28,6 +28,41 @@ pub mod never;
 pub mod missing;
 pub use self::missing::Missing;
 
+pub mod transfer;
+pub use self::transfer::{Transfer, Transferrer};
+
+pub mod debug;
+pub use self::debug::{DebugDeep, DeepContext, FmtDeep};
+
+pub mod export;
+pub use self::export::{Export, Value};
+#[cfg(feature = "serde")]
+pub use self::export::Exported;
+
+/// Returns the `TypeId` of a type that needn't be `'static`.
+///
+/// Lifetimes are erased, so `&'a T` and `&'b T` have the same id; that's fine for telling apart
+/// values of different types behind the same pointer, which is all this is used for.
+pub(crate) fn type_id_of<T: ?Sized>() -> TypeId {
+    trait NonStaticAny {
+        fn type_id(&self) -> TypeId where Self: 'static;
+    }
+
+    impl<T: ?Sized> NonStaticAny for core::marker::PhantomData<T> {
+        fn type_id(&self) -> TypeId where Self: 'static {
+            TypeId::of::<T>()
+        }
+    }
+
+    let phantom = core::marker::PhantomData::<T>;
+    let phantom: &dyn NonStaticAny = &phantom;
+
+    // SAFETY: TypeId::of() doesn't depend on lifetimes, so pretending that the PhantomData is
+    // 'static has no effect other than letting us call it.
+    let phantom: &(dyn NonStaticAny + 'static) = unsafe { core::mem::transmute(phantom) };
+    phantom.type_id()
+}
+
 pub trait Zone : Sized + fmt::Debug {
     type Ptr : Copy + Eq + Ord + fmt::Debug + core::hash::Hash + Send + Sync;
     type Persist : 'static + Zone<Ptr=Self::PersistPtr, PersistPtr=Self::PersistPtr>;
@@ -87,6 +122,10 @@ pub trait TryGet : Zone {
         -> Result<Own<T::Owned, Self>, Self::Error>;
 }
 
+/// Copy-on-write mutable access.
+///
+/// `try_get_mut()` must never modify the persistent copy of a value: clean values are copied into
+/// memory first, leaving the pointer dirty. See `ValidPtr::make_mut()`.
 pub trait TryGetMut : TryGet {
     fn try_get_mut<'a, T: ?Sized + Load<Self>>(&self, ptr: &'a mut ValidPtr<T, Self>)
         -> Result<RefMut<'a, T, Self>, Self::Error>;
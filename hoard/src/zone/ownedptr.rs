@@ -183,6 +183,17 @@ where T: Save<'a, Y>,
     }
 }
 
+impl<T, Z: TryGet, Y: Alloc + Clone> Transfer<Z, Y> for OwnedPtr<T, Z>
+where T: Load<Z> + Transfer<Z, Y>,
+      T::Transferred: 'static + Clone,
+{
+    type Transferred = OwnedPtr<T::Transferred, Y>;
+
+    fn transfer_with(&self, transferrer: &mut Transferrer<Z, Y>) -> Result<Self::Transferred, Z::Error> {
+        transferrer.transfer_ptr(self)
+    }
+}
+
 /*
 impl<'a, Y: Zone, Z: 'a + Zone + Encode<'a, Y>, T: 'a + ?Sized + Save<'a, Y>> Encode<'a, Y> for OwnedPtr<T, Z> {
     type State = <ValidPtr<T, Z> as Encode<'a, Y>>::State;
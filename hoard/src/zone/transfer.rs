@@ -0,0 +1,265 @@
+//! Transfers of values between zones.
+//!
+//! A transfer is a deep copy: every `OwnedPtr` in the value is loaded from the source zone with
+//! `TryGet`, transferred in turn, and allocated in the destination zone with `Alloc`. Data is
+//! loaded one pointer at a time as the value is walked, so the source is never validated as a
+//! whole.
+//!
+//! Transfers are memoized by a `Transferrer`, keyed on the source pointer and the type it's
+//! transferred to. A subtree that's reachable from more than one pointer - as in a pile written
+//! with a `DedupDumper` - is only loaded and transferred once; every later pointer to it gets a
+//! `clone_ptr()` of the first transfer. In destinations with persistent pointers the clones share
+//! storage.
+//!
+//! Only clean pointers are memoized. A dirty pointer is a heap allocation, whose address can be
+//! reused once freed, so the same address needn't mean the same value; nor do dirty values get
+//! shared by deduplication in the first place.
+
+use std::collections::HashMap;
+
+use super::*;
+
+pub use hoard_derive::Transfer;
+
+/// Values that can be transferred from zone `Z` to zone `Y`.
+///
+/// Can be derived for structs and enums; see `hoard_derive::Transfer`.
+pub trait Transfer<Z: TryGet, Y: Alloc> : Sized {
+    /// The type of the value once in the destination zone.
+    type Transferred;
+
+    /// Transfers the value, with a fresh `Transferrer`.
+    fn transfer(&self, src: &Z, dst: &Y) -> Result<Self::Transferred, Z::Error> {
+        self.transfer_with(&mut Transferrer::new(src, dst))
+    }
+
+    /// Transfers the value, reusing the transfers already done by `transferrer`.
+    fn transfer_with(&self, transferrer: &mut Transferrer<Z, Y>) -> Result<Self::Transferred, Z::Error>;
+}
+
+/// Memoizes the clean pointers transferred from `Z` to `Y`.
+#[derive(Debug)]
+pub struct Transferrer<'a, Z: Zone, Y: Zone> {
+    src: &'a Z,
+    dst: &'a Y,
+    done: HashMap<(Z::Ptr, TypeId, TypeId), Transferred<Y>>,
+    shared: usize,
+}
+
+/// A clone of a transferred `OwnedPtr<T, Y>`, with its type erased.
+#[derive(Debug)]
+struct Transferred<Y: Zone> {
+    raw: Y::Ptr,
+    drop: unsafe fn(Y::Ptr),
+}
+
+impl<Y: Zone> Transferred<Y> {
+    fn new<T>(owned: OwnedPtr<T, Y>) -> Self {
+        unsafe fn drop_owned<T, Y: Zone>(raw: Y::Ptr) {
+            let fatptr = FatPtr::<T, Y> { raw, metadata: () };
+            drop(OwnedPtr::new_unchecked(ValidPtr::new_unchecked(fatptr)))
+        }
+
+        Self {
+            raw: owned.into_inner().into_inner().raw,
+            drop: drop_owned::<T, Y>,
+        }
+    }
+}
+
+impl<Y: Zone> Drop for Transferred<Y> {
+    fn drop(&mut self) {
+        // SAFETY: drop was instantiated with the type raw was an OwnedPtr to.
+        unsafe { (self.drop)(self.raw) }
+    }
+}
+
+impl<'a, Z: TryGet, Y: Alloc> Transferrer<'a, Z, Y> {
+    pub fn new(src: &'a Z, dst: &'a Y) -> Self {
+        Self {
+            src, dst,
+            done: HashMap::new(),
+            shared: 0,
+        }
+    }
+
+    pub fn src(&self) -> &'a Z {
+        self.src
+    }
+
+    pub fn dst(&self) -> &'a Y {
+        self.dst
+    }
+
+    /// Returns the number of pointers whose transfer was reused, rather than done again.
+    pub fn shared(&self) -> usize {
+        self.shared
+    }
+
+    /// Transfers the value behind a pointer, or clones the result of a previous transfer of the
+    /// same clean pointer.
+    ///
+    /// `T::Transferred` must be `'static`, as that's what the memoized transfer is looked up by.
+    pub fn transfer_ptr<T>(&mut self, ptr: &ValidPtr<T, Z>) -> Result<OwnedPtr<T::Transferred, Y>, Z::Error>
+        where T: Load<Z> + Transfer<Z, Y>,
+              T::Transferred: 'static + Clone,
+              Y: Clone,
+    {
+        // The source type is only part of the key to tell apart different types loaded from the
+        // same pointer; type_id_of() ignores lifetimes, so it can't be relied on for safety.
+        let key = (ptr.raw, TypeId::of::<T::Transferred>(), type_id_of::<T>());
+        let memoize = !ptr.is_dirty();
+        if let Some(done) = self.done.get(&key).filter(|_| memoize) {
+            // SAFETY: entries are keyed by the TypeId of the type they're an OwnedPtr to, and the
+            // entry is still owned by the map.
+            let done = unsafe {
+                ValidPtr::<T::Transferred, Y>::new_unchecked(FatPtr { raw: done.raw, metadata: () })
+            };
+            self.shared += 1;
+            return Ok(Y::clone_ptr(&done));
+        }
+
+        let src = self.src;
+        let value = src.try_get(ptr)?;
+        let value = value.this.transfer_with(self)?;
+        let owned = self.dst.alloc(value);
+
+        if memoize {
+            self.done.insert(key, Transferred::new(Y::clone_ptr(&owned)));
+        }
+        Ok(owned)
+    }
+}
+
+impl<Z: TryGet, Y: Alloc, T: Transfer<Z, Y>> Transfer<Z, Y> for Option<T> {
+    type Transferred = Option<T::Transferred>;
+
+    fn transfer_with(&self, transferrer: &mut Transferrer<Z, Y>) -> Result<Self::Transferred, Z::Error> {
+        match self {
+            None => Ok(None),
+            Some(value) => value.transfer_with(transferrer).map(Some),
+        }
+    }
+}
+
+macro_rules! impl_transfer_for_tuples {
+    ($( ( $($t:ident: $i:tt),+ ), )+) => {$(
+        impl<Z: TryGet, Y: Alloc, $($t: Transfer<Z, Y>),+> Transfer<Z, Y> for ($($t,)+) {
+            type Transferred = ($($t::Transferred,)+);
+
+            fn transfer_with(&self, transferrer: &mut Transferrer<Z, Y>) -> Result<Self::Transferred, Z::Error> {
+                Ok(($(self.$i.transfer_with(transferrer)?,)+))
+            }
+        }
+    )+}
+}
+
+impl_transfer_for_tuples! {
+    (A: 0),
+    (A: 0, B: 1),
+    (A: 0, B: 1, C: 2),
+    (A: 0, B: 1, C: 2, D: 3),
+    (A: 0, B: 1, C: 2, D: 3, E: 4),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7),
+}
+
+#[macro_export]
+macro_rules! impl_transfer_for_primitive {
+    ($t:ty) => {
+        impl<Z, Y> $crate::zone::transfer::Transfer<Z, Y> for $t
+        where Z: $crate::zone::TryGet,
+              Y: $crate::zone::Alloc,
+        {
+            type Transferred = $t;
+
+            #[inline(always)]
+            fn transfer_with(&self, _: &mut $crate::zone::transfer::Transferrer<Z, Y>) -> Result<$t, Z::Error> {
+                Ok(self.clone())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use leint::Le;
+
+    use crate::pile::{TryPile, TryPileMut};
+
+    #[test]
+    fn transfer_pile_to_heap() {
+        let pile = TryPileMut::default();
+        let x = pile.alloc([pile.alloc(1u8), pile.alloc(2u8)]);
+        let buf = pile.encode_dirty(&x);
+
+        TryPile::new(&buf, |src| {
+            let tip = src.try_get_tip::<OwnedPtr<[OwnedPtr<u8, TryPile>; 2], TryPile>>().unwrap();
+
+            let dst = TryPileMut::default();
+            let y = tip.transfer(&src, &dst).unwrap();
+
+            assert!(y.raw.get_ptr().is_some());
+            for ptr in dst.try_get(&y).unwrap().iter() {
+                assert!(ptr.raw.get_ptr().is_some());
+            }
+            assert_eq!(dst.encode_dirty(&y), buf);
+
+            // Transferring to Missing prunes the value.
+            let z = tip.transfer(&src, &Missing).unwrap();
+            assert!(Missing.try_get(&z).is_err());
+        });
+    }
+
+    #[test]
+    fn transfer_shared_subtrees() {
+        // Both pointers point to the same u8 at offset 0.
+        let buf = [42, 0, 0, 0, 0, 0, 0, 0,
+                    1, 0, 0, 0, 0, 0, 0, 0,
+                    1, 0, 0, 0, 0, 0, 0, 0];
+
+        TryPile::new(&buf[..], |src| {
+            let tip = src.try_get_tip::<[OwnedPtr<u8, TryPile>; 2]>().unwrap();
+
+            let dst = TryPileMut::default();
+            let mut transferrer = Transferrer::new(&src, &dst);
+            let y = tip.transfer_with(&mut transferrer).unwrap();
+            assert_eq!(transferrer.shared(), 1);
+
+            assert_eq!(**dst.try_get(&y[0]).unwrap(), 42);
+            assert_eq!(**dst.try_get(&y[1]).unwrap(), 42);
+
+            // Later transfers with the same transferrer keep sharing.
+            let z = (Some(OwnedPtr::clone(&tip[0])), (), Le::new(1u16))
+                        .transfer_with(&mut transferrer).unwrap();
+            assert_eq!(transferrer.shared(), 2);
+            assert_eq!(**dst.try_get(z.0.as_ref().unwrap()).unwrap(), 42);
+        });
+    }
+
+    #[test]
+    fn transfer_dirty_not_memoized() {
+        let src = TryPileMut::default();
+        let dst = TryPileMut::default();
+        let mut transferrer = Transferrer::new(&src, &dst);
+
+        let x = src.alloc(1u8);
+        let y1 = x.transfer_with(&mut transferrer).unwrap();
+        let y2 = x.transfer_with(&mut transferrer).unwrap();
+        assert_eq!(transferrer.shared(), 0);
+        assert_eq!(**dst.try_get(&y1).unwrap(), 1);
+        assert_eq!(**dst.try_get(&y2).unwrap(), 1);
+    }
+
+    #[test]
+    fn transfer_invalid_ptr() {
+        // Offset 16 is past the end of the pile.
+        TryPile::new(&[33, 0, 0, 0, 0, 0, 0, 0], |src| {
+            let tip = src.try_get_tip::<OwnedPtr<u8, TryPile>>().unwrap();
+            assert!(tip.transfer(&src, &TryPileMut::default()).is_err());
+        });
+    }
+}
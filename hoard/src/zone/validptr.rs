@@ -56,6 +56,22 @@ impl<T: ?Sized + Pointee, Z: Zone> ValidPtr<T, Z> {
     pub fn into_inner(self) -> FatPtr<T,Z> {
         self.0
     }
+
+    /// Returns `true` if the value is dirty: in memory, rather than in the zone's persistent
+    /// storage.
+    pub fn is_dirty(&self) -> bool {
+        Z::try_get_dirty(self).is_ok()
+    }
+
+    /// Gets mutable access to the value, copying it into memory first if it isn't already dirty.
+    ///
+    /// The pointer is dirty afterwards, so the persistent copy is never modified.
+    pub fn make_mut<'a>(&'a mut self, zone: &Z) -> Result<RefMut<'a, T, Z>, Z::Error>
+        where Z: TryGetMut,
+              T: Load<Z>,
+    {
+        zone.try_get_mut(self)
+    }
 }
 
 // standard impls
@@ -209,7 +225,54 @@ where T: Save<'a, Y>,
 mod tests {
     use super::*;
 
+    use leint::Le;
+
+    use crate::coerce::Coerce;
+    use crate::pile::{TryPile, TryPileMut, PileZone};
+    use crate::pile::compact::{TryPile32, TryPileMut32, offset32::Offset32};
+
+    fn increment<Z: TryGetMut>(zone: &Z, ptr: &mut ValidPtr<Le<u32>, Z>) {
+        let r = ptr.make_mut(zone).ok().unwrap();
+        *r.this = Le::new(r.this.get() + 1);
+    }
+
     #[test]
     fn test() {
     }
+
+    #[test]
+    fn make_mut() {
+        TryPile::new(&[42, 0, 0, 0], |pile| {
+            let pile = TryPileMut::from(pile);
+            let ptr = TryPile::new_valid_ptr::<Le<u32>>(0, ());
+            let mut ptr: OwnedPtr<Le<u32>, TryPileMut> = unsafe {
+                OwnedPtr::new_unchecked(ValidPtr::new_unchecked((*ptr).coerce()))
+            };
+            assert!(!ptr.is_dirty());
+
+            increment(&pile, &mut ptr);
+            assert!(ptr.is_dirty());
+            increment(&pile, &mut ptr);
+            assert_eq!(**pile.try_get(&ptr).unwrap(), 44);
+
+            // The persistent copy is unchanged.
+            let ptr = TryPile::new_valid_ptr::<Le<u32>>(0, ());
+            assert_eq!(**TryGet::try_get(&pile.get_try_pile(), &ptr).unwrap(), 42);
+        });
+
+        TryPile32::new(&[42, 0, 0, 0], |pile| {
+            crate::dirty_table!(Table);
+
+            let pile = TryPileMut32::<Table>::from(pile);
+            let ptr = FatPtr::<Le<u32>, TryPile32> { raw: Offset32::new(0).unwrap(), metadata: () };
+            let mut ptr: OwnedPtr<Le<u32>, TryPileMut32<Table>> = unsafe {
+                OwnedPtr::new_unchecked(ValidPtr::new_unchecked(ptr.coerce()))
+            };
+            assert!(!ptr.is_dirty());
+
+            increment(&pile, &mut ptr);
+            assert!(ptr.is_dirty());
+            assert_eq!(**pile.try_get(&ptr).unwrap(), 43);
+        });
+    }
 }
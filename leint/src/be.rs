@@ -0,0 +1,263 @@
+//! Big-endian integers.
+
+use super::*;
+
+/// A big-endian integer.
+///
+/// The actual memory representation of a `Be<T>` will be big-endian regardless of platform
+/// endianness. For unsigned integers this means that comparing the raw bytes lexicographically
+/// gives the same order as comparing the numbers, which makes `Be<T>` suitable for sortable keys.
+/// Signed integers are two's complement, so negative numbers sort after positive ones bytewise;
+/// `Ord` on `Be<T>` itself is always numeric.
+#[repr(packed)]
+pub struct Be<T: sealed::ToFromBe>(T);
+
+mod sealed {
+    use super::*;
+
+    pub trait ToFromBe
+        : 'static + Copy + Eq + Ord + fmt::Display + fmt::Debug
+    {
+        fn to_be(this: Self) -> Self;
+        fn from_be(be_this: Self) -> Self;
+    }
+}
+use self::sealed::ToFromBe;
+
+// Byte order doesn't change whether or not all bytes are zero.
+unsafe impl<T: NonZero + ToFromBe> NonZero for Be<T> {}
+
+impl<T: ToFromBe> Be<T> {
+    #[inline(always)]
+    pub fn new(n: T) -> Self {
+        Be(T::to_be(n))
+    }
+
+    #[inline(always)]
+    pub fn get(self) -> T {
+        T::from_be(self.0)
+    }
+}
+
+impl<T: ToFromBe> From<T> for Be<T> {
+    #[inline(always)]
+    fn from(n: T) -> Self {
+        Be::new(n)
+    }
+}
+
+impl<T: ToFromBe + ToFromLe> From<Le<T>> for Be<T> {
+    #[inline(always)]
+    fn from(le: Le<T>) -> Self {
+        Be::new(le.get())
+    }
+}
+
+impl<T: ToFromBe + ToFromLe> From<Be<T>> for Le<T> {
+    #[inline(always)]
+    fn from(be: Be<T>) -> Self {
+        Le::new(be.get())
+    }
+}
+
+impl<T: ToFromBe> fmt::Debug for Be<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Be({:?})", self.get())
+    }
+}
+impl<T: ToFromBe> fmt::Display for Be<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.get(), f)
+    }
+}
+
+impl<T: ToFromBe> Clone for Be<T> {
+    #[inline(always)]
+    fn clone(&self) -> Self {
+        Be(self.0)
+    }
+}
+impl<T: ToFromBe> Copy for Be<T> {}
+
+impl<T: ToFromBe + Default> Default for Be<T> {
+    #[inline(always)]
+    fn default() -> Self {
+        Be::from(T::default())
+    }
+}
+
+impl<T: ToFromBe> Hash for Be<T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Self::hash_slice(slice::from_ref(self), state)
+    }
+    #[inline]
+    fn hash_slice<H: Hasher>(data: &[Self], state: &mut H) {
+        unsafe {
+            let buf: &[u8] = slice::from_raw_parts(data.as_ptr() as *const u8,
+                                                   data.len() * mem::size_of::<Self>());
+            state.write(buf)
+        }
+    }
+}
+
+macro_rules! impl_ints {
+    ( $( $t:ident, )+ ) => {
+        $(
+            impl_tofrombe!($t, $t);
+        )+
+    };
+}
+
+macro_rules! impl_nonzero_ints {
+    ( $( $t:ident => $inner:ident; )+ ) => {
+        $(
+            impl_tofrombe!($t, $inner);
+        )+
+    };
+}
+
+macro_rules! impl_tofrombe {
+    ($t:ident, $inner:ident) => {
+        impl ToFromBe for $t {
+            #[inline(always)]
+            fn to_be(this: Self) -> Self {
+                unsafe {
+                    let this: $inner = mem::transmute(this);
+                    mem::transmute(this.to_be())
+                }
+            }
+            #[inline(always)]
+            fn from_be(be_this: Self) -> Self {
+                unsafe {
+                    let be_this: $inner = mem::transmute(be_this);
+                    let this = $inner::from_be(mem::transmute(be_this));
+                    mem::transmute(this)
+                }
+            }
+        }
+
+        impl From<Be<$t>> for $t {
+            #[inline(always)]
+            fn from(be: Be<$t>) -> Self {
+                be.get()
+            }
+        }
+
+        impl cmp::PartialEq for Be<$t> {
+            #[inline(always)]
+            fn eq(&self, other: &Self) -> bool {
+                cmp::PartialEq::eq(&self.get(), &other.get())
+            }
+        }
+        impl cmp::PartialEq<$t> for Be<$t> {
+            #[inline(always)]
+            fn eq(&self, other: &$t) -> bool {
+                cmp::PartialEq::eq(&self.get(), other)
+            }
+        }
+        impl cmp::PartialEq<Be<$t>> for $t {
+            #[inline(always)]
+            fn eq(&self, other: &Be<$t>) -> bool {
+                cmp::PartialEq::eq(self, &other.get())
+            }
+        }
+        impl cmp::Eq for Be<$t> {}
+
+        impl cmp::PartialOrd for Be<$t> {
+            #[inline(always)]
+            fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+                cmp::PartialOrd::partial_cmp(&self.get(), &other.get())
+            }
+        }
+        impl cmp::PartialOrd<$t> for Be<$t> {
+            #[inline(always)]
+            fn partial_cmp(&self, other: &$t) -> Option<cmp::Ordering> {
+                cmp::PartialOrd::partial_cmp(&self.get(), other)
+            }
+        }
+        impl cmp::PartialOrd<Be<$t>> for $t {
+            #[inline(always)]
+            fn partial_cmp(&self, other: &Be<$t>) -> Option<cmp::Ordering> {
+                cmp::PartialOrd::partial_cmp(self, &(other.get()))
+            }
+        }
+        impl cmp::Ord for Be<$t> {
+            #[inline(always)]
+            fn cmp(&self, other: &Self) -> cmp::Ordering {
+                cmp::Ord::cmp(&self.get(), &other.get())
+            }
+        }
+    }
+}
+
+impl_ints!(
+    u16, i16,
+    u32, i32,
+    u64, i64,
+    u128, i128,
+);
+
+impl_nonzero_ints!(
+    NonZeroU16 =>   u16; NonZeroI16  =>  i16;
+    NonZeroU32 =>   u32; NonZeroI32  =>  i32;
+    NonZeroU64 =>   u64; NonZeroI64  =>  i64;
+    NonZeroU128 => u128; NonZeroI128 =>  i128;
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes<T: ToFromBe>(n: &Be<T>) -> &[u8] {
+        unsafe { slice::from_raw_parts(n as *const _ as *const u8, mem::size_of::<Be<T>>()) }
+    }
+
+    #[test]
+    fn alignment() {
+        assert_eq!(mem::align_of::<Be<u16>>(),  1);
+        assert_eq!(mem::align_of::<Be<u32>>(),  1);
+        assert_eq!(mem::align_of::<Be<u64>>(),  1);
+        assert_eq!(mem::align_of::<Be<u128>>(), 1);
+
+        assert_eq!(mem::align_of::<Be<i16>>(),  1);
+        assert_eq!(mem::align_of::<Be<i32>>(),  1);
+        assert_eq!(mem::align_of::<Be<i64>>(),  1);
+        assert_eq!(mem::align_of::<Be<i128>>(), 1);
+
+        assert_eq!(mem::align_of::<Be<NonZeroU64>>(), 1);
+    }
+
+    #[test]
+    fn representation() {
+        assert_eq!(bytes(&Be::new(0x0102_0304u32)), &[1, 2, 3, 4]);
+        assert_eq!(bytes(&Be::new(-2i16)), &[0xff, 0xfe]);
+        assert_eq!(Be::new(0x0102_0304u32).get(), 0x0102_0304);
+    }
+
+    #[test]
+    fn le_conversions() {
+        let le = Le::new(0x1234u16);
+        let be = Be::from(le);
+        assert_eq!(be, 0x1234);
+        assert_eq!(bytes(&be), &[0x12, 0x34]);
+        assert_eq!(Le::from(be), le);
+
+        let nz = Be::new(NonZeroU32::new(7).unwrap());
+        assert_eq!(Le::from(nz).get().get(), 7);
+    }
+
+    #[test]
+    fn ordering() {
+        let ns = [0u32, 1, 0xff, 0x100, 0xffff, 0x1_0000, u32::max_value()];
+        for a in ns.iter() {
+            for b in ns.iter() {
+                let (a_be, b_be) = (Be::new(*a), Be::new(*b));
+                assert_eq!(a_be.cmp(&b_be), a.cmp(b));
+                assert_eq!(bytes(&a_be).cmp(bytes(&b_be)), a.cmp(b));
+            }
+        }
+
+        assert!(Be::new(-1i32) < Be::new(1i32));
+    }
+}
@@ -12,6 +12,13 @@ use core::num::{
 };
 use core::slice;
 
+use nonzero::NonZero;
+
+mod be;
+pub use self::be::Be;
+
+mod ops;
+
 /// A little-endian integer.
 ///
 /// The actual memory representation of a `Le<T>` will be little-endian regardless of platform
@@ -23,7 +30,7 @@ mod sealed {
     use super::*;
 
     pub trait ToFromLe
-        : 'static + Copy + Eq + Ord + fmt::Display + fmt::Debug
+        : 'static + Copy + PartialEq + PartialOrd + fmt::Display + fmt::Debug
     {
         fn to_le(this: Self) -> Self;
         fn from_le(le_this: Self) -> Self;
@@ -99,9 +106,8 @@ macro_rules! impl_ints {
     };
 }
 
-/*
+// Byte order doesn't change whether or not all bytes are zero.
 unsafe impl<T: NonZero + ToFromLe> NonZero for Le<T> {}
-*/
 
 macro_rules! impl_nonzero_ints {
     ( $( $t:ident => $inner:ident; )+ ) => {
@@ -199,6 +205,95 @@ impl_nonzero_ints!(
     NonZeroU128 => u128; NonZeroI128 =>  i128;
 );
 
+macro_rules! impl_floats {
+    ( $( $t:ident => $bits:ident, $canonical_nan:expr; )+ ) => {
+        $(
+            impl ToFromLe for $t {
+                #[inline(always)]
+                fn to_le(this: Self) -> Self {
+                    $t::from_bits(this.to_bits().to_le())
+                }
+                #[inline(always)]
+                fn from_le(le_this: Self) -> Self {
+                    $t::from_bits($bits::from_le(le_this.to_bits()))
+                }
+            }
+
+            impl Le<$t> {
+                /// Creates a new `Le`, replacing any NaN with the canonical NaN.
+                ///
+                /// NaNs can have many different bit patterns, so without canonicalization equal
+                /// values can have different encodings.
+                #[inline]
+                pub fn new_canonical(n: $t) -> Self {
+                    if n.is_nan() {
+                        Le::new($t::from_bits($canonical_nan))
+                    } else {
+                        Le::new(n)
+                    }
+                }
+
+                /// Returns true unless the value is a non-canonical NaN.
+                #[inline]
+                pub fn is_canonical(self) -> bool {
+                    let n = self.get();
+                    !n.is_nan() || n.to_bits() == $canonical_nan
+                }
+            }
+
+            impl From<Le<$t>> for $t {
+                #[inline(always)]
+                fn from(le: Le<$t>) -> Self {
+                    le.get()
+                }
+            }
+
+            impl cmp::PartialEq for Le<$t> {
+                #[inline(always)]
+                fn eq(&self, other: &Self) -> bool {
+                    cmp::PartialEq::eq(&self.get(), &other.get())
+                }
+            }
+            impl cmp::PartialEq<$t> for Le<$t> {
+                #[inline(always)]
+                fn eq(&self, other: &$t) -> bool {
+                    cmp::PartialEq::eq(&self.get(), other)
+                }
+            }
+            impl cmp::PartialEq<Le<$t>> for $t {
+                #[inline(always)]
+                fn eq(&self, other: &Le<$t>) -> bool {
+                    cmp::PartialEq::eq(self, &other.get())
+                }
+            }
+
+            impl cmp::PartialOrd for Le<$t> {
+                #[inline(always)]
+                fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+                    cmp::PartialOrd::partial_cmp(&self.get(), &other.get())
+                }
+            }
+            impl cmp::PartialOrd<$t> for Le<$t> {
+                #[inline(always)]
+                fn partial_cmp(&self, other: &$t) -> Option<cmp::Ordering> {
+                    cmp::PartialOrd::partial_cmp(&self.get(), other)
+                }
+            }
+            impl cmp::PartialOrd<Le<$t>> for $t {
+                #[inline(always)]
+                fn partial_cmp(&self, other: &Le<$t>) -> Option<cmp::Ordering> {
+                    cmp::PartialOrd::partial_cmp(self, &(other.get()))
+                }
+            }
+        )+
+    };
+}
+
+impl_floats!(
+    f32 => u32, 0x7fc0_0000;
+    f64 => u64, 0x7ff8_0000_0000_0000;
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -214,5 +309,32 @@ mod tests {
         assert_eq!(mem::align_of::<Le<i32>>(),  1);
         assert_eq!(mem::align_of::<Le<i64>>(),  1);
         assert_eq!(mem::align_of::<Le<i128>>(), 1);
+
+        assert_eq!(mem::align_of::<Le<f32>>(),  1);
+        assert_eq!(mem::align_of::<Le<f64>>(),  1);
+    }
+
+    #[test]
+    fn nonzero_niche() {
+        assert_eq!(mem::size_of::<Option<Le<NonZeroU16>>>(),  2);
+        assert_eq!(mem::size_of::<Option<Le<NonZeroU64>>>(),  8);
+        assert_eq!(mem::size_of::<Option<Le<NonZeroI128>>>(), 16);
+        assert_eq!(mem::size_of::<Option<Be<NonZeroU32>>>(),  4);
+    }
+
+    #[test]
+    fn floats() {
+        let n = Le::new(1.5f64);
+        assert_eq!(n, 1.5);
+        assert!(n < 2.0);
+        assert_eq!(n.get().to_bits().to_le_bytes(),
+                   unsafe { mem::transmute::<Le<f64>, [u8; 8]>(n) });
+
+        let nan = f32::from_bits(0x7fc0_0001);
+        assert!(!Le::new(nan).is_canonical());
+        assert!(Le::<f32>::new_canonical(nan).is_canonical());
+        assert!(Le::<f32>::new_canonical(nan).get().is_nan());
+        assert_eq!(Le::<f32>::new_canonical(1.0f32), 1.0);
+        assert!(Le::new(-0.0f32).is_canonical());
     }
 }
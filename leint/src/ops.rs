@@ -0,0 +1,258 @@
+//! Arithmetic and bit operations on `Le<T>`.
+//!
+//! Operators work between two `Le<T>`'s, or an `Le<T>` and a native `T`, and behave exactly like
+//! the native operators, including overflow panics in debug builds.
+
+use core::iter;
+use core::num::ParseIntError;
+use core::ops;
+use core::str::FromStr;
+
+use super::*;
+
+macro_rules! impl_binop {
+    ($t:ident, $trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident) => {
+        impl ops::$trait for Le<$t> {
+            type Output = Self;
+
+            #[inline(always)]
+            fn $method(self, rhs: Self) -> Self {
+                Le::new(ops::$trait::$method(self.get(), rhs.get()))
+            }
+        }
+        impl ops::$trait<$t> for Le<$t> {
+            type Output = Self;
+
+            #[inline(always)]
+            fn $method(self, rhs: $t) -> Self {
+                Le::new(ops::$trait::$method(self.get(), rhs))
+            }
+        }
+        impl ops::$assign_trait for Le<$t> {
+            #[inline(always)]
+            fn $assign_method(&mut self, rhs: Self) {
+                *self = ops::$trait::$method(*self, rhs)
+            }
+        }
+        impl ops::$assign_trait<$t> for Le<$t> {
+            #[inline(always)]
+            fn $assign_method(&mut self, rhs: $t) {
+                *self = ops::$trait::$method(*self, rhs)
+            }
+        }
+    }
+}
+
+macro_rules! impl_methods {
+    ($t:ident, checked: [$( $checked:ident ),*], other: [$( $method:ident ),*]) => {
+        impl Le<$t> {
+            $(
+                #[inline(always)]
+                pub fn $checked(self, rhs: Self) -> Option<Self> {
+                    self.get().$checked(rhs.get()).map(Le::new)
+                }
+            )+
+            $(
+                #[inline(always)]
+                pub fn $method(self, rhs: Self) -> Self {
+                    Le::new(self.get().$method(rhs.get()))
+                }
+            )+
+        }
+    }
+}
+
+macro_rules! impl_ops {
+    ( $( $t:ident, )+ ) => {$(
+        impl_binop!($t, Add, add, AddAssign, add_assign);
+        impl_binop!($t, Sub, sub, SubAssign, sub_assign);
+        impl_binop!($t, Mul, mul, MulAssign, mul_assign);
+        impl_binop!($t, Div, div, DivAssign, div_assign);
+        impl_binop!($t, Rem, rem, RemAssign, rem_assign);
+        impl_binop!($t, BitAnd, bitand, BitAndAssign, bitand_assign);
+        impl_binop!($t, BitOr, bitor, BitOrAssign, bitor_assign);
+        impl_binop!($t, BitXor, bitxor, BitXorAssign, bitxor_assign);
+
+        impl ops::Shl<u32> for Le<$t> {
+            type Output = Self;
+
+            #[inline(always)]
+            fn shl(self, rhs: u32) -> Self {
+                Le::new(self.get() << rhs)
+            }
+        }
+        impl ops::ShlAssign<u32> for Le<$t> {
+            #[inline(always)]
+            fn shl_assign(&mut self, rhs: u32) {
+                *self = *self << rhs
+            }
+        }
+        impl ops::Shr<u32> for Le<$t> {
+            type Output = Self;
+
+            #[inline(always)]
+            fn shr(self, rhs: u32) -> Self {
+                Le::new(self.get() >> rhs)
+            }
+        }
+        impl ops::ShrAssign<u32> for Le<$t> {
+            #[inline(always)]
+            fn shr_assign(&mut self, rhs: u32) {
+                *self = *self >> rhs
+            }
+        }
+
+        impl ops::Not for Le<$t> {
+            type Output = Self;
+
+            #[inline(always)]
+            fn not(self) -> Self {
+                Le::new(!self.get())
+            }
+        }
+
+        impl_methods!($t,
+            checked: [checked_add, checked_sub, checked_mul, checked_div, checked_rem],
+            other: [saturating_add, saturating_sub, saturating_mul,
+                    wrapping_add, wrapping_sub, wrapping_mul]
+        );
+
+        impl iter::Sum for Le<$t> {
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+                Le::new(iter.map(Le::get).sum())
+            }
+        }
+        impl<'a> iter::Sum<&'a Le<$t>> for Le<$t> {
+            fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+                Le::new(iter.map(|n| n.get()).sum())
+            }
+        }
+        impl iter::Product for Le<$t> {
+            fn product<I: Iterator<Item = Self>>(iter: I) -> Self {
+                Le::new(iter.map(Le::get).product())
+            }
+        }
+        impl<'a> iter::Product<&'a Le<$t>> for Le<$t> {
+            fn product<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+                Le::new(iter.map(|n| n.get()).product())
+            }
+        }
+
+        impl FromStr for Le<$t> {
+            type Err = ParseIntError;
+
+            fn from_str(s: &str) -> Result<Self, ParseIntError> {
+                $t::from_str(s).map(Le::new)
+            }
+        }
+
+        impl fmt::LowerHex for Le<$t> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::LowerHex::fmt(&self.get(), f)
+            }
+        }
+        impl fmt::UpperHex for Le<$t> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                fmt::UpperHex::fmt(&self.get(), f)
+            }
+        }
+    )+}
+}
+
+impl_ops!(
+    u16, i16,
+    u32, i32,
+    u64, i64,
+    u128, i128,
+);
+
+macro_rules! impl_neg {
+    ( $( $t:ident, )+ ) => {$(
+        impl ops::Neg for Le<$t> {
+            type Output = Self;
+
+            #[inline(always)]
+            fn neg(self) -> Self {
+                Le::new(-self.get())
+            }
+        }
+    )+}
+}
+
+impl_neg!(i16, i32, i64, i128,);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic() {
+        let a = Le::new(10u64);
+        let b = Le::new(3u64);
+
+        assert_eq!(a + b, 13);
+        assert_eq!(a - b, 7);
+        assert_eq!(a * b, 30);
+        assert_eq!(a / b, 3);
+        assert_eq!(a % b, 1);
+        assert_eq!(a + 1, 11);
+
+        let mut c = a;
+        c += b;
+        c -= 1;
+        c *= 2;
+        assert_eq!(c, 24);
+
+        assert_eq!(-Le::new(5i32), -5);
+    }
+
+    #[test]
+    fn bits() {
+        let a = Le::new(0b1100u16);
+        assert_eq!(a & 0b1010, 0b1000);
+        assert_eq!(a | Le::new(0b0011), 0b1111);
+        assert_eq!(a ^ 0b0110, 0b1010);
+        assert_eq!(a << 2, 0b110000);
+        assert_eq!(a >> 2, 0b11);
+        assert_eq!(!Le::new(0u16), u16::max_value());
+
+        let mut b = a;
+        b >>= 3;
+        b |= 0b10;
+        assert_eq!(b, 0b11);
+    }
+
+    #[test]
+    fn checked() {
+        let max = Le::new(u32::max_value());
+        let one = Le::new(1u32);
+
+        assert_eq!(max.checked_add(one), None);
+        assert_eq!(one.checked_add(one), Some(Le::new(2)));
+        assert_eq!(one.checked_div(Le::new(0)), None);
+        assert_eq!(max.saturating_add(one), max);
+        assert_eq!(Le::new(0u32).saturating_sub(one), 0);
+        assert_eq!(max.wrapping_add(one), 0);
+    }
+
+    #[test]
+    fn iterators() {
+        let amounts = [Le::new(1u64), Le::new(2), Le::new(3)];
+        assert_eq!(amounts.iter().sum::<Le<u64>>(), 6);
+        assert_eq!(amounts.iter().copied().product::<Le<u64>>(), 6);
+    }
+
+    #[test]
+    fn parse_and_format() {
+        assert_eq!("1234".parse::<Le<u32>>().unwrap(), 1234);
+        assert!("-1".parse::<Le<u32>>().is_err());
+        assert_eq!(format!("{:x}", Le::new(255u16)), "ff");
+        assert_eq!(format!("{:#X}", Le::new(255u16)), "0xFF");
+    }
+
+    #[test]
+    fn ordering_with_native() {
+        assert!(Le::new(1u64) < 2);
+        assert!(2 > Le::new(1u64));
+    }
+}
@@ -0,0 +1,75 @@
+//! `#[derive(NonZero)]`
+//!
+//! Implements `NonZero` for structs with a `NonZero` field: the first field, or the field marked
+//! `#[nonzero]`. The derive checks at compile time that the field is `NonZero`, and that
+//! `Option<Self>` is the same size as `Self`.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use synstructure::decl_derive;
+
+decl_derive!([NonZero, attributes(nonzero)] => derive_nonzero);
+
+fn derive_nonzero(s: synstructure::Structure) -> TokenStream {
+    match try_derive_nonzero(s) {
+        Ok(tokens) => tokens,
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+fn try_derive_nonzero(s: synstructure::Structure) -> syn::Result<TokenStream> {
+    let ast = s.ast();
+    let name = &ast.ident;
+
+    let fields = match &ast.data {
+        syn::Data::Struct(data) => &data.fields,
+        _ => return Err(syn::Error::new(name.span(), "NonZero can only be derived for structs")),
+    };
+
+    let marked: Vec<&syn::Field> = fields.iter()
+        .filter(|field| field.attrs.iter().any(|attr| attr.path.is_ident("nonzero")))
+        .collect();
+    if let Some(extra) = marked.get(1) {
+        return Err(syn::Error::new(extra.span(), "only one field can be #[nonzero]"));
+    }
+    let field = match marked.first() {
+        Some(field) => *field,
+        None => fields.iter().next()
+                      .ok_or_else(|| syn::Error::new(name.span(), "NonZero requires at least one field"))?,
+    };
+    let field_ty = &field.ty;
+
+    if let Some(param) = ast.generics.type_params().next() {
+        return Err(syn::Error::new(param.span(), "NonZero can't be derived for generic types"));
+    }
+    if let Some(param) = ast.generics.const_params().next() {
+        return Err(syn::Error::new(param.span(), "NonZero can't be derived for generic types"));
+    }
+
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    // The size check needs a concrete type, so any lifetimes are replaced with 'static.
+    let static_lifetimes = ast.generics.lifetimes().map(|_| quote!('static));
+    let static_ty = if ast.generics.params.is_empty() {
+        quote!(#name)
+    } else {
+        quote!(#name<#(#static_lifetimes),*>)
+    };
+
+    Ok(quote! {
+        const _: () = {
+            fn assert_nonzero<T: ?Sized + ::nonzero::NonZero>() {}
+
+            #[allow(dead_code)]
+            fn assert_field_nonzero #impl_generics () #where_clause {
+                assert_nonzero::<#field_ty>();
+            }
+
+            let _: [(); ::core::mem::size_of::<#static_ty>()]
+                = [(); ::core::mem::size_of::<::core::option::Option<#static_ty>>()];
+        };
+
+        unsafe impl #impl_generics ::nonzero::NonZero for #name #ty_generics #where_clause {}
+    })
+}
@@ -7,6 +7,12 @@ use core::ptr::NonNull;
 
 use static_assertions::assert_eq_size;
 
+pub use nonzero_derive::NonZero;
+
+// Lets the derive's ::nonzero paths work in our own tests.
+#[cfg(test)]
+extern crate self as nonzero;
+
 /// Asserts that the bit representation of a value of this type is never all zeros, and that an
 /// `Option<Self>` is the same size as `Self`.
 ///
@@ -57,3 +63,32 @@ array_impls! {
     20 21 22 23 24 25 26 27 28 29
     30 31 32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::marker::PhantomData;
+    use core::mem::size_of;
+
+    #[derive(NonZero)]
+    struct Id(num::NonZeroU32);
+
+    #[derive(NonZero)]
+    #[repr(C)]
+    struct Tagged<'a> {
+        marker: PhantomData<&'a ()>,
+        #[nonzero]
+        ptr: NonNull<u8>,
+        len: usize,
+    }
+
+    fn assert_nonzero<T: ?Sized + NonZero>() {}
+
+    #[test]
+    fn derive() {
+        assert_nonzero::<Id>();
+        assert_nonzero::<Tagged>();
+        assert_eq!(size_of::<Option<Tagged>>(), size_of::<Tagged>());
+    }
+}
@@ -4,7 +4,7 @@ use std::num::NonZeroU8;
 use thiserror::Error;
 
 use hoard::marshal::{Primitive, blob::*};
-use hoard::pointee::{Metadata, MetadataKind};
+use hoard::pointee::{Metadata, MetadataKind, dst::SliceMetadata};
 use proofmarshal_derive::{Commit, Prune};
 
 /// The height of a perfect binary tree.
@@ -118,7 +118,19 @@ impl Primitive for NonZeroHeight {}
 impl Metadata for NonZeroHeight {
     #[inline]
     fn kind(&self) -> MetadataKind {
-        MetadataKind::Len(self.0.get() as u64)
+        MetadataKind::Height(self.0.get().into())
+    }
+}
+
+impl SliceMetadata for NonZeroHeight {
+    #[inline]
+    fn from_len(len: usize) -> Option<Self> {
+        Self::try_from(len).ok()
+    }
+
+    #[inline]
+    fn to_len(self) -> usize {
+        self.into()
     }
 }
 
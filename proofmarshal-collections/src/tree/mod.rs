@@ -405,77 +405,12 @@ impl<T, S, Z: Zone, E> BorrowMut<SumTree<T, S, Z, E, [()]>> for SumTree<T, S, Z,
     }
 }
 
-unsafe impl<T, S, Z: Zone, E> IntoOwned for Inner<T, S, Z, E, [()]> {
-    type Owned = Inner<T, S, Z, E, NonZeroHeight>;
-
-    #[inline(always)]
-    unsafe fn into_owned_unchecked(this: &mut ManuallyDrop<Self>) -> Self::Owned {
-        todo!()
-    }
-}
-
-impl<T, S, Z: Zone, E> Borrow<Inner<T, S, Z, E, [()]>> for Inner<T, S, Z, E, NonZeroHeight> {
-    #[inline(always)]
-    fn borrow(&self) -> &Inner<T, S, Z, E, [()]> {
-        unsafe {
-            mem::transmute(slice::from_raw_parts(self, self.height.into()))
-        }
-    }
-}
-
-impl<T, S, Z: Zone, E> BorrowMut<Inner<T, S, Z, E, [()]>> for Inner<T, S, Z, E, NonZeroHeight> {
-    #[inline(always)]
-    fn borrow_mut(&mut self) -> &mut Inner<T, S, Z, E, [()]> {
-        unsafe {
-            mem::transmute(slice::from_raw_parts_mut(self, self.height.into()))
-        }
-    }
-}
-
-unsafe impl<T, S, Z: Zone, E> Take<Inner<T, S, Z, E, [()]>> for Inner<T, S, Z, E, NonZeroHeight> {
-    #[inline(always)]
-    fn take_unsized<F, R>(self, f: F) -> R
-        where F: FnOnce(&mut ManuallyDrop<Inner<T, S, Z, E, [()]>>) -> R
-    {
-        let mut this = ManuallyDrop::new(self);
-        let this: &mut Inner<T, S, Z, E, [()]> = (&mut *this).borrow_mut();
-        let this: &mut ManuallyDrop<_> = unsafe { &mut *(this as *mut _ as *mut _)};
-        f(this)
-    }
-}
-
-unsafe impl<T, S, Z: Zone, E> Pointee for Inner<T, S, Z, E, [()]> {
-    type Metadata = NonZeroHeight;
-    type LayoutError = !;
-
-    #[inline(always)]
-    fn try_layout(_: NonZeroHeight) -> Result<Layout, !> {
-        Ok(Layout::new::<Inner<T,S,Z,E,()>>())
-    }
-
-    #[inline(always)]
-    fn metadata_from_dropped(dropped: &MaybeDropped<Self>) -> NonZeroHeight {
-        let height = unsafe { dropped.get_unchecked().height.len() };
-        match NonZeroHeight::try_from(height) {
-            Ok(height) => height,
-            Err(err) => {
-                unreachable!("{:?}", err)
-            }
-        }
-    }
-
-    #[inline(always)]
-    fn make_fat_ptr(thin: *const (), height: NonZeroHeight) -> *const Self {
-        unsafe {
-            mem::transmute(slice::from_raw_parts(thin, height.into()))
-        }
-    }
-
-    #[inline(always)]
-    fn make_fat_ptr_mut(thin: *mut (), height: NonZeroHeight) -> *mut Self {
-        unsafe {
-            mem::transmute(slice::from_raw_parts_mut(thin, height.into()))
-        }
+hoard::impl_pointee_for_dst! {
+    unsafe impl[T, S, Z: Zone, E] Pointee for Inner<T, S, Z, E, [()]> {
+        type Metadata = NonZeroHeight;
+        type Header = Inner<T, S, Z, E, ()>;
+        type Owned = Inner<T, S, Z, E, NonZeroHeight>;
+        tail = height: [()];
     }
 }
 
@@ -592,4 +527,21 @@ mod tests {
         dbg!(tip.tip_digest());
         dbg!(&tip);
     }
+
+    #[test]
+    fn inner_error_reports_height() {
+        use hoard::pile::{TryPile, offset::Offset, error::{Error, ErrorKind}};
+
+        type Node<'p, 'v> = Inner<Digest<u8>, (), TryPile<'p, 'v>, (), [()]>;
+
+        TryPile::new(&[0; 8][..], |pile| {
+            let height = NonZeroHeight::try_from(17usize).unwrap();
+            let ptr = FatPtr::<Node, TryPile> { raw: Offset::new(100).unwrap(), metadata: height };
+
+            assert_eq!(format!("{:?}", ptr), "FatPtr { raw: 100, metadata: height 17 }");
+
+            let err = Error::new(&pile, &ptr, ErrorKind::Offset);
+            assert!(err.to_string().contains(" at offset 100 with height 17 ("));
+        })
+    }
 }